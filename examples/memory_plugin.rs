@@ -0,0 +1,37 @@
+//! A plugin with no devices and no arguments, built as a library for the tests loading plugins from memory.
+//! They tell copies of it apart by patching `PLUGIN_NAME` in the built bytes.
+use clap::ArgMatches;
+use karlsen_miner::{declare_plugin, Error, Plugin, WorkerSpec};
+
+const PLUGIN_NAME: &str = "memory plugin A";
+
+#[derive(clap::Args)]
+struct MemoryOpt {}
+
+struct MemoryPlugin;
+
+impl MemoryPlugin {
+    fn new() -> Result<Self, Error> {
+        Ok(Self)
+    }
+}
+
+impl Plugin for MemoryPlugin {
+    fn name(&self) -> &'static str {
+        PLUGIN_NAME
+    }
+
+    fn enabled(&self) -> bool {
+        false
+    }
+
+    fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+        Vec::new()
+    }
+
+    fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+declare_plugin!(MemoryPlugin, MemoryPlugin::new, MemoryOpt);
@@ -2,8 +2,9 @@
 extern crate karlsen_miner;
 
 use clap::{ArgMatches, FromArgMatches};
+use cust::device::DeviceAttribute;
 use cust::prelude::*;
-use karlsen_miner::{Plugin, Worker, WorkerSpec};
+use karlsen_miner::{topology, Plugin, PluginCapabilities, RawDevice, Worker, WorkerSpec};
 use log::LevelFilter;
 use std::error::Error as StdError;
 #[cfg(feature = "overclock")]
@@ -22,6 +23,7 @@ use crate::cli::{CudaOpt, NonceGenEnum};
 use crate::worker::CudaGPUWorker;
 
 const DEFAULT_WORKLOAD_SCALE: f32 = 1024.;
+pub(crate) const PLUGIN_NAME: &str = "CUDA Worker";
 
 pub struct CudaPlugin {
     specs: Vec<CudaWorkerSpec>,
@@ -45,7 +47,11 @@ impl CudaPlugin {
 
 impl Plugin for CudaPlugin {
     fn name(&self) -> &'static str {
-        "CUDA Worker"
+        PLUGIN_NAME
+    }
+
+    fn version(&self) -> &'static str {
+        env!("CARGO_PKG_VERSION")
     }
 
     fn enabled(&self) -> bool {
@@ -56,6 +62,22 @@ impl Plugin for CudaPlugin {
         self.specs.iter().map(|spec| Box::new(*spec) as Box<dyn WorkerSpec>).collect::<Vec<Box<dyn WorkerSpec>>>()
     }
 
+    fn available_devices(&self) -> Vec<RawDevice> {
+        let devices = match Device::devices() {
+            Ok(devices) => devices,
+            Err(_) => return Vec::new(),
+        };
+        devices
+            .flatten()
+            .enumerate()
+            .map(|(id, device)| RawDevice {
+                id: id as u32,
+                name: device.name().unwrap_or_else(|_| "Unknown Device".into()),
+                memory_bytes: device.total_memory().unwrap_or(0) as u64,
+            })
+            .collect()
+    }
+
     //noinspection RsTypeCheck
     fn process_option(&mut self, matches: &ArgMatches) -> Result<usize, karlsen_miner::Error> {
         let opts: CudaOpt = CudaOpt::from_arg_matches(matches)?;
@@ -136,6 +158,17 @@ impl Plugin for CudaPlugin {
         }
         Ok(self.specs.len())
     }
+
+    fn validate(&self) -> Result<(), karlsen_miner::Error> {
+        match self.specs.iter().find(|spec| spec.workload <= 0.) {
+            Some(spec) => Err(format!("workload of {} must be positive, got {}", spec.id(), spec.workload).into()),
+            None => Ok(()),
+        }
+    }
+
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities { async_sync: true, ..Default::default() }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -153,10 +186,48 @@ impl WorkerSpec for CudaWorkerSpec {
         format!("#{} ({})", self.device_id, device.name().unwrap())
     }
 
-    fn build(&self) -> Box<dyn Worker> {
-        Box::new(
-            CudaGPUWorker::new(self.device_id, self.workload, self.is_absolute, self.blocking_sync, self.random)
-                .unwrap(),
+    fn build(&self) -> Result<Box<dyn Worker>, karlsen_miner::Error> {
+        Ok(Box::new(CudaGPUWorker::new(
+            self.device_id,
+            self.workload,
+            self.is_absolute,
+            self.blocking_sync,
+            self.random,
+        )?))
+    }
+
+    fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+        Box::new(*self)
+    }
+
+    fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    fn gpu_type(&self) -> &str {
+        "CUDA"
+    }
+
+    fn workload(&self) -> f32 {
+        self.workload
+    }
+
+    fn workload_is_absolute(&self) -> bool {
+        self.is_absolute
+    }
+
+    /// Relative workloads are already multiplied by the device's parallelism, so the default scale suits any card
+    fn recommended_workload(&self) -> f32 {
+        DEFAULT_WORKLOAD_SCALE
+    }
+
+    fn numa_node(&self) -> Option<u32> {
+        let device = Device::get_device(self.device_id).ok()?;
+        let pci = |attribute| device.get_attribute(attribute).ok().map(|value| value as u32);
+        topology::pci_numa_node(
+            pci(DeviceAttribute::PciDomainId)?,
+            pci(DeviceAttribute::PciBusId)?,
+            pci(DeviceAttribute::PciDeviceId)?,
         )
     }
 }
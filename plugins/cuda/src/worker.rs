@@ -1,15 +1,18 @@
-use crate::{Error, NonceGenEnum};
+use crate::{Error, NonceGenEnum, PLUGIN_NAME};
 use cust::context::CurrentContext;
 use cust::device::DeviceAttribute;
+use cust::event::EventStatus;
 use cust::function::Function;
 use cust::module::{ModuleJitOption, OptLevel};
 use cust::prelude::*;
 use karlsen_miner::xoshiro256starstar::Xoshiro256StarStar;
-use karlsen_miner::Worker;
+use karlsen_miner::events::{EventSink, MinerEvent};
+use karlsen_miner::{assign_worker_uuid, MiningJob, Worker, WorkerError};
 use log::{error, info};
 use rand::{Fill, RngCore};
 use std::ffi::CString;
 use std::sync::{Arc, Weak};
+use std::time::Instant;
 
 static BPS: f32 = 1.;
 
@@ -67,6 +70,11 @@ pub struct CudaGPUWorker<'gpu> {
     _context: Context,
 
     random: NonceGenEnum,
+    hashes_computed: u64,
+    last_progress: Instant,
+    uuid: u128,
+    job_id: Option<u64>,
+    event_sink: Option<EventSink>,
 }
 
 impl<'gpu> Worker for CudaGPUWorker<'gpu> {
@@ -75,16 +83,47 @@ impl<'gpu> Worker for CudaGPUWorker<'gpu> {
         format!("#{} ({})", self.device_id, device.name().unwrap())
     }
 
-    fn load_block_constants(&mut self, hash_header: &[u8; 72], matrix: &[[u16; 64]; 64], target: &[u64; 4]) {
+    fn source_plugin(&self) -> &'static str {
+        PLUGIN_NAME
+    }
+
+    fn uuid(&self) -> u128 {
+        self.uuid
+    }
+
+    /// The nonce buffers only, the module's constants are negligible
+    fn memory_usage_bytes(&self) -> Option<u64> {
+        Some(((self.rand_state.len() + self.final_nonce_buff.len()) * std::mem::size_of::<u64>()) as u64)
+    }
+
+    fn load_block_constants(
+        &mut self,
+        hash_header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+    ) -> Result<(), Error> {
         let u8matrix: Arc<[[u8; 64]; 64]> = Arc::new(matrix.map(|row| row.map(|v| v as u8)));
-        let mut hash_header_gpu = self._module.get_global::<[u8; 72]>(&CString::new("hash_header").unwrap()).unwrap();
-        hash_header_gpu.copy_from(hash_header).map_err(|e| e.to_string()).unwrap();
+        let mut hash_header_gpu = self._module.get_global::<[u8; 72]>(&CString::new("hash_header")?)?;
+        hash_header_gpu.copy_from(hash_header)?;
+
+        let mut matrix_gpu = self._module.get_global::<[[u8; 64]; 64]>(&CString::new("matrix")?)?;
+        matrix_gpu.copy_from(&u8matrix)?;
 
-        let mut matrix_gpu = self._module.get_global::<[[u8; 64]; 64]>(&CString::new("matrix").unwrap()).unwrap();
-        matrix_gpu.copy_from(&u8matrix).map_err(|e| e.to_string()).unwrap();
+        let mut target_gpu = self._module.get_global::<[u64; 4]>(&CString::new("target")?)?;
+        target_gpu.copy_from(target)?;
+        self.job_id = Some(0);
+        Ok(())
+    }
 
-        let mut target_gpu = self._module.get_global::<[u64; 4]>(&CString::new("target").unwrap()).unwrap();
-        target_gpu.copy_from(target).map_err(|e| e.to_string()).unwrap();
+    fn load_job(&mut self, job: &MiningJob) -> Result<(), Error> {
+        self.load_block_constants(&job.header, &job.matrix, &job.target)?;
+        self.job_id = Some(job.job_id);
+        self.emit(MinerEvent::JobApplied { worker: self.id(), job_id: job.job_id });
+        Ok(())
+    }
+
+    fn current_job_id(&self) -> Option<u64> {
+        self.job_id
     }
 
     #[inline(always)]
@@ -116,30 +155,69 @@ impl<'gpu> Worker for CudaGPUWorker<'gpu> {
             .unwrap(); // We see errors in sync
         }
         self.stop_event.record(stream).unwrap();
+        self.hashes_computed += self.workload as u64;
+        self.last_progress = Instant::now();
     }
 
     #[inline(always)]
-    fn sync(&self) -> Result<(), Error> {
+    fn sync(&self) -> Result<(), WorkerError> {
         //self.stream.synchronize()?;
-        self.stop_event.synchronize()?;
-        if self.stop_event.elapsed_time_f32(&self.start_event)? > 1000. / BPS {
-            return Err("Cuda takes longer then block rate. Please reduce your workload.".into());
+        self.stop_event.synchronize().map_err(|e| WorkerError::KernelFailure(e.to_string()))?;
+        let elapsed = self
+            .stop_event
+            .elapsed_time_f32(&self.start_event)
+            .map_err(|e| WorkerError::KernelFailure(e.to_string()))?;
+        if elapsed > 1000. / BPS {
+            return Err(WorkerError::Timeout("Cuda takes longer then block rate. Please reduce your workload.".into()));
         }
         Ok(())
     }
 
+    fn try_sync(&self) -> Result<bool, WorkerError> {
+        match self.stop_event.query().map_err(|e| WorkerError::KernelFailure(e.to_string()))? {
+            EventStatus::Ready => self.sync().map(|()| true),
+            EventStatus::NotReady => Ok(false),
+        }
+    }
+
+    fn hashes_computed(&self) -> u64 {
+        self.hashes_computed
+    }
+
+    fn last_progress(&self) -> Instant {
+        self.last_progress
+    }
+
     fn get_workload(&self) -> usize {
         self.workload
     }
 
     #[inline(always)]
-    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<(), Error> {
+    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error> {
         self.final_nonce_buff.copy_to(nonces)?;
-        Ok(())
+        self.final_nonce_buff.copy_from(&[0])?;
+        if let (true, Some(job_id)) = (nonces[0] != 0, self.job_id) {
+            self.emit(MinerEvent::NonceFound { worker: self.id(), nonce: nonces[0], job_id });
+        }
+        Ok(usize::from(nonces[0] != 0))
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.event_sink = Some(sink);
+    }
+
+    fn event_sink(&self) -> Option<EventSink> {
+        self.event_sink.clone()
     }
 }
 
 impl<'gpu> CudaGPUWorker<'gpu> {
+    fn emit(&self, event: MinerEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(event);
+        }
+    }
+
     pub fn new(
         device_id: u32,
         workload: f32,
@@ -246,6 +324,11 @@ impl<'gpu> CudaGPUWorker<'gpu> {
             final_nonce_buff,
             heavy_hash_kernel,
             random,
+            hashes_computed: 0,
+            last_progress: Instant::now(),
+            uuid: assign_worker_uuid(PLUGIN_NAME, device_id),
+            job_id: None,
+            event_sink: None,
         })
     }
 }
@@ -2,7 +2,7 @@
 extern crate karlsen_miner;
 
 use clap::{ArgMatches, FromArgMatches};
-use karlsen_miner::{Plugin, Worker, WorkerSpec};
+use karlsen_miner::{Plugin, RawDevice, Worker, WorkerSpec};
 use log::{info, LevelFilter};
 use opencl3::device::{Device, CL_DEVICE_TYPE_ALL};
 use opencl3::platform::{get_platforms, Platform};
@@ -18,6 +18,7 @@ use crate::cli::{NonceGenEnum, OpenCLOpt};
 use crate::worker::OpenCLGPUWorker;
 
 const DEFAULT_WORKLOAD_SCALE: f32 = 512.;
+pub(crate) const PLUGIN_NAME: &str = "OpenCL Worker";
 
 pub struct OpenCLPlugin {
     specs: Vec<OpenCLWorkerSpec>,
@@ -33,7 +34,11 @@ impl OpenCLPlugin {
 
 impl Plugin for OpenCLPlugin {
     fn name(&self) -> &'static str {
-        "OpenCL Worker"
+        PLUGIN_NAME
+    }
+
+    fn version(&self) -> &'static str {
+        env!("CARGO_PKG_VERSION")
     }
 
     fn enabled(&self) -> bool {
@@ -44,6 +49,23 @@ impl Plugin for OpenCLPlugin {
         self.specs.iter().map(|spec| Box::new(*spec) as Box<dyn WorkerSpec>).collect::<Vec<Box<dyn WorkerSpec>>>()
     }
 
+    /// Devices of every platform; ids are per platform like `--opencl-device`, so the name carries the platform
+    fn available_devices(&self) -> Vec<RawDevice> {
+        let mut devices = Vec::new();
+        for platform in get_platforms().unwrap_or_default() {
+            let platform_name = platform.name().unwrap_or_else(|_| "Unk".into());
+            for (id, device) in platform.get_devices(CL_DEVICE_TYPE_ALL).unwrap_or_default().into_iter().enumerate() {
+                let device = Device::new(device);
+                devices.push(RawDevice {
+                    id: id as u32,
+                    name: format!("{}: {}", platform_name, device.name().unwrap_or_else(|_| "Unknown Device".into())),
+                    memory_bytes: device.global_mem_size().unwrap_or(0),
+                });
+            }
+        }
+        devices
+    }
+
     //noinspection RsTypeCheck
     fn process_option(&mut self, matches: &ArgMatches) -> Result<usize, karlsen_miner::Error> {
         let opts: OpenCLOpt = OpenCLOpt::from_arg_matches(matches)?;
@@ -63,24 +85,25 @@ impl Plugin for OpenCLPlugin {
             let num_devices = platform.get_devices(CL_DEVICE_TYPE_ALL).unwrap_or_default().len();
             info!("{}: {} ({} devices available)", vendor, name, num_devices);
         }
-        let amd_platforms = (&platforms)
-            .iter()
-            .filter(|p| {
+        let amd_platforms = (0..platforms.len())
+            .filter(|&i| {
+                let p = &platforms[i];
                 p.vendor().unwrap_or_else(|_| "Unk".into()) == "Advanced Micro Devices, Inc."
                     && !p.get_devices(CL_DEVICE_TYPE_ALL).unwrap_or_default().is_empty()
             })
-            .collect::<Vec<&Platform>>();
-        let _platform: &Platform = match opts.opencl_platform {
+            .collect::<Vec<usize>>();
+        let platform_index: u16 = match opts.opencl_platform {
             Some(idx) => {
                 self._enabled = true;
-                &platforms[idx as usize]
+                idx
             }
             None if !opts.opencl_amd_disable && !amd_platforms.is_empty() => {
                 self._enabled = true;
-                amd_platforms[0]
+                amd_platforms[0] as u16
             }
-            None => &platforms[0],
+            None => 0,
         };
+        let _platform: &Platform = &platforms[platform_index as usize];
         if self._enabled {
             info!(
                 "Chose to mine on {}: {}.",
@@ -100,6 +123,7 @@ impl Plugin for OpenCLPlugin {
             self.specs = (0..gpus.len())
                 .map(|i| OpenCLWorkerSpec {
                     _platform: *_platform,
+                    platform_index,
                     index: i,
                     device_id: Device::new(gpus[i]),
                     workload: match &opts.opencl_workload {
@@ -116,11 +140,19 @@ impl Plugin for OpenCLPlugin {
         }
         Ok(self.specs.len())
     }
+
+    fn validate(&self) -> Result<(), karlsen_miner::Error> {
+        match self.specs.iter().find(|spec| spec.workload <= 0.) {
+            Some(spec) => Err(format!("workload of {} must be positive, got {}", spec.id(), spec.workload).into()),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
 struct OpenCLWorkerSpec {
     _platform: Platform,
+    platform_index: u16,
     index: usize,
     device_id: Device,
     workload: f32,
@@ -141,18 +173,45 @@ impl WorkerSpec for OpenCLWorkerSpec {
         )
     }
 
-    fn build(&self) -> Box<dyn Worker> {
-        Box::new(
-            OpenCLGPUWorker::new(
-                self.device_id,
-                self.workload,
-                self.is_absolute,
-                self.experimental_amd,
-                self.use_amd_binary,
-                &self.random,
-            )
-            .unwrap(),
-        )
+    fn build(&self) -> Result<Box<dyn Worker>, karlsen_miner::Error> {
+        Ok(Box::new(OpenCLGPUWorker::new(
+            self.device_id,
+            self.index as u32,
+            self.workload,
+            self.is_absolute,
+            self.experimental_amd,
+            self.use_amd_binary,
+            &self.random,
+        )?))
+    }
+
+    fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+        Box::new(*self)
+    }
+
+    fn device_id(&self) -> u32 {
+        self.index as u32
+    }
+
+    fn gpu_type(&self) -> &str {
+        "OpenCL"
+    }
+
+    fn opencl_platform(&self) -> Option<u16> {
+        Some(self.platform_index)
+    }
+
+    fn workload(&self) -> f32 {
+        self.workload
+    }
+
+    fn workload_is_absolute(&self) -> bool {
+        self.is_absolute
+    }
+
+    /// Relative workloads are already multiplied by the device's parallelism, so the default scale suits any card
+    fn recommended_workload(&self) -> f32 {
+        DEFAULT_WORKLOAD_SCALE
     }
 }
 
@@ -1,8 +1,9 @@
 use crate::cli::NonceGenEnum;
-use crate::Error;
+use crate::{Error, PLUGIN_NAME};
 use include_dir::{include_dir, Dir};
 use karlsen_miner::xoshiro256starstar::Xoshiro256StarStar;
-use karlsen_miner::Worker;
+use karlsen_miner::events::{EventSink, MinerEvent};
+use karlsen_miner::{assign_worker_uuid, MiningJob, Worker, WorkerError};
 use log::{info, warn};
 use opencl3::command_queue::{CommandQueue, CL_QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE};
 use opencl3::context::Context;
@@ -18,6 +19,7 @@ use std::borrow::Borrow;
 use std::ffi::c_void;
 use std::ptr;
 use std::sync::Arc;
+use std::time::Instant;
 
 static BINARY_DIR: Dir = include_dir!("./plugins/opencl/resources/bin/");
 static PROGRAM_SOURCE: &str = include_str!("../resources/kaspa-opencl.cl");
@@ -42,6 +44,11 @@ pub struct OpenCLGPUWorker {
 
     events: Vec<cl_event>,
     experimental_amd: bool,
+    hashes_computed: u64,
+    last_progress: Instant,
+    uuid: u128,
+    job_id: Option<u64>,
+    event_sink: Option<EventSink>,
 }
 
 impl Worker for OpenCLGPUWorker {
@@ -50,7 +57,32 @@ impl Worker for OpenCLGPUWorker {
         device.name().unwrap()
     }
 
-    fn load_block_constants(&mut self, hash_header: &[u8; 72], matrix: &[[u16; 64]; 64], target: &[u64; 4]) {
+    fn source_plugin(&self) -> &'static str {
+        PLUGIN_NAME
+    }
+
+    fn uuid(&self) -> u128 {
+        self.uuid
+    }
+
+    fn memory_usage_bytes(&self) -> Option<u64> {
+        let sizes = [
+            self.random_state.size(),
+            self.final_nonce.size(),
+            self.final_hash.size(),
+            self.hash_header.size(),
+            self.matrix.size(),
+            self.target.size(),
+        ];
+        sizes.into_iter().map(|size| size.ok().map(|size| size as u64)).sum()
+    }
+
+    fn load_block_constants(
+        &mut self,
+        hash_header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+    ) -> Result<(), Error> {
         let cl_uchar_matrix = match self.experimental_amd {
             true => matrix
                 .iter()
@@ -60,32 +92,41 @@ impl Worker for OpenCLGPUWorker {
         };
         self.queue
             .enqueue_write_buffer(&mut self.final_nonce, CL_BLOCKING, 0, &[0], &[])
-            .map_err(|e| e.to_string())
-            .unwrap()
+            .map_err(|e| e.to_string())?
             .wait()
-            .unwrap();
+            .map_err(|e| e.to_string())?;
         self.queue
             .enqueue_write_buffer(&mut self.hash_header, CL_BLOCKING, 0, hash_header, &[])
-            .map_err(|e| e.to_string())
-            .unwrap()
+            .map_err(|e| e.to_string())?
             .wait()
-            .unwrap();
+            .map_err(|e| e.to_string())?;
         self.queue
             .enqueue_write_buffer(&mut self.matrix, CL_BLOCKING, 0, cl_uchar_matrix.as_slice(), &[])
-            .map_err(|e| e.to_string())
-            .unwrap()
+            .map_err(|e| e.to_string())?
             .wait()
-            .unwrap();
+            .map_err(|e| e.to_string())?;
         let copy_target = self
             .queue
             .enqueue_write_buffer(&mut self.target, CL_BLOCKING, 0, target, &[])
-            .map_err(|e| e.to_string())
-            .unwrap();
+            .map_err(|e| e.to_string())?;
 
         self.events = vec![copy_target.get()];
         for event in &self.events {
-            retain_event(*event).unwrap();
+            retain_event(*event).map_err(|e| format!("retaining event error code {}", e))?;
         }
+        self.job_id = Some(0);
+        Ok(())
+    }
+
+    fn load_job(&mut self, job: &MiningJob) -> Result<(), Error> {
+        self.load_block_constants(&job.header, &job.matrix, &job.target)?;
+        self.job_id = Some(job.job_id);
+        self.emit(MinerEvent::JobApplied { worker: self.id(), job_id: job.job_id });
+        Ok(())
+    }
+
+    fn current_job_id(&self) -> Option<u64> {
+        self.job_id
     }
 
     fn calculate_hash(&mut self, _nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64) {
@@ -119,6 +160,8 @@ impl Worker for OpenCLGPUWorker {
             .unwrap();
 
         kernel_event.wait().unwrap();
+        self.hashes_computed += self.workload as u64;
+        self.last_progress = Instant::now();
 
         /*let mut nonces = [0u64; 1];
         let mut hash = [[0u64; 4]];
@@ -133,30 +176,56 @@ impl Worker for OpenCLGPUWorker {
         retain_event(event);*/
     }
 
-    fn sync(&self) -> Result<(), Error> {
-        wait_for_events(&self.events).map_err(|e| format!("waiting error code {}", e))?;
+    fn sync(&self) -> Result<(), WorkerError> {
+        wait_for_events(&self.events).map_err(|e| WorkerError::KernelFailure(format!("waiting error code {}", e)))?;
         for event in &self.events {
-            release_event(*event).unwrap();
+            release_event(*event).map_err(|e| WorkerError::KernelFailure(format!("releasing event error code {}", e)))?;
         }
         Ok(())
     }
 
+    fn hashes_computed(&self) -> u64 {
+        self.hashes_computed
+    }
+
+    fn last_progress(&self) -> Instant {
+        self.last_progress
+    }
+
     fn get_workload(&self) -> usize {
         self.workload as usize
     }
 
-    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<(), Error> {
+    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error> {
         self.queue
             .enqueue_read_buffer(&self.final_nonce, CL_BLOCKING, 0, nonces, &[])
-            .map_err(|e| e.to_string())
-            .unwrap();
-        Ok(())
+            .map_err(|e| e.to_string())?;
+        self.queue.enqueue_write_buffer(&mut self.final_nonce, CL_BLOCKING, 0, &[0], &[]).map_err(|e| e.to_string())?;
+        if let (true, Some(job_id)) = (nonces[0] != 0, self.job_id) {
+            self.emit(MinerEvent::NonceFound { worker: self.id(), nonce: nonces[0], job_id });
+        }
+        Ok(usize::from(nonces[0] != 0))
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.event_sink = Some(sink);
+    }
+
+    fn event_sink(&self) -> Option<EventSink> {
+        self.event_sink.clone()
     }
 }
 
 impl OpenCLGPUWorker {
+    fn emit(&self, event: MinerEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(event);
+        }
+    }
+
     pub fn new(
         device: Device,
+        device_index: u32,
         workload: f32,
         is_absolute: bool,
         experimental_amd: bool,
@@ -317,6 +386,11 @@ impl OpenCLGPUWorker {
             target,
             events: Vec::<cl_event>::new(),
             experimental_amd: ((experimental_amd | use_binary) & experimental_amd_use),
+            hashes_computed: 0,
+            last_progress: Instant::now(),
+            uuid: assign_worker_uuid(PLUGIN_NAME, device_index),
+            job_id: None,
+            event_sink: None,
         })
     }
 }
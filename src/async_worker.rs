@@ -0,0 +1,153 @@
+//! An async counterpart of `Worker`, for backends whose launches and syncs are naturally futures.
+//!
+//! Only the hashing is async, loading constants and reading the output stay synchronous as they don't wait
+//! on the device. `BlockingWorker` turns an `AsyncWorker` into a `Worker`, so it fits everything taking one.
+use crate::{Error, Worker, WorkerError};
+use async_trait::async_trait;
+use std::cell::RefCell;
+
+#[async_trait(?Send)]
+pub trait AsyncWorker {
+    fn id(&self) -> String;
+    fn load_block_constants(
+        &mut self,
+        hash_header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+    ) -> Result<(), Error>;
+    /// Resolves once the launch is enqueued, like `Worker::calculate_hash_async`
+    async fn calculate_hash(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64);
+    /// Resolves once the last launch completed
+    async fn sync(&mut self) -> Result<(), WorkerError>;
+    fn get_workload(&self) -> usize;
+    /// Same contract as `Worker::copy_output_to`
+    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error>;
+}
+
+/// Drives an `AsyncWorker` on a single-threaded runtime of its own. Each call blocks until the future
+/// resolves, so this must not be called from within another runtime's task.
+pub struct BlockingWorker<W: AsyncWorker> {
+    // `Worker::sync` takes `&self`, the async one needs `&mut`
+    worker: RefCell<W>,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl<W: AsyncWorker> BlockingWorker<W> {
+    pub fn new(worker: W) -> Result<Self, Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        Ok(Self { worker: RefCell::new(worker), runtime })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.worker.into_inner()
+    }
+}
+
+impl<W: AsyncWorker> Worker for BlockingWorker<W> {
+    fn id(&self) -> String {
+        self.worker.borrow().id()
+    }
+
+    fn load_block_constants(
+        &mut self,
+        hash_header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+    ) -> Result<(), Error> {
+        self.worker.get_mut().load_block_constants(hash_header, matrix, target)
+    }
+
+    fn calculate_hash(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64) {
+        self.runtime.block_on(self.worker.get_mut().calculate_hash(nonces, nonce_mask, nonce_fixed))
+    }
+
+    fn sync(&self) -> Result<(), WorkerError> {
+        self.runtime.block_on(self.worker.borrow_mut().sync())
+    }
+
+    fn get_workload(&self) -> usize {
+        self.worker.borrow().get_workload()
+    }
+
+    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error> {
+        self.worker.get_mut().copy_output_to(nonces)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::async_worker::{AsyncWorker, BlockingWorker};
+    use crate::{mine_with_retry, Error, Worker, WorkerError};
+    use async_trait::async_trait;
+
+    /// Finds `target[0]` as nonce; a launch only completes after a few yields to the runtime
+    #[derive(Default)]
+    struct YieldingWorker {
+        target: u64,
+        launched: Vec<u64>,
+        completed: usize,
+        found: Option<u64>,
+    }
+
+    #[async_trait(?Send)]
+    impl AsyncWorker for YieldingWorker {
+        fn id(&self) -> String {
+            "yielding".into()
+        }
+
+        fn load_block_constants(
+            &mut self,
+            _hash_header: &[u8; 72],
+            _matrix: &[[u16; 64]; 64],
+            target: &[u64; 4],
+        ) -> Result<(), Error> {
+            self.target = target[0];
+            Ok(())
+        }
+
+        async fn calculate_hash(&mut self, _nonces: Option<&Vec<u64>>, _nonce_mask: u64, nonce_fixed: u64) {
+            tokio::task::yield_now().await;
+            self.launched.push(nonce_fixed);
+        }
+
+        async fn sync(&mut self) -> Result<(), WorkerError> {
+            for _ in 0..3 {
+                tokio::task::yield_now().await;
+            }
+            if self.launched.len() > self.completed {
+                self.completed = self.launched.len();
+                self.found = Some(self.target);
+            }
+            Ok(())
+        }
+
+        fn get_workload(&self) -> usize {
+            1
+        }
+
+        fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error> {
+            let found = self.found.take().unwrap_or(0);
+            nonces.clear();
+            nonces.push(found);
+            Ok(usize::from(found != 0))
+        }
+    }
+
+    #[test]
+    fn test_blocking_worker() {
+        let mut worker = BlockingWorker::new(YieldingWorker::default()).unwrap();
+        assert_eq!(worker.id(), "yielding");
+        worker.load_block_constants(&[0; 72], &[[0; 64]; 64], &[42, 0, 0, 0]).unwrap();
+        worker.calculate_hash(None, 0, 7);
+        worker.sync().unwrap();
+        let mut nonces = Vec::new();
+        assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 1);
+        assert_eq!(nonces, [42]);
+
+        // Anything taking a `Worker` drives it
+        mine_with_retry(&mut worker, 0).unwrap();
+        assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 1);
+        let inner = worker.into_inner();
+        assert_eq!((inner.launched, inner.completed), (vec![7, 0], 2));
+    }
+}
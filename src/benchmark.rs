@@ -0,0 +1,184 @@
+//! Offline hashrate measurement against fixed block constants, no pool or node involved.
+use crate::header::{generate_matrix, HEADER_SIZE};
+use crate::target::difficulty_to_target;
+use crate::{events, Error, MiningJob, Worker};
+use std::time::{Duration, Instant};
+
+const BENCHMARK_PRE_POW_HASH: [u8; 32] = [0x42; 32];
+const BENCHMARK_TIMESTAMP: u64 = 1_700_000_000_000;
+/// How long `autotune_workload` benchmarks each candidate
+const AUTOTUNE_SAMPLE: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkResult {
+    pub hashes: u64,
+    pub elapsed: Duration,
+    pub hashes_per_sec: f64,
+}
+
+impl BenchmarkResult {
+    fn new(hashes: u64, elapsed: Duration) -> Self {
+        let hashes_per_sec = match elapsed.is_zero() {
+            true => 0.0,
+            false => hashes as f64 / elapsed.as_secs_f64(),
+        };
+        Self { hashes, elapsed, hashes_per_sec }
+    }
+}
+
+/// Warms `worker` up, then launches it back to back for at least `duration`, discarding whatever it finds. The
+/// warmup is neither timed nor counted. Workers that don't count their hashes are credited with their workload
+/// per launch. Fails if the warmup fails or the worker rejects the constants.
+pub fn run_benchmark(worker: &mut dyn Worker, duration: Duration) -> Result<BenchmarkResult, Error> {
+    worker.warmup()?;
+    let mut header = [0u8; HEADER_SIZE];
+    header[..32].copy_from_slice(&BENCHMARK_PRE_POW_HASH);
+    header[32..40].copy_from_slice(&BENCHMARK_TIMESTAMP.to_le_bytes());
+    worker.load_block_constants(&header, &generate_matrix(&BENCHMARK_PRE_POW_HASH), &difficulty_to_target(1.0))?;
+    Ok(measure(worker, duration))
+}
+
+/// Benchmarks `job` at each of the `candidates` workloads in turn and keeps the fastest, the lowest one of those
+/// equally fast. A candidate the worker fails to resize to, warm up at or load the job at, e.g. as it runs the
+/// device out of memory, is skipped and the workload it had before is restored. Returns the workload chosen,
+/// which is applied with `job` loaded; fails if no candidate could be benchmarked.
+pub fn autotune_workload(worker: &mut dyn Worker, job: &MiningJob, candidates: &[usize]) -> Result<usize, Error> {
+    autotune_workload_for(worker, job, candidates, AUTOTUNE_SAMPLE)
+}
+
+fn autotune_workload_for(
+    worker: &mut dyn Worker,
+    job: &MiningJob,
+    candidates: &[usize],
+    sample: Duration,
+) -> Result<usize, Error> {
+    let original = worker.get_workload();
+    let mut candidates = candidates.to_vec();
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mut best: Option<(usize, f64)> = None;
+    for workload in candidates {
+        let prepared = worker.set_workload(workload).and_then(|()| {
+            worker.warmup()?;
+            worker.reset_for_new_job()?;
+            worker.load_job(job)
+        });
+        match prepared {
+            Ok(()) => {
+                let hashes_per_sec = measure(worker, sample).hashes_per_sec;
+                // Ascending order, so only a strictly faster one replaces an earlier candidate
+                if best.is_none_or(|(_, fastest)| hashes_per_sec > fastest) {
+                    best = Some((workload, hashes_per_sec));
+                }
+            }
+            Err(e) => {
+                log_warn!("Skipping workload {} for {}: {}", workload, worker.id(), e);
+                worker.set_workload(original)?;
+            }
+        }
+    }
+    let workload = match best {
+        Some((workload, _)) => workload,
+        None => return Err("none of the candidate workloads could be benchmarked".into()),
+    };
+    worker.set_workload(workload)?;
+    worker.load_job(job)?;
+    Ok(workload)
+}
+
+/// Launches `worker` over whatever it has loaded for at least `duration`
+fn measure(worker: &mut dyn Worker, duration: Duration) -> BenchmarkResult {
+    let mut nonces = vec![0u64; worker.max_output_nonces()];
+    let first_count = worker.hashes_computed();
+    let mut launches = 0u64;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        worker.calculate_hash(None, u64::MAX, 0);
+        // A failing device only distorts its own result, so there is nothing better to do than to go on
+        if let Err(e) = events::reported_sync(worker) {
+            log_error!("Benchmarking {} failed to sync: {}", worker.id(), e);
+        }
+        let _ = worker.copy_output_to(&mut nonces);
+        launches += 1;
+    }
+    let elapsed = start.elapsed();
+
+    let hashes = match worker.hashes_computed().saturating_sub(first_count) {
+        0 => launches * worker.get_workload() as u64,
+        counted => counted,
+    };
+    BenchmarkResult::new(hashes, elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::benchmark::{autotune_workload_for, run_benchmark, BenchmarkResult};
+    use crate::testing::NullWorker;
+    use crate::{MiningJob, Worker};
+    use std::time::{Duration, Instant};
+
+    /// Takes one millisecond per launch, each finding a nonce, and 30 to warm up as if compiling its kernel
+    fn sleeping(workload: usize) -> NullWorker {
+        NullWorker::new("sleeping", workload)
+            .with_launch_time(Duration::from_millis(1))
+            .with_warmup(Duration::from_millis(30))
+            .with_found_nonce(1)
+    }
+
+    #[test]
+    fn test_benchmark_hashrate() {
+        let mut worker = sleeping(1000);
+        let result = run_benchmark(&mut worker, Duration::from_millis(20)).unwrap();
+        assert!(result.elapsed >= Duration::from_millis(20));
+        assert_eq!(result.hashes % 1000, 0);
+        assert!(result.hashes >= 1000);
+        assert_eq!(result.hashes_per_sec, result.hashes as f64 / result.elapsed.as_secs_f64());
+
+        let mut worker = sleeping(500).without_hash_count();
+        let result = run_benchmark(&mut worker, Duration::from_millis(20)).unwrap();
+        assert_eq!(result.hashes % 500, 0);
+        assert!(result.hashes >= 500);
+
+        assert_eq!(BenchmarkResult::new(10, Duration::ZERO).hashes_per_sec, 0.0);
+        assert_eq!(BenchmarkResult::new(10, Duration::from_millis(500)).hashes_per_sec, 20.0);
+    }
+
+    #[test]
+    fn test_benchmark_excludes_warmup() {
+        let mut worker = sleeping(1000);
+        let start = Instant::now();
+        let result = run_benchmark(&mut worker, Duration::from_millis(20)).unwrap();
+        assert!(start.elapsed() >= result.elapsed + Duration::from_millis(30));
+        assert_eq!(result.hashes % 1000, 0);
+        // The warmup's launch was counted by the worker, but not by the benchmark
+        assert_eq!(worker.hashes_computed(), result.hashes + 1000);
+    }
+
+    /// Takes one millisecond per launch whatever its workload, so the hashes per launch are its hashrate: it
+    /// peaks at 256 and above 512 runs out of memory
+    fn tunable() -> NullWorker {
+        NullWorker::new("tunable", 32)
+            .with_launch_time(Duration::from_millis(1))
+            .with_hashes_per_launch(|workload| match workload {
+                0..=256 => workload as u64,
+                _ => 100,
+            })
+            .with_max_workload(512)
+    }
+
+    #[test]
+    fn test_autotune_workload() {
+        let job = MiningJob::new([0; 72], [[0; 64]; 64], [0; 4], 9);
+        let mut worker = tunable();
+        let sample = Duration::from_millis(20);
+        let chosen = autotune_workload_for(&mut worker, &job, &[1024, 64, 512, 256, 128], sample).unwrap();
+        assert_eq!(chosen, 256);
+        assert_eq!((worker.get_workload(), worker.current_job_id()), (256, Some(9)));
+
+        // Nothing to choose from, the workload from before stays
+        let mut worker = tunable();
+        assert!(autotune_workload_for(&mut worker, &job, &[2048, 4096], sample).is_err());
+        assert_eq!(worker.get_workload(), 32);
+    }
+}
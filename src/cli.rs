@@ -8,8 +8,13 @@ use crate::Error;
 pub struct Opt {
     #[clap(short, long, help = "Enable debug logging level")]
     pub debug: bool,
-    #[clap(short = 'a', long = "mining-address", help = "The Kaspa address for the miner reward")]
-    pub mining_address: String,
+    #[clap(
+        short = 'a',
+        long = "mining-address",
+        help = "The Kaspa address for the miner reward",
+        required_unless_present = "benchmark"
+    )]
+    pub mining_address: Option<String>,
     #[clap(short = 's', long = "karlsend-address", default_value = "127.0.0.1", help = "The IP of the karlsend instance")]
     pub karlsend_address: String,
 
@@ -29,6 +34,8 @@ pub struct Opt {
         long_help = "Mine even when karlsend says it is not synced, only useful when passing `--allow-submit-block-when-not-synced` to karlsend  [default: false]"
     )]
     pub mine_when_not_synced: bool,
+    #[clap(long, help = "Measure the hashrate of every plugin worker offline and exit, no karlsend needed")]
+    pub benchmark: bool,
 
     #[clap(skip)]
     pub devfund_address: String,
@@ -85,7 +92,7 @@ impl Opt {
             self.num_threads = Some(0);
         }
 
-        let miner_network = self.mining_address.split(':').next();
+        let miner_network = self.mining_address.as_deref().and_then(|address| address.split(':').next());
         self.devfund_address = String::from("karlsen:qzrq7v5jhsc5znvtfdg6vxg7dz5x8dqe4wrh90jkdnwehp6vr8uj7csdss2l7");
         let devfund_network = self.devfund_address.split(':').next();
         if miner_network.is_some() && devfund_network.is_some() && miner_network != devfund_network {
@@ -1,15 +1,16 @@
 use crate::client::Client;
-use crate::pow::BlockSeed;
 use crate::pow::BlockSeed::{FullBlock, PartialBlock};
+use crate::pow::{serialize_header, BlockSeed, HeaderHasher};
 use crate::proto::kaspad_message::Payload;
 use crate::proto::rpc_client::RpcClient;
 use crate::proto::{
     GetBlockTemplateRequestMessage, GetInfoRequestMessage, KaspadMessage, NotifyBlockAddedRequestMessage,
-    NotifyNewBlockTemplateRequestMessage,
+    NotifyNewBlockTemplateRequestMessage, RpcBlock,
 };
 use crate::{miner::MinerManager, Error};
 use async_trait::async_trait;
 use futures_util::StreamExt;
+use karlsen_miner::solo::{BlockTemplate, NodeRpc};
 use log::{error, info, warn};
 use rand::{thread_rng, RngCore};
 use semver::Version;
@@ -182,8 +183,98 @@ impl KaspadHandler {
     }
 }
 
+/// Polled by `karlsen_miner::solo::SoloClient` instead of driven by `listen`; only one of the two may read the stream
+#[async_trait(?Send)]
+impl NodeRpc for KaspadHandler {
+    type Block = RpcBlock;
+
+    async fn get_block_template(&mut self, pay_address: &str) -> Result<Option<BlockTemplate<RpcBlock>>, Error> {
+        self.client_send(GetBlockTemplateRequestMessage {
+            pay_address: pay_address.into(),
+            extra_data: EXTRA_DATA.into(),
+        })
+        .await?;
+        loop {
+            // Notifications and the answer to the `GetInfoRequestMessage` from `connect` are of no use when polling
+            if let Payload::GetBlockTemplateResponse(template) = self.next_payload().await? {
+                return match (template.block, template.is_synced, template.error) {
+                    (_, _, Some(e)) => Err(format!("GetTemplate returned with an error: {:?}", e).into()),
+                    (Some(b), true, None) => block_template(b).map(Some),
+                    (Some(b), false, None) if self.mine_when_not_synced => block_template(b).map(Some),
+                    _ => Ok(None),
+                };
+            }
+        }
+    }
+
+    async fn submit_block(&mut self, mut block: RpcBlock, nonce: u64) -> Result<(), Error> {
+        block.header.as_mut().ok_or("Header is missing")?.nonce = nonce;
+        self.client_send(KaspadMessage::submit_block(block)).await?;
+        loop {
+            if let Payload::SubmitBlockResponse(res) = self.next_payload().await? {
+                return match res.error {
+                    None => Ok(()),
+                    Some(e) => Err(format!("Failed submitting block: {:?}", e).into()),
+                };
+            }
+        }
+    }
+}
+
+impl KaspadHandler {
+    async fn next_payload(&mut self) -> Result<Payload, Error> {
+        loop {
+            match self.stream.message().await? {
+                Some(KaspadMessage { payload: Some(payload) }) => return Ok(payload),
+                Some(_) => warn!("kaspad message payload is empty"),
+                None => return Err("kaspad closed the message stream".into()),
+            }
+        }
+    }
+}
+
+/// The template of `block`, its pre-pow hash computed the way `pow::State` does for a full block
+fn block_template(block: RpcBlock) -> Result<BlockTemplate<RpcBlock>, Error> {
+    let header = block.header.as_ref().ok_or("Header is missing")?;
+    let mut hasher = HeaderHasher::new();
+    serialize_header(&mut hasher, header, true);
+    let (timestamp, bits) = (header.timestamp as u64, header.bits);
+    Ok(BlockTemplate { pre_pow_hash: hasher.finalize().to_le_bytes(), timestamp, bits, block })
+}
+
 impl Drop for KaspadHandler {
     fn drop(&mut self) {
         self.block_handle.abort();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::client::grpc::block_template;
+    use crate::pow::{BlockSeed, State};
+    use crate::proto::{RpcBlock, RpcBlockHeader};
+
+    #[test]
+    fn test_block_template() {
+        let header = RpcBlockHeader {
+            version: 1,
+            hash_merkle_root: "a98347ec1e71514eb26822162dc7c3992fd41f0b2ccc26e55e7bd8f3fa37215f".into(),
+            accepted_id_merkle_root: "774b5216b5b872b6c2388dd950160e3ffa3bf0623c438655bb5c8c768ab33ae2".into(),
+            utxo_commitment: "ee39218674008665e20a3acdf84abef35cabcc489158c0853fd5bfa954226139".into(),
+            timestamp: 1_700_000_000_000,
+            bits: 0x1e7fffff,
+            nonce: 42,
+            blue_work: "ce5639b8ed46571e".into(),
+            pruning_point: "fc44c4f57cf8f7a2ba410a70d0ad49060355b9deb97012345603d9d0d1dcb0de".into(),
+            ..Default::default()
+        };
+        let block = RpcBlock { header: Some(header), ..Default::default() };
+        let template = block_template(block.clone()).unwrap();
+        let state = State::new(0, BlockSeed::FullBlock(Box::new(block))).unwrap();
+        assert_eq!(template.pre_pow_hash, state.pow_hash_header[..32]);
+        assert_eq!(template.timestamp.to_le_bytes(), state.pow_hash_header[32..40]);
+        assert_eq!(template.bits, 0x1e7fffff);
+
+        assert!(block_template(RpcBlock::default()).is_err());
+    }
+}
@@ -0,0 +1,747 @@
+//! Reference heavy-hash implementation running on the CPU.
+//!
+//! It is slow, but it is the ground truth GPU plugins can be checked against.
+use crate::cpu::hasher::PowHasher;
+use crate::cpu::heavy_hash::Matrix;
+use crate::events::{EventSink, MinerEvent};
+use crate::header::is_matrix_valid;
+use crate::session::WorkerSession;
+use crate::target::Uint256;
+use crate::xoshiro256starstar::Xoshiro256StarStar;
+use crate::{
+    assign_worker_uuid, planted_self_check, Error, FoundNonce, JobConstants, MiningJob, NonceRange, Plugin,
+    PluginCapabilities, Worker, WorkerError, WorkerSpec, WorkerStats,
+};
+use clap::ArgMatches;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher as _};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+pub mod hasher;
+pub mod heavy_hash;
+mod keccak;
+mod xoshiro;
+
+pub type Hash = Uint256;
+
+const PLUGIN_NAME: &str = "CPU Reference Worker";
+
+/// Recomputes every candidate on the CPU and keeps only those that really meet `target`,
+/// so false positives from a misbehaving GPU are never submitted.
+pub fn verify_nonces(header: &[u8; 72], matrix: &[[u16; 64]; 64], target: &[u64; 4], nonces: &[u64]) -> Vec<u64> {
+    let hasher = pow_hasher(header);
+    let matrix = Matrix(*matrix);
+    let target = Uint256::new(*target);
+    nonces.iter().copied().filter(|&nonce| matrix.heavy_hash(hasher.finalize_with_nonce(nonce)) <= target).collect()
+}
+
+/// The pow hash of `nonce`, what `verify_nonces` compares against the target
+pub fn pow_hash(header: &[u8; 72], matrix: &[[u16; 64]; 64], nonce: u64) -> Hash {
+    Matrix(*matrix).heavy_hash(pow_hasher(header).finalize_with_nonce(nonce))
+}
+
+/// The full pow hash of a single nonce as little-endian bytes, the layout a GPU kernel writes its hash in,
+/// to compare a kernel's result bit for bit when the pool rejects a share the GPU found
+pub fn debug_hash(header: &[u8; 72], matrix: &[[u16; 64]; 64], nonce: u64) -> [u8; 32] {
+    pow_hash(header, matrix, nonce).to_le_bytes()
+}
+
+// The header is PRE_POW_HASH || TIME || 32 zero byte padding
+fn pow_hasher(header: &[u8; 72]) -> PowHasher {
+    let pre_pow_hash = Hash::from_le_bytes(header[..32].try_into().unwrap());
+    let timestamp = u64::from_le_bytes(header[32..40].try_into().unwrap());
+    PowHasher::new(pre_pow_hash, timestamp)
+}
+
+/// One set of block constants a `CpuWorker` checks every nonce against
+struct CpuJob {
+    job_id: u64,
+    hasher: PowHasher,
+    matrix: Matrix,
+    target: Uint256,
+    found: Option<u64>,
+}
+
+impl CpuJob {
+    fn new(job_id: u64, hash_header: &[u8; 72], matrix: &[[u16; 64]; 64], target: &[u64; 4]) -> Result<Self, Error> {
+        // Consensus only ever generates full rank matrices, anything else is a corrupted job
+        if !is_matrix_valid(matrix) {
+            return Err("matrix is not full rank".into());
+        }
+        Ok(Self {
+            job_id,
+            hasher: pow_hasher(hash_header),
+            matrix: Matrix(*matrix),
+            target: Uint256::new(*target),
+            found: None,
+        })
+    }
+}
+
+pub struct CpuWorker {
+    id: usize,
+    workload: usize,
+    // Set by `load_block_constants` (as job 0) or `load_jobs`
+    jobs: Vec<CpuJob>,
+    rng: Xoshiro256StarStar,
+    found_count: u64,
+    hashes_computed: u64,
+    // Scanned by `calculate_hash_range` since the jobs were set
+    covered: Vec<NonceRange>,
+    last_progress: Instant,
+    paused: bool,
+    stop_requested: AtomicBool,
+    uuid: u128,
+    events: Option<EventSink>,
+}
+
+impl CpuWorker {
+    pub fn new(id: usize, workload: usize, rng_state: [u64; 4]) -> Self {
+        Self {
+            id,
+            workload,
+            jobs: Vec::new(),
+            rng: Xoshiro256StarStar::new(&rng_state),
+            found_count: 0,
+            hashes_computed: 0,
+            covered: Vec::new(),
+            last_progress: Instant::now(),
+            paused: false,
+            stop_requested: AtomicBool::new(false),
+            uuid: assign_worker_uuid(PLUGIN_NAME, id as u32),
+            events: None,
+        }
+    }
+
+    fn check_nonce(&mut self, nonce: u64) {
+        for job in &mut self.jobs {
+            if job.found.is_some() {
+                continue;
+            }
+            let pow = job.matrix.heavy_hash(job.hasher.finalize_with_nonce(nonce));
+            // The pow hash must be less or equal than the claimed target.
+            if pow <= job.target {
+                job.found = Some(nonce);
+                self.found_count += 1;
+                if let Some(events) = &self.events {
+                    events.emit(MinerEvent::NonceFound {
+                        worker: format!("CPU #{}", self.id),
+                        nonce,
+                        job_id: job.job_id,
+                    });
+                }
+            }
+        }
+        self.hashes_computed += self.jobs.len() as u64;
+    }
+
+    fn is_idle(&self) -> bool {
+        self.paused || self.jobs.is_empty()
+    }
+
+    /// Returns how many of `nonces` were hashed before a stop request (if any)
+    fn hash_nonces(&mut self, nonces: impl IntoIterator<Item = u64>) -> u64 {
+        if self.is_idle() {
+            return 0;
+        }
+        let mut hashed = 0;
+        for nonce in nonces {
+            if self.stop_requested.load(Ordering::Relaxed) {
+                break;
+            }
+            self.check_nonce(nonce);
+            hashed += 1;
+        }
+        self.last_progress = Instant::now();
+        hashed
+    }
+
+    fn record_covered(&mut self, range: NonceRange) {
+        if range.len == 0 {
+            return;
+        }
+        match self.covered.last_mut() {
+            Some(last) if last.end() == range.start => last.len += range.len,
+            _ => self.covered.push(range),
+        }
+    }
+
+    fn set_jobs(&mut self, jobs: Vec<CpuJob>) {
+        self.covered.clear();
+        self.jobs = jobs;
+        self.stop_requested.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Worker for CpuWorker {
+    fn id(&self) -> String {
+        format!("CPU #{}", self.id)
+    }
+
+    fn source_plugin(&self) -> &'static str {
+        PLUGIN_NAME
+    }
+
+    fn uuid(&self) -> u128 {
+        self.uuid
+    }
+
+    fn self_check(&mut self) -> Result<(), Error> {
+        planted_self_check(self)
+    }
+
+    fn load_block_constants(
+        &mut self,
+        hash_header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+    ) -> Result<(), Error> {
+        let job = CpuJob::new(0, hash_header, matrix, target)?;
+        self.set_jobs(vec![job]);
+        Ok(())
+    }
+
+    fn load_job(&mut self, job: &MiningJob) -> Result<(), Error> {
+        self.load_jobs(&[job.constants()])
+    }
+
+    fn current_job_id(&self) -> Option<u64> {
+        self.jobs.last().map(|job| job.job_id)
+    }
+
+    fn load_jobs(&mut self, jobs: &[JobConstants]) -> Result<(), Error> {
+        let jobs = jobs
+            .iter()
+            .map(|job| CpuJob::new(job.job_id, &job.header, &job.matrix, &job.target))
+            .collect::<Result<_, _>>()?;
+        self.set_jobs(jobs);
+        if let Some(events) = &self.events {
+            for job in &self.jobs {
+                events.emit(MinerEvent::JobApplied { worker: self.id(), job_id: job.job_id });
+            }
+        }
+        Ok(())
+    }
+
+    fn calculate_hash(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64) {
+        match nonces {
+            Some(nonces) => {
+                self.hash_nonces(nonces.iter().copied());
+            }
+            None if self.is_idle() => {}
+            None => {
+                for _ in 0..self.workload {
+                    if self.stop_requested.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let nonce = (self.rng.next_u64() & nonce_mask) | nonce_fixed;
+                    self.check_nonce(nonce);
+                }
+                self.last_progress = Instant::now();
+            }
+        }
+    }
+
+    fn calculate_hash_range(&mut self, start: u64, count: u64) {
+        let len = self.hash_nonces(start..start.saturating_add(count));
+        self.record_covered(NonceRange { start, len });
+    }
+
+    fn covered_ranges(&self) -> Vec<NonceRange> {
+        self.covered.clone()
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn event_sink(&self) -> Option<EventSink> {
+        self.events.clone()
+    }
+
+    fn rng_state(&self) -> Option<[u64; 4]> {
+        Some(self.rng.state())
+    }
+
+    fn set_rng_seed(&mut self, seed: u64) {
+        self.rng = Xoshiro256StarStar::from_seed_u64(seed);
+    }
+
+    fn restore_session(&mut self, session: &WorkerSession) -> Result<(), Error> {
+        if let Some(state) = session.rng_state {
+            self.rng = Xoshiro256StarStar::from_state(state).ok_or("the saved generator state is all zeros")?;
+        }
+        self.covered = session.covered.clone();
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), WorkerError> {
+        Ok(())
+    }
+
+    fn get_workload(&self) -> usize {
+        self.workload
+    }
+
+    fn set_workload(&mut self, workload: usize) -> Result<(), Error> {
+        self.workload = workload;
+        Ok(())
+    }
+
+    /// The CPU has no launches to size, a batch is simply the workload
+    fn set_batch_size(&mut self, hashes_per_launch: u64) {
+        self.workload = hashes_per_launch.try_into().unwrap_or(usize::MAX);
+    }
+
+    fn hashes_computed(&self) -> u64 {
+        self.hashes_computed
+    }
+
+    /// Every nonce is checked against the target on the CPU before it gets reported
+    fn self_verifies(&self) -> bool {
+        true
+    }
+
+    fn last_progress(&self) -> Instant {
+        self.last_progress
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn request_stop(&self) {
+        self.stop_requested.store(true, Ordering::Relaxed);
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.stop_requested.load(Ordering::Relaxed)
+    }
+
+    fn stats(&self) -> WorkerStats {
+        WorkerStats {
+            hashes: self.hashes_computed,
+            found: self.found_count,
+            workload: self.workload,
+            paused: self.paused,
+        }
+    }
+
+    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error> {
+        if nonces.is_empty() {
+            nonces.push(0);
+        }
+        nonces[0] = 0;
+        // Nonces of jobs beyond the slots given stay pending for the next call
+        let mut written = 0;
+        for job in &mut self.jobs {
+            if written == nonces.len() {
+                break;
+            }
+            if let Some(nonce) = job.found.take() {
+                nonces[written] = nonce;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    fn output_pending(&self) -> usize {
+        self.jobs.iter().filter(|job| job.found.is_some()).count()
+    }
+
+    fn copy_job_output_to(&mut self, _jobs: &[JobConstants], found: &mut Vec<FoundNonce>) -> Result<usize, Error> {
+        let before = found.len();
+        found.extend(
+            self.jobs
+                .iter_mut()
+                .filter_map(|job| job.found.take().map(|nonce| FoundNonce { job_id: job.job_id, nonce })),
+        );
+        Ok(found.len() - before)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct CpuWorkerSpec {
+    id: usize,
+    workload: usize,
+    rng_state: [u64; 4],
+}
+
+impl WorkerSpec for CpuWorkerSpec {
+    fn id(&self) -> String {
+        format!("CPU #{}", self.id)
+    }
+
+    fn build(&self) -> Result<Box<dyn Worker>, Error> {
+        Ok(Box::new(CpuWorker::new(self.id, self.workload, self.rng_state)))
+    }
+
+    fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+        Box::new(*self)
+    }
+
+    fn device_id(&self) -> u32 {
+        self.id as u32
+    }
+
+    fn gpu_type(&self) -> &str {
+        "CPU"
+    }
+}
+
+/// Built-in plugin handing out `CpuWorker`s, register it with `PluginManager::register_plugin`
+pub struct CpuPlugin {
+    specs: Vec<CpuWorkerSpec>,
+}
+
+impl CpuPlugin {
+    pub fn new(workers: usize, workload: usize) -> Self {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(RandomState::new().build_hasher().finish());
+        let specs = (0..workers)
+            .map(|id| {
+                // Every worker gets its own non-overlapping nonce stream
+                rng.jump();
+                CpuWorkerSpec { id, workload, rng_state: rng.state() }
+            })
+            .collect();
+        Self { specs }
+    }
+}
+
+impl Plugin for CpuPlugin {
+    fn name(&self) -> &'static str {
+        PLUGIN_NAME
+    }
+
+    fn version(&self) -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn enabled(&self) -> bool {
+        !self.specs.is_empty()
+    }
+
+    fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+        self.specs.iter().map(|spec| Box::new(*spec) as Box<dyn WorkerSpec>).collect()
+    }
+
+    fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+        Ok(self.specs.len())
+    }
+
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities { async_sync: false, pausable: true, nonce_ranges: true, self_verifying: true }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::heavy_hash::Matrix;
+    use crate::cpu::{debug_hash, verify_nonces, CpuPlugin, CpuWorker, Hash};
+    use crate::events::{EventSink, MinerEvent};
+    use crate::{FoundNonce, JobConstants, MiningJob, NonceRange, Plugin, Worker};
+    use sha3::digest::{ExtendableOutput, Update, XofReader};
+    use sha3::CShake256;
+    use std::time::{Duration, Instant};
+
+    const PROOF_OF_WORK_DOMAIN: &[u8] = b"ProofOfWorkHash";
+
+    fn header(pre_pow_hash: Hash, timestamp: u64) -> [u8; 72] {
+        let mut header = [0u8; 72];
+        header[..32].copy_from_slice(&pre_pow_hash.to_le_bytes());
+        header[32..40].copy_from_slice(&timestamp.to_le_bytes());
+        header
+    }
+
+    // Independent of `PowHasher`, straight from the cSHAKE256 definition
+    fn reference_pow(pre_pow_hash: Hash, timestamp: u64, matrix: &Matrix, nonce: u64) -> Hash {
+        let hasher = CShake256::new(PROOF_OF_WORK_DOMAIN)
+            .chain(pre_pow_hash.to_le_bytes())
+            .chain(timestamp.to_le_bytes())
+            .chain([0u8; 32])
+            .chain(nonce.to_le_bytes());
+        let mut hash = [0u8; 32];
+        hasher.finalize_xof().read(&mut hash);
+        matrix.heavy_hash(Hash::from_le_bytes(hash))
+    }
+
+    // NIST SP 800-185 cSHAKE sample #3, anchoring the primitive `reference_pow` is built on to a published value
+    #[test]
+    fn test_cshake256_nist_vector() {
+        let mut hash = [0u8; 64];
+        CShake256::new(b"Email Signature").chain([0, 1, 2, 3]).finalize_xof().read(&mut hash);
+        assert_eq!(
+            hex::encode_upper(hash),
+            "D008828E2B80AC9D2218FFEE1D070C48B8E4C87BFF32C9699D5B6896EEE0EDD1\
+             64020E2BE0560858D9C00C037E34A96937C561A74C412BB4C746469527281C8C"
+        );
+    }
+
+    #[test]
+    fn test_cpu_worker_target_boundary() {
+        let pre_pow_hash = Hash::from_le_bytes([42; 32]);
+        let timestamp = 5435345234;
+        let nonce = 432432432;
+        let matrix = Matrix::generate(pre_pow_hash);
+        let pow = reference_pow(pre_pow_hash, timestamp, &matrix, nonce);
+
+        let mut worker = CpuWorker::new(0, 1, [1, 2, 3, 4]);
+        let mut out = vec![0u64; 1];
+        worker.load_block_constants(&header(pre_pow_hash, timestamp), &matrix.0, &pow.0).unwrap();
+        worker.calculate_hash(Some(&vec![nonce]), 0, 0);
+        assert_eq!(worker.copy_output_to(&mut out).unwrap(), 1);
+        assert_eq!(out[0], nonce);
+
+        let mut below = pow;
+        below.0[0] = below.0[0].wrapping_sub(1);
+        // The lowest word underflowing would borrow from the next one
+        assert_ne!(below.0[0], u64::MAX);
+        worker.load_block_constants(&header(pre_pow_hash, timestamp), &matrix.0, &below.0).unwrap();
+        worker.calculate_hash(Some(&vec![nonce]), 0, 0);
+        assert_eq!(worker.copy_output_to(&mut out).unwrap(), 0);
+        assert_eq!(out[0], 0);
+        assert_eq!(worker.hashes_computed(), 2);
+        assert_eq!(worker.stats().found, 1);
+    }
+
+    #[test]
+    fn test_cpu_worker_respects_nonce_mask() {
+        let pre_pow_hash = Hash::from_le_bytes([7; 32]);
+        let matrix = Matrix::generate(pre_pow_hash);
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        let mut out = vec![0u64; 1];
+        worker.load_block_constants(&header(pre_pow_hash, 1), &matrix.0, &[u64::MAX; 4]).unwrap();
+        worker.calculate_hash(None, 0xffff, 0xabcd_0000);
+        worker.copy_output_to(&mut out).unwrap();
+        assert_eq!(out[0] & !0xffff, 0xabcd_0000);
+    }
+
+    #[test]
+    fn test_cpu_worker_hashes_range() {
+        let pre_pow_hash = Hash::from_le_bytes([7; 32]);
+        let matrix = Matrix::generate(pre_pow_hash);
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        let mut out = vec![0u64; 1];
+        // Every nonce meets the maximal target, so the first one of the range is reported
+        worker.load_block_constants(&header(pre_pow_hash, 1), &matrix.0, &[u64::MAX; 4]).unwrap();
+        worker.calculate_hash_range(500, 10);
+        worker.copy_output_to(&mut out).unwrap();
+        assert_eq!(out[0], 500);
+        assert_eq!(worker.hashes_computed(), 10);
+    }
+
+    #[test]
+    fn test_cpu_worker_covered_ranges() {
+        let pre_pow_hash = Hash::from_le_bytes([7; 32]);
+        let matrix = Matrix::generate(pre_pow_hash);
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        worker.load_block_constants(&header(pre_pow_hash, 1), &matrix.0, &[0; 4]).unwrap();
+        worker.calculate_hash_range(500, 10);
+        worker.calculate_hash_range(2000, 5);
+        assert_eq!(worker.covered_ranges(), [NonceRange { start: 500, len: 10 }, NonceRange { start: 2000, len: 5 }]);
+
+        // Continuing where a range ended extends it
+        worker.calculate_hash_range(2005, 3);
+        assert_eq!(worker.covered_ranges()[1], NonceRange { start: 2000, len: 8 });
+
+        worker.load_block_constants(&header(pre_pow_hash, 2), &matrix.0, &[0; 4]).unwrap();
+        assert!(worker.covered_ranges().is_empty());
+    }
+
+    #[test]
+    fn test_cpu_worker_multiple_jobs() {
+        let job = |job_id: u64, seed: u8, target: [u64; 4]| {
+            let pre_pow_hash = Hash::from_le_bytes([seed; 32]);
+            JobConstants { header: header(pre_pow_hash, 1), matrix: Matrix::generate(pre_pow_hash).0, target, job_id }
+        };
+        let jobs = [job(7, 1, [0; 4]), job(9, 2, [u64::MAX; 4]), job(11, 3, [u64::MAX; 4])];
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        worker.load_jobs(&jobs).unwrap();
+        worker.calculate_hash_range(100, 4);
+        assert_eq!(worker.hashes_computed(), 12);
+
+        // Job 7 can't be met, the others take the first nonce of the range
+        let mut found = Vec::new();
+        assert_eq!(worker.copy_job_output_to(&jobs, &mut found).unwrap(), 2);
+        assert_eq!(found, [FoundNonce { job_id: 9, nonce: 100 }, FoundNonce { job_id: 11, nonce: 100 }]);
+        assert_eq!(worker.copy_job_output_to(&jobs, &mut found).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_multiple_jobs_drain_through_copy_output_to() {
+        let job = |job_id: u64, seed: u8| {
+            let pre_pow_hash = Hash::from_le_bytes([seed; 32]);
+            let matrix = Matrix::generate(pre_pow_hash).0;
+            JobConstants { header: header(pre_pow_hash, 1), matrix, target: [u64::MAX; 4], job_id }
+        };
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        worker.load_jobs(&[job(9, 2), job(11, 3)]).unwrap();
+        worker.calculate_hash_range(100, 1);
+        assert_eq!(worker.output_pending(), 2);
+
+        // One slot, one nonce per call and nothing lost
+        let mut out = vec![0u64; 1];
+        assert_eq!(worker.copy_output_to(&mut out).unwrap(), 1);
+        assert_eq!(out[0], 100);
+        assert_eq!(worker.output_pending(), 1);
+        assert_eq!(worker.copy_output_to(&mut out).unwrap(), 1);
+        assert_eq!(out[0], 100);
+        assert_eq!(worker.copy_output_to(&mut out).unwrap(), 0);
+        assert_eq!(out[0], 0);
+
+        worker.calculate_hash_range(200, 1);
+        let mut out = vec![0u64; 2];
+        assert_eq!(worker.copy_output_to(&mut out).unwrap(), 2);
+        assert_eq!(out, [200, 200]);
+        assert_eq!(worker.output_pending(), 0);
+    }
+
+    #[test]
+    fn test_random_nonces_count_as_progress() {
+        let pre_pow_hash = Hash::from_le_bytes([4; 32]);
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        worker.load_block_constants(&header(pre_pow_hash, 1), &Matrix::generate(pre_pow_hash).0, &[0; 4]).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let before = Instant::now();
+        worker.calculate_hash(None, u64::MAX, 0);
+        assert!(worker.last_progress() >= before);
+    }
+
+    #[test]
+    fn test_current_job_id() {
+        let pre_pow_hash = Hash::from_le_bytes([5; 32]);
+        let job = MiningJob::new(header(pre_pow_hash, 1), Matrix::generate(pre_pow_hash).0, [u64::MAX; 4], 42);
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        assert_eq!(worker.current_job_id(), None);
+        worker.load_job(&job).unwrap();
+        assert_eq!(worker.current_job_id(), Some(42));
+        // Still mines it like `load_block_constants` would
+        worker.calculate_hash(None, 0, 7);
+        let mut nonces = vec![0];
+        assert_eq!((worker.copy_output_to(&mut nonces).unwrap(), nonces[0]), (1, 7));
+
+        worker.load_block_constants(&job.header, &job.matrix, &job.target).unwrap();
+        assert_eq!(worker.current_job_id(), Some(0));
+        let jobs = [job.constants(), JobConstants { job_id: 43, ..job.constants() }];
+        worker.load_jobs(&jobs).unwrap();
+        assert_eq!(worker.current_job_id(), Some(43));
+    }
+
+    #[test]
+    fn test_events() {
+        let pre_pow_hash = Hash::from_le_bytes([5; 32]);
+        let job = MiningJob::new(header(pre_pow_hash, 1), Matrix::generate(pre_pow_hash).0, [u64::MAX; 4], 42);
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        let sink = EventSink::new();
+        let events = sink.subscribe();
+        worker.set_event_sink(sink);
+        worker.load_job(&job).unwrap();
+        worker.calculate_hash(None, 0, 7);
+        let expected = [
+            MinerEvent::JobApplied { worker: "CPU #0".into(), job_id: 42 },
+            MinerEvent::NonceFound { worker: "CPU #0".into(), nonce: 7, job_id: 42 },
+        ];
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_rng_seed() {
+        let pre_pow_hash = Hash::from_le_bytes([9; 32]);
+        let matrix = Matrix::generate(pre_pow_hash);
+        let first_batch = |initial: [u64; 4], seed: u64| {
+            let mut worker = CpuWorker::new(0, 16, initial);
+            worker.set_rng_seed(seed);
+            // Every nonce meets the target, the first drawn is the one reported
+            worker.load_block_constants(&header(pre_pow_hash, 1), &matrix.0, &[u64::MAX; 4]).unwrap();
+            worker.calculate_hash(None, u64::MAX, 0);
+            let mut out = vec![0u64; 1];
+            assert_eq!(worker.copy_output_to(&mut out).unwrap(), 1);
+            (out[0], worker.rng_state().unwrap())
+        };
+        // The seed replaces whatever the workers started with
+        assert_eq!(first_batch([1, 2, 3, 4], 42), first_batch([5, 6, 7, 8], 42));
+        assert_ne!(first_batch([1, 2, 3, 4], 42), first_batch([1, 2, 3, 4], 43));
+    }
+
+    #[test]
+    fn test_cpu_worker_rejects_singular_matrix() {
+        let mut worker = CpuWorker::new(0, 16, [1, 2, 3, 4]);
+        let mut matrix = Matrix::generate(Hash::from_le_bytes([7; 32]));
+        matrix.0[1] = matrix.0[0];
+        assert!(worker.load_block_constants(&[0u8; 72], &matrix.0, &[u64::MAX; 4]).is_err());
+        // Nothing is hashed against the rejected constants
+        worker.calculate_hash_range(0, 10);
+        assert_eq!(worker.hashes_computed(), 0);
+    }
+
+    #[test]
+    fn test_cpu_plugin_specs() {
+        let plugin = CpuPlugin::new(2, 64);
+        let specs = plugin.get_worker_specs();
+        assert!(plugin.enabled());
+        assert_eq!(specs.iter().map(|spec| spec.id()).collect::<Vec<_>>(), vec!["CPU #0", "CPU #1"]);
+        assert_eq!(specs[0].build().unwrap().get_workload(), 64);
+        assert_eq!(specs[0].build().unwrap().source_plugin(), plugin.name());
+    }
+
+    #[test]
+    fn test_self_check() {
+        let mut worker = CpuWorker::new(0, 4, [1, 2, 3, 4]);
+        worker.self_check().unwrap();
+    }
+
+    #[test]
+    fn test_uuid() {
+        let specs = CpuPlugin::new(2, 64).get_worker_specs();
+        let first = specs[0].build().unwrap();
+        let rebuilt = specs[0].build().unwrap();
+        let other = specs[1].build().unwrap();
+        assert_ne!(first.uuid(), rebuilt.uuid());
+        assert_ne!(first.uuid(), other.uuid());
+        assert_eq!(first.uuid(), first.uuid());
+        // Same plugin hash, the device id in the next 32 bits
+        assert_eq!(first.uuid() >> 96, other.uuid() >> 96);
+        assert_eq!(((first.uuid() >> 64) as u32, (other.uuid() >> 64) as u32), (0, 1));
+        assert_eq!(first.id(), rebuilt.id());
+    }
+
+    #[test]
+    fn test_debug_hash() {
+        let pre_pow_hash = Hash::from_le_bytes([42; 32]);
+        let timestamp = 5435345234;
+        let matrix = Matrix::generate(pre_pow_hash);
+        let hash = debug_hash(&header(pre_pow_hash, timestamp), &matrix.0, 7);
+        let expected = [
+            109, 87, 191, 129, 234, 26, 229, 164, 244, 59, 34, 226, 214, 221, 33, 3, 57, 216, 52, 229, 255, 227, 221,
+            132, 198, 202, 134, 195, 184, 116, 137, 182,
+        ];
+        assert_eq!(hash, expected);
+        assert_eq!(Hash::from_le_bytes(hash), reference_pow(pre_pow_hash, timestamp, &matrix, 7));
+    }
+
+    #[test]
+    fn test_verify_nonces() {
+        let pre_pow_hash = Hash::from_le_bytes([42; 32]);
+        let timestamp = 5435345234;
+        let matrix = Matrix::generate(pre_pow_hash);
+        let candidates: Vec<u64> = (1..=8).collect();
+        let mut pows: Vec<(Hash, u64)> =
+            candidates.iter().map(|&nonce| (reference_pow(pre_pow_hash, timestamp, &matrix, nonce), nonce)).collect();
+        pows.sort();
+        // Only the four smallest hashes meet a target equal to the fourth one
+        let target = pows[3].0;
+        let mut expected: Vec<u64> = pows[..4].iter().map(|&(_, nonce)| nonce).collect();
+        expected.sort();
+        assert_eq!(verify_nonces(&header(pre_pow_hash, timestamp), &matrix.0, &target.0, &candidates), expected);
+    }
+}
@@ -1,10 +1,10 @@
-use crate::Hash;
+use crate::cpu::Hash;
 use blake2b_simd::State as Blake2bState;
 
 const BLOCK_HASH_DOMAIN: &[u8] = b"BlockHash";
 
 #[derive(Clone, Copy)]
-pub(super) struct PowHasher([u64; 25]);
+pub struct PowHasher([u64; 25]);
 
 #[derive(Clone, Copy)]
 pub(super) struct HeavyHasher;
@@ -25,7 +25,7 @@ impl PowHasher {
         9928834659948351306, 5237849264682708699, 12825353012139217522, 6706187291358897596, 196324915476054915,
     ];
     #[inline(always)]
-    pub(super) fn new(pre_pow_hash: Hash, timestamp: u64) -> Self {
+    pub fn new(pre_pow_hash: Hash, timestamp: u64) -> Self {
         let mut start = Self::INITIAL_STATE;
         for (&pre_pow_word, state_word) in pre_pow_hash.0.iter().zip(start.iter_mut()) {
             *state_word ^= pre_pow_word;
@@ -35,7 +35,7 @@ impl PowHasher {
     }
 
     #[inline(always)]
-    pub(super) fn finalize_with_nonce(mut self, nonce: u64) -> Hash {
+    pub fn finalize_with_nonce(mut self, nonce: u64) -> Hash {
         self.0[9] ^= nonce;
         super::keccak::f1600(&mut self.0);
         Hash::new(self.0[..4].try_into().unwrap())
@@ -81,6 +81,12 @@ impl HeaderHasher {
     }
 }
 
+impl Default for HeaderHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub trait Hasher {
     fn update<A: AsRef<[u8]>>(&mut self, data: A) -> &mut Self;
 }
@@ -94,8 +100,8 @@ impl Hasher for HeaderHasher {
 
 #[cfg(test)]
 mod tests {
-    use crate::pow::hasher::{HeavyHasher, PowHasher};
-    use crate::Hash;
+    use crate::cpu::hasher::{HeavyHasher, PowHasher};
+    use crate::cpu::Hash;
     use sha3::digest::{ExtendableOutput, Update, XofReader};
     use sha3::CShake256;
 
@@ -138,7 +144,7 @@ mod benches {
 
     use self::test::{black_box, Bencher};
     use super::{HeavyHasher, PowHasher};
-    use crate::Hash;
+    use crate::cpu::Hash;
 
     #[bench]
     pub fn bench_pow_hash(bh: &mut Bencher) {
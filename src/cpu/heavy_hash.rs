@@ -1,5 +1,5 @@
-use crate::pow::{hasher::HeavyHasher, xoshiro::XoShiRo256PlusPlus};
-use crate::Hash;
+use crate::cpu::Hash;
+use crate::cpu::{hasher::HeavyHasher, xoshiro::XoShiRo256PlusPlus};
 use std::mem::MaybeUninit;
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
@@ -140,9 +140,9 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::pow::heavy_hash::Matrix;
-    use crate::pow::xoshiro::XoShiRo256PlusPlus;
-    use crate::Hash;
+    use crate::cpu::heavy_hash::Matrix;
+    use crate::cpu::xoshiro::XoShiRo256PlusPlus;
+    use crate::cpu::Hash;
 
     #[test]
     fn test_compute_rank() {
@@ -321,7 +321,7 @@ mod benches {
 
     use self::test::{black_box, Bencher};
     use super::{Matrix, XoShiRo256PlusPlus};
-    use crate::Hash;
+    use crate::cpu::Hash;
     use rand::{thread_rng, Rng};
 
     #[bench]
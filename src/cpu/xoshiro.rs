@@ -1,4 +1,4 @@
-use crate::Hash;
+use crate::cpu::Hash;
 use std::num::Wrapping;
 
 pub(super) struct XoShiRo256PlusPlus {
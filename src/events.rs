@@ -0,0 +1,55 @@
+//! What happened on the workers, pushed as it happens rather than polled from `Worker::stats`.
+//!
+//! Backends get an `EventSink` through `Worker::set_event_sink` and emit on it; every receiver from
+//! `EventSink::subscribe` (or `pool::WorkerPool::events`) gets its own copy of each event.
+use crate::{Worker, WorkerError};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MinerEvent {
+    NonceFound { worker: String, nonce: u64, job_id: u64 },
+    DeviceError { worker: String, err: String },
+    JobApplied { worker: String, job_id: u64 },
+}
+
+/// Broadcasts events to the current subscribers. Cheap to clone, every clone feeds the same subscribers.
+#[derive(Clone, Default)]
+pub struct EventSink {
+    subscribers: Arc<Mutex<Vec<Sender<MinerEvent>>>>,
+}
+
+impl EventSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receives every event emitted from now on
+    pub fn subscribe(&self) -> Receiver<MinerEvent> {
+        let (tx, rx) = channel();
+        self.lock().push(tx);
+        rx
+    }
+
+    /// Never blocks; subscribers that dropped their receiver are forgotten
+    pub fn emit(&self, event: MinerEvent) {
+        self.lock().retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Sender<MinerEvent>>> {
+        // The list is only pushed to and filtered, a panic can't leave it half updated
+        self.subscribers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Emits `MinerEvent::DeviceError` for `err` on the worker's sink, if it has one
+pub fn report_device_error<W: Worker + ?Sized>(worker: &W, err: &WorkerError) {
+    if let Some(sink) = worker.event_sink() {
+        sink.emit(MinerEvent::DeviceError { worker: worker.id(), err: err.to_string() });
+    }
+}
+
+/// `Worker::sync`, reporting a failure on the worker's sink before handing it back
+pub(crate) fn reported_sync<W: Worker + ?Sized>(worker: &W) -> Result<(), WorkerError> {
+    worker.sync().inspect_err(|e| report_device_error(worker, e))
+}
@@ -0,0 +1,254 @@
+//! Mining on a primary pool with backups to fall back to.
+//!
+//! `PoolFailover` holds one `StratumClient` at a time and moves down the list of endpoints when the current
+//! pool can't be reconnected to or rejects too many shares. While on a backup it periodically probes the
+//! primary and only returns once the primary was reachable for a whole cooldown, so a flaky primary doesn't
+//! make the miner bounce between pools.
+use crate::stratum::{ReconnectPolicy, ShareCounts, StratumClient, StratumJob};
+use crate::Error;
+use std::time::{Duration, Instant};
+
+pub struct PoolFailover {
+    endpoints: Vec<String>,
+    user: String,
+    active: usize,
+    client: StratumClient,
+    reconnect: ReconnectPolicy,
+    max_reject_rate: f64,
+    min_shares: u64,
+    primary_retry: Duration,
+    last_probe: Instant,
+    probe: PrimaryProbe,
+    // Shares of the clients dropped on the way
+    retired: ShareCounts,
+}
+
+impl PoolFailover {
+    /// Connects to the first reachable endpoint of `endpoints`, given in priority order like `StratumClient::connect`.
+    /// `reconnect` is how hard each pool is retried before moving on to the next; its `max_attempts` should be
+    /// bounded, with `None` the failover never happens on disconnects.
+    pub async fn connect(endpoints: &[&str], user: &str, reconnect: ReconnectPolicy) -> Result<Self, Error> {
+        let mut last_error = None;
+        for (active, endpoint) in endpoints.iter().enumerate() {
+            match StratumClient::connect(endpoint, user, reconnect).await {
+                Ok(client) => {
+                    return Ok(Self {
+                        endpoints: endpoints.iter().map(|endpoint| endpoint.to_string()).collect(),
+                        user: user.to_string(),
+                        active,
+                        client,
+                        reconnect,
+                        max_reject_rate: 0.5,
+                        min_shares: 20,
+                        primary_retry: Duration::from_secs(30),
+                        last_probe: Instant::now(),
+                        probe: PrimaryProbe::new(Duration::from_secs(120)),
+                        retired: ShareCounts::default(),
+                    })
+                }
+                Err(e) => {
+                    log_warn!("Connecting to pool {} failed: {}", endpoint, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| "no pool endpoints given".into()))
+    }
+
+    /// Moves on once more than `rate` of the shares sent to the current pool were rejected, judged after `min_shares`
+    pub fn with_max_reject_rate(mut self, rate: f64, min_shares: u64) -> Self {
+        self.max_reject_rate = rate;
+        self.min_shares = min_shares;
+        self
+    }
+
+    /// While on a backup, probes the primary every `every` and returns to it once it was reachable for `cooldown`
+    pub fn with_primary_retry(mut self, every: Duration, cooldown: Duration) -> Self {
+        self.primary_retry = every;
+        self.probe = PrimaryProbe::new(cooldown);
+        self
+    }
+
+    /// The endpoint jobs currently come from
+    pub fn active_endpoint(&self) -> &str {
+        &self.endpoints[self.active]
+    }
+
+    /// Like `StratumClient::next_job`, switching pools as needed. Fails only if no endpoint can be reached.
+    pub async fn next_job(&mut self) -> Result<StratumJob, Error> {
+        loop {
+            self.probe_primary().await;
+            match self.client.next_job().await {
+                Ok(job) if !self.rejecting() => return Ok(job),
+                Ok(_) => {
+                    log_warn!("Pool {} rejects too many shares, switching pools", self.active_endpoint());
+                    self.switch_from_active().await?;
+                }
+                Err(e) => {
+                    log_warn!("Lost pool {}: {}", self.active_endpoint(), e);
+                    self.switch_from_active().await?;
+                }
+            }
+        }
+    }
+
+    pub async fn submit(&mut self, nonce: u64) -> Result<(), Error> {
+        self.client.submit(nonce).await
+    }
+
+    pub fn is_current(&self, job_id: &str) -> bool {
+        self.client.is_current(job_id)
+    }
+
+    /// Shares over every pool mined on so far
+    pub fn shares(&self) -> ShareCounts {
+        let current = self.client.shares();
+        ShareCounts {
+            accepted: self.retired.accepted + current.accepted,
+            stale: self.retired.stale + current.stale,
+            rejected: self.retired.rejected + current.rejected,
+        }
+    }
+
+    fn rejecting(&self) -> bool {
+        let shares = self.client.shares();
+        let total = shares.accepted + shares.stale + shares.rejected;
+        total >= self.min_shares.max(1) && shares.rejected as f64 / total as f64 > self.max_reject_rate
+    }
+
+    /// Connects to the endpoints after the active one in order, wrapping around and trying the active one last
+    async fn switch_from_active(&mut self) -> Result<(), Error> {
+        let count = self.endpoints.len();
+        for next in (1..=count).map(|offset| (self.active + offset) % count) {
+            match StratumClient::connect(&self.endpoints[next], &self.user, self.reconnect).await {
+                Ok(client) => {
+                    self.replace_client(next, client);
+                    return Ok(());
+                }
+                Err(e) => log_warn!("Connecting to pool {} failed: {}", self.endpoints[next], e),
+            }
+        }
+        Err(format!("none of the {} pools could be reached", count).into())
+    }
+
+    async fn probe_primary(&mut self) {
+        if self.active == 0 || self.last_probe.elapsed() < self.primary_retry {
+            return;
+        }
+        self.last_probe = Instant::now();
+        let client = StratumClient::connect(&self.endpoints[0], &self.user, self.reconnect).await.ok();
+        if self.probe.record(Instant::now(), client.is_some()) {
+            self.replace_client(0, client.unwrap());
+        }
+    }
+
+    fn replace_client(&mut self, active: usize, client: StratumClient) {
+        let old = std::mem::replace(&mut self.client, client).shares();
+        self.retired.accepted += old.accepted;
+        self.retired.stale += old.stale;
+        self.retired.rejected += old.rejected;
+        self.active = active;
+        self.probe.reset();
+        log_info!("Mining on pool {}", self.active_endpoint());
+    }
+}
+
+/// Whether the primary stayed reachable for a whole cooldown, judged from the probes' results
+struct PrimaryProbe {
+    cooldown: Duration,
+    up_since: Option<Instant>,
+}
+
+impl PrimaryProbe {
+    fn new(cooldown: Duration) -> Self {
+        Self { cooldown, up_since: None }
+    }
+
+    /// Records a probe made at `now`, returns whether it's time to switch back
+    fn record(&mut self, now: Instant, reachable: bool) -> bool {
+        if !reachable {
+            self.up_since = None;
+            return false;
+        }
+        let up_since = *self.up_since.get_or_insert(now);
+        now.duration_since(up_since) >= self.cooldown
+    }
+
+    fn reset(&mut self) {
+        self.up_since = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::failover::{PoolFailover, PrimaryProbe};
+    use crate::stratum::mock_pool::{accept, handshake, recv, send};
+    use crate::stratum::{ReconnectPolicy, ShareCounts};
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_fails_over_to_backup() {
+        let primary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backup = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_address = primary.local_addr().unwrap().to_string();
+        let backup_address = backup.local_addr().unwrap().to_string();
+        let (primary_tx, primary_rx) = tokio::sync::oneshot::channel();
+        let primary = tokio::spawn(async move {
+            let mut pool = accept(&primary).await;
+            handshake(&mut pool, "karlsen:miner").await;
+            primary_rx.await.unwrap();
+            // Goes down for good
+            drop(primary);
+        });
+        let backup = tokio::spawn(async move {
+            let mut pool = accept(&backup).await;
+            handshake(&mut pool, "karlsen:miner").await;
+            send(&mut pool, json!({"id": null, "method": "mining.notify", "params": ["b1", [1, 2, 3, 4], 5]})).await;
+            let submit = recv(&mut pool).await;
+            assert_eq!(submit["params"][1], "b1");
+            send(&mut pool, json!({"id": submit["id"], "result": true, "error": null})).await;
+            send(&mut pool, json!({"id": null, "method": "mining.notify", "params": ["b2", [1, 2, 3, 4], 6]})).await;
+            pool
+        });
+
+        let policy = ReconnectPolicy {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+            multiplier: 1.0,
+            max_attempts: Some(2),
+        };
+        let mut failover =
+            PoolFailover::connect(&[&primary_address, &backup_address], "karlsen:miner", policy).await.unwrap();
+        assert_eq!(failover.active_endpoint(), primary_address);
+        primary_tx.send(()).unwrap();
+        primary.await.unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let job = tokio::time::timeout(timeout, failover.next_job()).await.unwrap().unwrap();
+        assert_eq!(job.id, "b1");
+        assert_eq!(failover.active_endpoint(), backup_address);
+        failover.submit(7).await.unwrap();
+        // The mining loop carries on with the backup's jobs
+        let job = tokio::time::timeout(timeout, failover.next_job()).await.unwrap().unwrap();
+        assert_eq!(job.id, "b2");
+        assert!(failover.is_current("b2"));
+        assert_eq!(failover.shares(), ShareCounts { accepted: 1, stale: 0, rejected: 0 });
+        drop(backup.await.unwrap());
+    }
+
+    #[test]
+    fn test_primary_cooldown() {
+        let mut probe = PrimaryProbe::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(!probe.record(at(0), true));
+        assert!(!probe.record(at(30), true));
+        // A failed probe restarts the cooldown
+        assert!(!probe.record(at(50), false));
+        assert!(!probe.record(at(70), true));
+        assert!(!probe.record(at(100), true));
+        assert!(probe.record(at(130), true));
+    }
+}
@@ -0,0 +1,176 @@
+//! Layout of the data workers hash.
+//!
+//! The 72 byte header handed to `Worker::load_block_constants` is PRE_POW_HASH || TIME || 32 zero byte padding.
+//! It has no room for the nonce: kernels append it as 8 little-endian bytes, hashing an 80 byte input
+//! PRE_POW_HASH || TIME || 32 zero byte padding || NONCE.
+
+use crate::cpu::heavy_hash::Matrix;
+use crate::cpu::Hash;
+use crate::Error;
+
+pub const HEADER_SIZE: usize = 72;
+/// Byte offset of the nonce within the pow input, right after the header
+pub const NONCE_OFFSET: usize = HEADER_SIZE;
+pub const POW_INPUT_SIZE: usize = NONCE_OFFSET + 8;
+
+/// The header in `bytes`, e.g. as received from the network, failing unless there are exactly `HEADER_SIZE`
+pub fn header_from_slice(bytes: &[u8]) -> Result<[u8; HEADER_SIZE], Error> {
+    Ok(bytes.try_into().map_err(|_| format!("the header is {} bytes, not {}", bytes.len(), HEADER_SIZE))?)
+}
+
+/// The matrix for a block, generated from its pre-pow hash.
+///
+/// Per the spec the hash words seed xoshiro256++ (not the xoshiro256** used for nonces), which fills the matrix
+/// with 4 bit values, sixteen per output; matrices failing `is_matrix_valid` are discarded, and the next one is
+/// drawn from the same generator.
+pub fn generate_matrix(hash: &[u8; 32]) -> [[u16; 64]; 64] {
+    Matrix::generate(Hash::from_le_bytes(*hash)).0
+}
+
+/// Whether `matrix` has full rank, the check the spec runs on every generated matrix. The rank is computed by
+/// Gaussian elimination over the reals, like consensus does, not over a finite field. Hosts handed matrices by
+/// a pool or tests should reject those that fail it, heavy hashes of them never match the node's.
+pub fn is_matrix_valid(matrix: &[[u16; 64]; 64]) -> bool {
+    Matrix(*matrix).compute_rank() == 64
+}
+
+/// Folds the whole header into a seed for `Xoshiro256StarStar::from_seed_u64`, so a worker's nonce stream is as
+/// reproducible from the job as its matrix is, which helps when comparing runs. This is a mixing function (the
+/// MurmurHash3 finalizer over each little-endian word), not a cryptographic hash: don't use it where seeds
+/// must be unpredictable.
+pub fn seed_from_header(header: &[u8; HEADER_SIZE]) -> u64 {
+    fn fmix64(mut x: u64) -> u64 {
+        x = (x ^ (x >> 33)).wrapping_mul(0xff51afd7ed558ccd);
+        x = (x ^ (x >> 33)).wrapping_mul(0xc4ceb9fe1a85ec53);
+        x ^ (x >> 33)
+    }
+    header.chunks_exact(8).fold(HEADER_SIZE as u64, |seed, word| {
+        fmix64(seed.rotate_left(29) ^ u64::from_le_bytes(word.try_into().unwrap()))
+    })
+}
+
+/// The header followed by `nonce`, exactly as the kernels hash it
+pub fn pow_input(header: &[u8; HEADER_SIZE], nonce: u64) -> [u8; POW_INPUT_SIZE] {
+    let mut input = [0u8; POW_INPUT_SIZE];
+    input[..HEADER_SIZE].copy_from_slice(header);
+    inject_nonce(&mut input, nonce, true);
+    input
+}
+
+/// Writes `nonce` little-endian if `le`, big-endian otherwise. Consensus hashes it little-endian, big-endian only
+/// suits pools expecting the nonce bytes of a submission reversed (see `Worker::set_nonce_endianness`).
+pub fn inject_nonce(input: &mut [u8; POW_INPUT_SIZE], nonce: u64, le: bool) {
+    let bytes = match le {
+        true => nonce.to_le_bytes(),
+        false => nonce.to_be_bytes(),
+    };
+    input[NONCE_OFFSET..].copy_from_slice(&bytes);
+}
+
+/// Reads back what `inject_nonce` wrote with the same `le`
+pub fn read_nonce(input: &[u8; POW_INPUT_SIZE], le: bool) -> u64 {
+    let bytes = input[NONCE_OFFSET..].try_into().unwrap();
+    match le {
+        true => u64::from_le_bytes(bytes),
+        false => u64::from_be_bytes(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header::{
+        generate_matrix, header_from_slice, inject_nonce, is_matrix_valid, pow_input, read_nonce, seed_from_header,
+        HEADER_SIZE, NONCE_OFFSET, POW_INPUT_SIZE,
+    };
+    use crate::xoshiro256starstar::Xoshiro256StarStar;
+
+    #[test]
+    fn test_nonce_round_trip() {
+        let header = [0xa5u8; HEADER_SIZE];
+        let mut input = pow_input(&header, 0);
+        for nonce in [0, 1, 0x0102030405060708, u64::MAX] {
+            for le in [true, false] {
+                inject_nonce(&mut input, nonce, le);
+                assert_eq!(read_nonce(&input, le), nonce);
+                assert_eq!(input[..HEADER_SIZE], header);
+            }
+        }
+        assert_eq!(pow_input(&header, 0x0102030405060708)[NONCE_OFFSET..], [8, 7, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_big_endian_nonce() {
+        let header = [0u8; HEADER_SIZE];
+        let mut big = pow_input(&header, 0);
+        inject_nonce(&mut big, 0x0102030405060708, false);
+        assert_eq!(big[NONCE_OFFSET..], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_ne!(big, pow_input(&header, 0x0102030405060708));
+        // Reading with the other convention swaps the bytes
+        assert_eq!(read_nonce(&big, true), 0x0807060504030201);
+    }
+
+    #[test]
+    fn test_header_from_slice() {
+        let bytes: Vec<u8> = (0..HEADER_SIZE as u8).collect();
+        assert_eq!(header_from_slice(&bytes).unwrap()[..], bytes[..]);
+        for len in [0, 40, HEADER_SIZE - 1, HEADER_SIZE + 1, POW_INPUT_SIZE] {
+            let e = header_from_slice(&vec![0; len]).unwrap_err().to_string();
+            assert_eq!(e, format!("the header is {} bytes, not 72", len));
+        }
+    }
+
+    #[test]
+    fn test_seed_from_header() {
+        let mut header = [0u8; HEADER_SIZE];
+        header[..32].copy_from_slice(&[42; 32]);
+        let seed = seed_from_header(&header);
+        assert_eq!(seed_from_header(&header), seed);
+        let mut rng = Xoshiro256StarStar::from_seed_u64(seed);
+        let mut again = Xoshiro256StarStar::from_seed_u64(seed_from_header(&header));
+        assert_eq!(rng.next_u64(), again.next_u64());
+
+        // Every byte counts, including the timestamp and the padding
+        for i in 0..HEADER_SIZE {
+            let mut other = header;
+            other[i] ^= 1;
+            assert_ne!(seed_from_header(&other), seed, "byte {}", i);
+        }
+        assert_ne!(seed_from_header(&[0; HEADER_SIZE]), seed_from_header(&[1; HEADER_SIZE]));
+    }
+
+    #[test]
+    fn test_generate_matrix() {
+        #[rustfmt::skip]
+        let first_row = [
+            4, 5, 4, 5, 4, 5, 4, 5, 4, 5, 4, 5, 4, 5, 4, 5, 15, 3, 15, 3, 15, 3, 15, 3, 15, 3, 15, 3, 15, 3, 15, 3,
+            2, 10, 2, 10, 2, 10, 2, 10, 2, 10, 2, 10, 2, 10, 2, 10, 14, 1, 2, 2, 14, 10, 4, 12, 4, 12, 10, 10, 10, 10, 10, 10,
+        ];
+        #[rustfmt::skip]
+        let last_row = [
+            10, 5, 11, 14, 12, 1, 12, 7, 12, 8, 10, 5, 6, 10, 0, 7, 5, 6, 11, 11, 13, 12, 0, 13, 0, 6, 11, 0, 14, 4, 2, 1,
+            12, 7, 1, 10, 7, 15, 5, 3, 14, 15, 1, 3, 1, 2, 10, 4, 11, 8, 2, 11, 2, 5, 5, 4, 15, 5, 10, 3, 1, 7, 2, 14,
+        ];
+        let matrix = generate_matrix(&[42; 32]);
+        assert_eq!(matrix[0], first_row);
+        assert_eq!(matrix[63], last_row);
+        assert_ne!(generate_matrix(&[43; 32]), matrix);
+    }
+
+    #[test]
+    fn test_is_matrix_valid() {
+        let matrix = generate_matrix(&[42; 32]);
+        assert!(is_matrix_valid(&matrix));
+        let mut identity = [[0u16; 64]; 64];
+        (0..64).for_each(|i| identity[i][i] = 1);
+        assert!(is_matrix_valid(&identity));
+
+        let mut degenerate = matrix;
+        degenerate[17] = [0; 64];
+        assert!(!is_matrix_valid(&degenerate));
+        // A repeated row is just as singular
+        let mut degenerate = matrix;
+        degenerate[1] = degenerate[0];
+        assert!(!is_matrix_valid(&degenerate));
+        assert!(!is_matrix_valid(&[[0; 64]; 64]));
+    }
+}
@@ -1,16 +1,533 @@
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
+
 use clap::ArgMatches;
+use sha2::{Digest, Sha256};
 use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error as StdError;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[macro_use]
+mod logging;
 
+#[cfg(feature = "async")]
+pub mod async_worker;
+pub mod benchmark;
+// `src/cli.rs` holds the binary's own arguments
+#[path = "lib_cli.rs"]
+pub mod cli;
+pub mod cpu;
+pub mod events;
+pub mod failover;
+pub mod header;
+pub mod metrics;
+pub mod pool;
+pub mod power;
+pub mod profile;
+pub mod replay;
+pub mod scheduler;
+pub mod session;
+pub mod solo;
+pub mod stratum;
+pub mod submission;
+pub mod target;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod throttle;
+pub mod topology;
 pub mod xoshiro256starstar;
 use libloading::{Library, Symbol};
 
 pub type Error = Box<dyn StdError + Send + Sync + 'static>;
 
+/// Bump whenever the `Plugin`/`WorkerSpec`/`Worker` traits or the `_plugin_create` signature change.
+pub const PLUGIN_ABI: u32 = 52;
+/// The value plugins export as `_plugin_abi_version`, the loader refuses plugins that disagree with it.
+pub const PLUGIN_ABI_VERSION: u32 = abi_version(parse_u32(env!("CARGO_PKG_VERSION_MAJOR")), PLUGIN_ABI);
+
+pub const fn abi_version(crate_major: u32, abi: u32) -> u32 {
+    (crate_major << 16) | abi
+}
+
+const fn parse_u32(s: &str) -> u32 {
+    let bytes = s.as_bytes();
+    let mut res = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        res = res * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    res
+}
+
+fn check_abi_version(path: &str, found: Option<u32>, expected: u32) -> Result<(), KarlsenMinerError> {
+    match found {
+        Some(found) if found == expected => Ok(()),
+        found => Err(KarlsenMinerError::AbiMismatch { path: path.to_string(), expected, found }),
+    }
+}
+
+/// Failure reported by `Worker::sync`, letting the host tell a dead device apart from a bad launch
+#[derive(Debug)]
+pub enum WorkerError {
+    DeviceLost(String),
+    KernelFailure(String),
+    Timeout(String),
+    Other(Error),
+}
+
+impl fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkerError::DeviceLost(msg) => write!(f, "device lost: {}", msg),
+            WorkerError::KernelFailure(msg) => write!(f, "kernel failure: {}", msg),
+            WorkerError::Timeout(msg) => write!(f, "timeout: {}", msg),
+            WorkerError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for WorkerError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            WorkerError::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl WorkerError {
+    /// Kernel failures and timeouts often go away on the next launch, a lost device or an unknown error won't
+    pub fn is_transient(&self) -> bool {
+        matches!(self, WorkerError::KernelFailure(_) | WorkerError::Timeout(_))
+    }
+}
+
+impl From<Error> for WorkerError {
+    fn from(e: Error) -> Self {
+        WorkerError::Other(e)
+    }
+}
+
+/// Errors raised by the plugin host, split by cause so callers can decide what is worth retrying.
+/// Boxes into `Error` with `?` like any other error, and can be recovered from it with `downcast_ref`.
+#[derive(Debug)]
+pub enum KarlsenMinerError {
+    PluginLoad {
+        path: String,
+        source: Error,
+    },
+    SymbolMissing {
+        path: String,
+        symbol: &'static str,
+    },
+    /// The library's SHA-256 differs from the pinned one passed to `load_single_plugin_verified`
+    ChecksumMismatch {
+        path: String,
+        expected: String,
+        found: String,
+    },
+    /// `found` is `None` for plugins predating `_plugin_abi_version`
+    AbiMismatch {
+        path: String,
+        expected: u32,
+        found: Option<u32>,
+    },
+    OptionProcessing {
+        plugin: &'static str,
+        source: Error,
+    },
+    /// Several plugins failed processing their options, each with why
+    OptionErrors(Vec<(String, Error)>),
+    /// Plugin code panicked while called by the manager, the panic was caught before reaching the host
+    PluginPanicked {
+        plugin: &'static str,
+    },
+    /// Workers were requested from a manager without any plugin
+    NoPlugins,
+    /// The device found more nonces than `Worker::max_output_nonces`, the workload should be reduced
+    OutputOverflow {
+        found: usize,
+        capacity: usize,
+    },
+    /// Every enabled plugin whose `Plugin::validate` rejected its configuration
+    InvalidConfiguration(Vec<(&'static str, Error)>),
+    Device(WorkerError),
+}
+
+impl fmt::Display for KarlsenMinerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KarlsenMinerError::PluginLoad { path, source } => {
+                write!(f, "failed to load plugin at {}: {}", path, source)
+            }
+            KarlsenMinerError::SymbolMissing { path, symbol } => {
+                write!(f, "plugin at {} does not export `{}`", path, symbol)
+            }
+            KarlsenMinerError::ChecksumMismatch { path, expected, found } => {
+                write!(f, "plugin at {} has SHA-256 {}, but {} was expected", path, found, expected)
+            }
+            KarlsenMinerError::AbiMismatch { path, expected, found: Some(found) } => write!(
+                f,
+                "plugin at {} was built for ABI version {:#x}, but this miner expects {:#x}",
+                path, found, expected
+            ),
+            KarlsenMinerError::AbiMismatch { path, found: None, .. } => {
+                write!(f, "plugin at {} is too old: it does not export `_plugin_abi_version`", path)
+            }
+            KarlsenMinerError::OptionProcessing { plugin, source } => {
+                write!(f, "failed processing options for {}: {}", plugin, source)
+            }
+            KarlsenMinerError::OptionErrors(errors) => {
+                write!(f, "failed processing options")?;
+                for (i, (plugin, e)) in errors.iter().enumerate() {
+                    write!(f, "{} {}: {}", if i == 0 { ":" } else { ";" }, plugin, e)?;
+                }
+                Ok(())
+            }
+            KarlsenMinerError::PluginPanicked { plugin } => write!(f, "plugin {} panicked", plugin),
+            KarlsenMinerError::NoPlugins => write!(f, "no mining plugins loaded; specify --gpu-plugin"),
+            KarlsenMinerError::OutputOverflow { found, capacity } => {
+                write!(f, "found {} nonces but the output holds only {}, reduce the workload", found, capacity)
+            }
+            KarlsenMinerError::InvalidConfiguration(errors) => {
+                write!(f, "invalid configuration")?;
+                for (i, (plugin, e)) in errors.iter().enumerate() {
+                    write!(f, "{} {}: {}", if i == 0 { ":" } else { ";" }, plugin, e)?;
+                }
+                Ok(())
+            }
+            KarlsenMinerError::Device(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for KarlsenMinerError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            KarlsenMinerError::PluginLoad { source, .. } | KarlsenMinerError::OptionProcessing { source, .. } => {
+                Some(source.as_ref())
+            }
+            KarlsenMinerError::Device(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Runs plugin code, turning a panic into `PluginPanicked` rather than letting it unwind through the host.
+///
+/// `catch_unwind` wants an `UnwindSafe` closure, which anything capturing `&mut dyn Plugin` (or `&dyn Plugin`,
+/// as trait objects may have interior mutability) is not. It is asserted instead: a plugin that panicked may be
+/// left half updated, so callers drop whatever it was producing and don't trust its state afterwards.
+/// Plugins built with `panic = "abort"` still take the whole process down.
+fn catch_plugin_panic<T>(plugin: &'static str, f: impl FnOnce() -> T) -> Result<T, KarlsenMinerError> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|_| KarlsenMinerError::PluginPanicked { plugin })
+}
+
+impl From<WorkerError> for KarlsenMinerError {
+    fn from(e: WorkerError) -> Self {
+        KarlsenMinerError::Device(e)
+    }
+}
+
+/// A contiguous slice of the nonce space, `start..start + len`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NonceRange {
+    pub start: u64,
+    pub len: u64,
+}
+
+impl NonceRange {
+    pub fn end(&self) -> u64 {
+        self.start + self.len
+    }
+
+    pub fn contains(&self, nonce: u64) -> bool {
+        nonce >= self.start && nonce - self.start < self.len
+    }
+}
+
+/// Splits `0..u64::MAX` evenly between `total_workers`, the last partition also takes the remainder.
+/// Panics if `worker_index` is not below `total_workers`.
+pub fn partition(total_workers: usize, worker_index: usize) -> NonceRange {
+    assert!(worker_index < total_workers, "worker {} out of {} workers", worker_index, total_workers);
+    let chunk = u64::MAX / total_workers as u64;
+    let start = chunk * worker_index as u64;
+    let len = match worker_index + 1 == total_workers {
+        true => u64::MAX - start,
+        false => chunk,
+    };
+    NonceRange { start, len }
+}
+
+/// Splits `0..u64::MAX` between as many workers as there are `weights`, each getting a share proportional to
+/// its weight. All-zero weights split evenly. The last partition also takes the rounding remainder.
+pub fn partition_weighted(weights: &[u64]) -> Vec<NonceRange> {
+    let all_zero = weights.iter().all(|&weight| weight == 0);
+    let weight = |w: u64| match all_zero {
+        true => 1u128,
+        false => w as u128,
+    };
+    let total: u128 = weights.iter().map(|&w| weight(w)).sum();
+    let mut cumulative = 0u128;
+    let mut start = 0u64;
+    weights
+        .iter()
+        .enumerate()
+        .map(|(i, &w)| {
+            cumulative += weight(w);
+            let end = match i + 1 == weights.len() {
+                true => u64::MAX,
+                false => (u64::MAX as u128 * cumulative / total) as u64,
+            };
+            let range = NonceRange { start, len: end - start };
+            start = end;
+            range
+        })
+        .collect()
+}
+
+/// Optional `Worker` features a plugin's workers implement, so the host can pick a mining loop before
+/// building any of them. Every flag defaults to `false`, which is what plugins predating it get.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PluginCapabilities {
+    /// `try_sync` polls without blocking
+    pub async_sync: bool,
+    /// `pause`/`resume` actually idle the device
+    pub pausable: bool,
+    /// `calculate_hash` honours explicit nonce lists, so the nonce space can be partitioned between workers
+    pub nonce_ranges: bool,
+    /// Found nonces are re-checked on the CPU before being reported
+    pub self_verifying: bool,
+}
+
+/// Settings for plugins that don't come from clap, such as pool credentials from the environment or a config
+/// file, handed to every plugin by `PluginManager::configure`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PluginConfig {
+    values: HashMap<String, String>,
+}
+
+impl PluginConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every environment variable starting with `prefix`, keyed by the rest of its name
+    pub fn from_env(prefix: &str) -> Self {
+        let values =
+            std::env::vars().filter_map(|(key, value)| Some((key.strip_prefix(prefix)?.to_string(), value))).collect();
+        Self { values }
+    }
+
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.values.insert(key.into(), value.into());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+}
+
+impl From<HashMap<String, String>> for PluginConfig {
+    fn from(values: HashMap<String, String>) -> Self {
+        Self { values }
+    }
+}
+
+/// What `PluginManager::build_report` built, and why the other devices failed, keyed by `WorkerSpec::device_id`
+pub struct BuildReport {
+    pub workers: Vec<Box<dyn Worker>>,
+    pub failures: Vec<(u32, Error)>,
+}
+
+/// A device as reported by a plugin, before any worker is built for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    pub plugin: &'static str,
+    pub device_id: u32,
+    pub name: String,
+    pub workload: f32,
+    /// `WorkerSpec::recommended_workload`, what `workload` would be if the user asked for nothing else
+    pub recommended_workload: f32,
+    /// Tells apart devices with the same `device_id` on different OpenCL platforms
+    pub opencl_platform: Option<u16>,
+}
+
+/// A device a backend detected, whether or not the user configured a worker for it, to help picking `--devices`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawDevice {
+    /// The index the backend's own device options take
+    pub id: u32,
+    pub name: String,
+    pub memory_bytes: u64,
+}
+
+/// `_plugin_create` as exported by `declare_plugin!`, see `construct_plugin` for who owns what. Both sides are
+/// Rust built against this crate, `extern "C"` only pins the calling convention.
+#[allow(improper_ctypes_definitions)]
+type PluginCreate<'help> =
+    unsafe extern "C" fn(*mut clap::App<'help>) -> (*mut clap::App<'help>, *mut dyn Plugin, *const Error);
+
+/// Runs a plugin's `_plugin_create`. The handoff is by value through boxes, each freed exactly once:
+/// - the host boxes `app` and gives up that box, the constructor takes it back with `Box::from_raw`;
+/// - the constructor always returns an `App` in a box of its own, which the host takes back here, whether
+///   augmenting worked or not (then it is the unmodified `App`);
+/// - on success it boxes the plugin and returns a null error, otherwise a null plugin and a boxed `Error`.
+///
+/// Neither side touches a pointer after handing it over, so pointer identity doesn't matter.
+///
+/// # Safety
+/// `constructor` must follow the contract above, which `declare_plugin!` does. The error, like the plugin, may
+/// point into the plugin's library, which must outlive both.
+unsafe fn construct_plugin<'help>(
+    constructor: PluginCreate<'help>,
+    app: clap::App<'help>,
+) -> (clap::App<'help>, Result<Box<dyn Plugin>, Error>) {
+    take_created(constructor(Box::into_raw(Box::new(app))))
+}
+
+/// Takes back what a `_plugin_create` returned, as described for `construct_plugin`
+unsafe fn take_created<'help>(
+    (app, boxed_raw, error): (*mut clap::App<'help>, *mut dyn Plugin, *const Error),
+) -> (clap::App<'help>, Result<Box<dyn Plugin>, Error>) {
+    debug_assert!(!app.is_null(), "_plugin_create returned no App");
+    let app = *Box::from_raw(app);
+    if boxed_raw.is_null() {
+        debug_assert!(!error.is_null(), "_plugin_create returned neither a plugin nor an error");
+        return (app, Err(*Box::from_raw(error as *mut Error)));
+    }
+    debug_assert!(error.is_null(), "_plugin_create returned both a plugin and an error");
+    (app, Ok(Box::from_raw(boxed_raw)))
+}
+
+/// The body of the `_plugin_create` generated by `declare_plugin!`, handing `augment` a clone of the host's
+/// `App` and the plugin's `Plugin::name`. Also run in-process by `testing::create_plugin_in_process`.
+///
+/// # Safety
+/// `app` must be a boxed `App` given up by the caller, as `construct_plugin` does.
+#[doc(hidden)]
+pub unsafe fn create_plugin<'help, P: Plugin>(
+    app: *mut clap::App<'help>,
+    constructor: fn() -> Result<P, Error>,
+    augment: impl FnOnce(clap::App<'help>, &'static str) -> clap::App<'help>,
+) -> (*mut clap::App<'help>, *mut dyn Plugin, *const Error) {
+    // A null data pointer is all the host checks, the vtable is `P`'s so the fat pointer is still valid
+    let no_plugin = std::ptr::null_mut::<P>() as *mut dyn Plugin;
+    let object = match constructor() {
+        Ok(obj) => obj,
+        Err(e) => return (app, no_plugin, Box::into_raw(Box::new(e))),
+    };
+
+    let name = object.name();
+    let boxed: Box<dyn Plugin> = Box::new(object);
+
+    // The only place the host's box is taken back, every path below hands out a fresh one. Arguments are
+    // added to a clone so a panicking `augment_args` can't unwind into the host and lose the `App`.
+    let original = *Box::from_raw(app);
+    let augmented = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| augment(original.clone(), name)));
+    match augmented {
+        Ok(app) => (Box::into_raw(Box::new(app)), Box::into_raw(boxed), std::ptr::null::<Error>()),
+        Err(_) => {
+            let e: Error = "plugin panicked while adding its arguments".into();
+            (Box::into_raw(Box::new(original)), no_plugin, Box::into_raw(Box::new(e)))
+        }
+    }
+}
+
+/// A library that was opened and passed the ABI check, but whose plugin wasn't constructed yet
+struct OpenedPlugin<'help> {
+    canonical: PathBuf,
+    lib: Library,
+    constructor: PluginCreate<'help>,
+    // The `memfd` a library loaded from memory was opened through, see `LoadedLibrary::_memory_file`
+    memory_file: Option<std::fs::File>,
+}
+
+impl<'help> OpenedPlugin<'help> {
+    /// `dlopen`s the library and resolves its symbols. Touches no shared state, so it can run in parallel.
+    unsafe fn open(path: &str) -> Result<Self, KarlsenMinerError> {
+        let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        Self::open_as(path, path, canonical)
+    }
+
+    /// `open`, with `label` standing in for `path` in errors and `canonical` in duplicate checks
+    unsafe fn open_as(path: &str, label: &str, canonical: PathBuf) -> Result<Self, KarlsenMinerError> {
+        let lib = Library::new(path)
+            .map_err(|e| KarlsenMinerError::PluginLoad { path: label.to_string(), source: Box::new(e) })?;
+
+        let found_abi = lib.get::<*const u32>(b"_plugin_abi_version").ok().map(|version| **version);
+        if let Err(e) = check_abi_version(label, found_abi, PLUGIN_ABI_VERSION) {
+            log_warn!("Skipping plugin: {}", e);
+            return Err(e);
+        }
+
+        let constructor: Symbol<PluginCreate> = lib
+            .get(b"_plugin_create")
+            .map_err(|_| KarlsenMinerError::SymbolMissing { path: label.to_string(), symbol: "_plugin_create" })?;
+        let constructor = *constructor;
+        Ok(Self { canonical, lib, constructor, memory_file: None })
+    }
+
+    /// Opens every path on its own thread; the results keep the order of `paths`.
+    fn open_all(paths: &[String]) -> Vec<Result<Self, KarlsenMinerError>> {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> =
+                paths.iter().map(|path| scope.spawn(move || unsafe { Self::open(path.as_str()) })).collect();
+            handles
+                .into_iter()
+                .zip(paths)
+                .map(|(handle, path)| {
+                    handle.join().unwrap_or_else(|_| {
+                        Err(KarlsenMinerError::PluginLoad { path: path.clone(), source: "loader panicked".into() })
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+/// A worker spec along with the name of the plugin that produced it
+type PluginSpec = (&'static str, Box<dyn WorkerSpec + 'static>);
+
+struct LoadedLibrary {
+    // Name of the plugin constructed from the library, `None` if its constructor failed
+    plugin: Option<&'static str>,
+    path: PathBuf,
+    _library: Library,
+    // Kept open for as long as the library is loaded: the loader knows it by its `/proc/self/fd` path, and a
+    // later `memfd` reusing the descriptor would otherwise be handed this library instead of its own
+    _memory_file: Option<std::fs::File>,
+}
+
+/// Plugins are boxed objects whose vtables live inside `loaded_libraries`,
+/// so every plugin must be dropped before the library that created it is unloaded.
 #[derive(Default)]
 pub struct PluginManager {
     plugins: Vec<Box<dyn Plugin>>,
-    loaded_libraries: Vec<Library>,
+    loaded_libraries: Vec<LoadedLibrary>,
+    loaded_paths: Vec<PathBuf>,
+    // (argument name, plugin name) for every argument a loaded plugin added to the `App`
+    arg_owners: Vec<(String, &'static str)>,
+    // Those of `arg_owners` that take values rather than being flags, with their default values, to rebuild each
+    // plugin's view of the matches
+    valued_args: Vec<(String, Vec<String>)>,
+    // Subcommands added by plugins declared with `declare_plugin_subcommand!`
+    subcommands: Vec<PluginSubcommand>,
+    // The last config `configure` succeeded with, applied again by `reprocess_options`
+    config: Option<PluginConfig>,
+}
+
+struct PluginSubcommand {
+    name: String,
+    plugin: &'static str,
+    // What the plugin sees if the subcommand isn't given, `None` if it has required arguments
+    defaults: Option<ArgMatches>,
 }
 
 /**
@@ -19,150 +536,3383 @@ pub struct PluginManager {
 */
 impl PluginManager {
     pub fn new() -> Self {
-        Self { plugins: Vec::new(), loaded_libraries: Vec::new() }
+        Self {
+            plugins: Vec::new(),
+            loaded_libraries: Vec::new(),
+            loaded_paths: Vec::new(),
+            arg_owners: Vec::new(),
+            valued_args: Vec::new(),
+            subcommands: Vec::new(),
+            config: None,
+        }
+    }
+
+    /// Pre-reserves room for `n` plugins, e.g. when the number of plugin paths is known up front
+    pub fn with_capacity(n: usize) -> Self {
+        Self {
+            plugins: Vec::with_capacity(n),
+            loaded_libraries: Vec::with_capacity(n),
+            loaded_paths: Vec::with_capacity(n),
+            arg_owners: Vec::new(),
+            valued_args: Vec::new(),
+            subcommands: Vec::new(),
+            config: None,
+        }
     }
 
-    pub(crate) unsafe fn load_single_plugin<'help>(
+    /// Loads one plugin library on the calling thread; `load_plugins` does the same for many paths at once.
+    ///
+    /// # Safety
+    /// Runs arbitrary initialization code from the library, which must be built against this crate.
+    #[allow(clippy::result_large_err)] // The `App` is handed back by value so loading can go on without the plugin
+    pub unsafe fn load_single_plugin<'help>(
         &mut self,
         app: clap::App<'help>,
         path: &str,
     ) -> Result<clap::App<'help>, (clap::App<'help>, Error)> {
-        type PluginCreate<'help> =
-            unsafe fn(*const clap::App<'help>) -> (*mut clap::App<'help>, *mut dyn Plugin, *mut Error);
+        // Bare library names are resolved by the dynamic loader, so only real files can be canonicalized
+        if self.loaded_paths.contains(&std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))) {
+            log_warn!("Skipping plugin at {}: already loaded", path);
+            return Err((app, format!("plugin at {} is already loaded, skipping", path).into()));
+        }
+        match OpenedPlugin::open(path) {
+            Ok(opened) => self.register_opened(app, path, opened),
+            Err(e) => Err((app, e.into())),
+        }
+    }
+
+    /// Loads a plugin from `bytes` rather than a file, for read-only or diskless deployments. On Linux the bytes
+    /// go to an anonymous `memfd` the dynamic loader opens through `/proc/self/fd`, nothing is written to disk.
+    /// `name` stands in for the path in messages and duplicate checks, as `memory:<name>`.
+    /// Other platforms fail with `PluginLoad`.
+    ///
+    /// # Safety
+    /// Same as `load_single_plugin`.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn load_from_memory<'help>(
+        &mut self,
+        app: clap::App<'help>,
+        bytes: &[u8],
+        name: &str,
+    ) -> Result<clap::App<'help>, (clap::App<'help>, Error)> {
+        let label = format!("memory:{}", name);
+        self.load_bytes(app, bytes, name, &label, PathBuf::from(&label))
+    }
 
-        let lib = match Library::new(path) {
-            Ok(l) => l,
-            Err(e) => return Err((app, e.to_string().into())),
+    /// `load_from_memory` under `label`, `canonical` being the path duplicates are detected by
+    #[allow(clippy::result_large_err)]
+    unsafe fn load_bytes<'help>(
+        &mut self,
+        app: clap::App<'help>,
+        bytes: &[u8],
+        name: &str,
+        label: &str,
+        canonical: PathBuf,
+    ) -> Result<clap::App<'help>, (clap::App<'help>, Error)> {
+        if self.loaded_paths.contains(&canonical) {
+            log_warn!("Skipping plugin at {}: already loaded", label);
+            return Err((app, format!("plugin at {} is already loaded, skipping", label).into()));
+        }
+        let load_error = |e: Error| KarlsenMinerError::PluginLoad { path: label.to_string(), source: e };
+        let file = match memory_file(bytes, name) {
+            Ok(file) => file,
+            Err(e) => return Err((app, load_error(e).into())),
         };
+        #[cfg(target_os = "linux")]
+        let path = format!("/proc/self/fd/{}", std::os::unix::io::AsRawFd::as_raw_fd(&file));
+        #[cfg(not(target_os = "linux"))]
+        let path = String::new();
+        match OpenedPlugin::open_as(&path, label, canonical) {
+            Ok(opened) => self.register_opened(app, label, OpenedPlugin { memory_file: Some(file), ..opened }),
+            Err(e) => Err((app, e.into())),
+        }
+    }
 
-        self.loaded_libraries.push(lib); // Save library so it persists in memory
-        let lib = self.loaded_libraries.last().unwrap();
+    /// Like `load_single_plugin`, but first checks that the file's SHA-256 is `expected_sha256` (hex, any case)
+    /// and refuses to open it otherwise. On Linux the bytes that were hashed are the ones loaded, through a
+    /// `memfd` like `load_from_memory`, so replacing the file after the check has no effect. Elsewhere the file
+    /// is read twice, once here and once by the dynamic loader, so the directory must not be writable by whoever
+    /// the pin protects against.
+    ///
+    /// # Safety
+    /// Same as `load_single_plugin`.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn load_single_plugin_verified<'help>(
+        &mut self,
+        app: clap::App<'help>,
+        path: &str,
+        expected_sha256: &str,
+    ) -> Result<clap::App<'help>, (clap::App<'help>, Error)> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return Err((app, KarlsenMinerError::PluginLoad { path: path.to_string(), source: e.into() }.into()))
+            }
+        };
+        let found = hex::encode(Sha256::digest(&bytes));
+        if !found.eq_ignore_ascii_case(expected_sha256.trim()) {
+            let expected = expected_sha256.to_string();
+            return Err((app, KarlsenMinerError::ChecksumMismatch { path: path.to_string(), expected, found }.into()));
+        }
+        match cfg!(target_os = "linux") {
+            true => {
+                let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+                let name = Path::new(path).file_name().map_or(path.into(), |name| name.to_string_lossy());
+                self.load_bytes(app, &bytes, &name, path, canonical)
+            }
+            false => self.load_single_plugin(app, path),
+        }
+    }
 
-        let constructor: Symbol<PluginCreate> = match lib.get(b"_plugin_create") {
-            Ok(cons) => cons,
-            Err(e) => return Err((app, e.to_string().into())),
+    /// Loads every shared library (by the platform's extension) in `dir`, in file name order. Libraries that
+    /// fail to load, e.g. because they aren't plugins or target another ABI version, are reported and skipped;
+    /// only an unreadable `dir` is an error.
+    ///
+    /// # Safety
+    /// Same as `load_single_plugin`, for every library in `dir`.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn load_from_dir<'help>(
+        &mut self,
+        app: clap::App<'help>,
+        dir: &Path,
+    ) -> Result<clap::App<'help>, (clap::App<'help>, Error)> {
+        let candidates = match plugin_candidates(dir) {
+            Ok(candidates) => candidates,
+            Err(e) => return Err((app, format!("failed to scan {} for plugins: {}", dir.display(), e).into())),
         };
+        let mut app = app;
+        for path in candidates {
+            app = self.load_single_plugin(app, &path.to_string_lossy()).unwrap_or_else(|(app, e)| {
+                log_warn!("Skipping {}: {}", path.display(), e);
+                app
+            });
+        }
+        Ok(app)
+    }
 
-        let (app, boxed_raw, error) = constructor(Box::into_raw(Box::new(app)));
-        let app = *Box::from_raw(app);
+    /// Second half of loading: runs the plugin constructor, which augments `app`, and keeps the library alive.
+    /// Must run sequentially in load order since every plugin threads the same `App`.
+    #[allow(clippy::result_large_err)]
+    unsafe fn register_opened<'help>(
+        &mut self,
+        app: clap::App<'help>,
+        path: &str,
+        opened: OpenedPlugin<'help>,
+    ) -> Result<clap::App<'help>, (clap::App<'help>, Error)> {
+        let OpenedPlugin { canonical, lib, constructor, memory_file } = opened;
+        if self.loaded_paths.contains(&canonical) {
+            log_warn!("Skipping plugin at {}: already loaded", path);
+            return Err((app, format!("plugin at {} is already loaded, skipping", path).into()));
+        }
+        // Save library so it persists in memory
+        self.loaded_libraries.push(LoadedLibrary {
+            plugin: None,
+            path: canonical.clone(),
+            _library: lib,
+            _memory_file: memory_file,
+        });
 
-        if boxed_raw.is_null() {
-            return Err((app, *Box::from_raw(error)));
+        // The last good `App`, handed back on any failure so the plugins loaded so far keep their arguments
+        // whatever this one returned
+        let previous = app.clone();
+        let first_new = app.get_arguments().count();
+        let first_new_subcommand = app.get_subcommands().count();
+        let (app, plugin) = construct_plugin(constructor, app);
+        let plugin = match plugin {
+            Ok(plugin) => plugin,
+            Err(e) => return Err((previous, e)),
+        };
+        let conflicts = check_kept_previous(&previous, &app, plugin.name())
+            .and_then(|()| check_arg_conflicts(&app, first_new, plugin.name(), &self.arg_owners))
+            .and_then(|()| check_subcommand_conflicts(&app, first_new_subcommand, plugin.name(), &self.subcommands));
+        if let Err(e) = conflicts {
+            drop(plugin); // The plugin's code lives in the library, so it must go first
+            self.loaded_libraries.pop();
+            return Err((previous, e));
         }
-        let plugin = Box::from_raw(boxed_raw);
+        self.arg_owners
+            .extend(app.get_arguments().skip(first_new).map(|arg| (arg.get_name().to_string(), plugin.name())));
+        self.valued_args.extend(
+            app.get_arguments().skip(first_new).filter(|arg| arg.is_set(clap::ArgSettings::TakesValue)).map(|arg| {
+                let defaults = arg.get_default_values().iter().map(|value| value.to_string_lossy().into_owned());
+                (arg.get_name().to_string(), defaults.collect())
+            }),
+        );
+        self.subcommands.extend(app.get_subcommands().skip(first_new_subcommand).map(|subcommand| PluginSubcommand {
+            name: subcommand.get_name().to_string(),
+            plugin: plugin.name(),
+            defaults: subcommand.clone().try_get_matches_from([subcommand.get_name()]).ok(),
+        }));
+        self.loaded_libraries.last_mut().unwrap().plugin = Some(plugin.name());
+        log_info!("Loaded plugin {} {} from {}", plugin.name(), plugin.version(), canonical.display());
         self.plugins.push(plugin);
+        self.loaded_paths.push(canonical);
 
         Ok(app)
     }
 
+    /// Swaps the named plugin for the library at `path`, e.g. to pick up an updated backend. The old plugin
+    /// and then its library are unloaded before the new one is opened, so a failed reload leaves it unloaded.
+    /// The new plugin keeps the old one's position but is unconfigured: call `process_options` again.
+    ///
+    /// # Safety
+    /// Every `Worker` and `WorkerSpec` built from the old plugin must be dropped beforehand, as their code
+    /// is unmapped with the library. The new library must be built against this crate, like `load_single_plugin`.
+    pub unsafe fn reload_plugin(&mut self, name: &str, path: &str) -> Result<(), Error> {
+        let index =
+            self.plugins.iter().position(|plugin| plugin.name() == name).ok_or(format!("no plugin named {}", name))?;
+        if !self.loaded_libraries.iter().any(|lib| lib.plugin == Some(name)) {
+            return Err(format!("plugin {} was not loaded from a library", name).into());
+        }
+        self.remove_plugin(index);
+
+        // The arguments were already registered and parsed by the host, so the new ones are only recorded
+        let app = clap::App::new(name.to_string());
+        self.load_single_plugin(app, path).map_err(|(_, e)| e)?;
+        let plugin = self.plugins.pop().unwrap();
+        self.plugins.insert(index, plugin);
+        Ok(())
+    }
+
+    /// Permanently removes the named plugin, e.g. a misbehaving backend, and unloads its library if it came from one.
+    /// The manager doesn't see the workers it built, so the caller passes how many built from this plugin it still
+    /// holds (`Worker::source_plugin` tells them apart); unloading is refused unless that is zero, as their code
+    /// would be unmapped with the library. Specs from `build` must be dropped as well.
+    pub fn unload_plugin(&mut self, name: &str, live_workers: usize) -> Result<(), Error> {
+        let index =
+            self.plugins.iter().position(|plugin| plugin.name() == name).ok_or(format!("no plugin named {}", name))?;
+        if live_workers > 0 {
+            return Err(format!("{} workers built from plugin {} are still alive", live_workers, name).into());
+        }
+        self.remove_plugin(index);
+        Ok(())
+    }
+
+    /// Drops the plugin at `index`, then its library (if any), and forgets its path and arguments
+    fn remove_plugin(&mut self, index: usize) {
+        let mut plugin = self.plugins.remove(index);
+        let name = plugin.name();
+        shutdown_plugin(plugin.as_mut());
+        drop(plugin);
+        if let Some(library) = self.loaded_libraries.iter().position(|lib| lib.plugin == Some(name)) {
+            let old = self.loaded_libraries.remove(library);
+            self.loaded_paths.retain(|path| *path != old.path);
+            drop(old);
+        }
+        let owners = &mut self.arg_owners;
+        self.valued_args.retain(|(arg, _)| owners.iter().any(|(owned, owner)| owned == arg && *owner != name));
+        owners.retain(|(_, owner)| *owner != name);
+        self.subcommands.retain(|subcommand| subcommand.plugin != name);
+    }
+
+    /// Adds a plugin that is compiled into the miner rather than loaded from a library
+    pub fn register_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        log_info!("Registered built-in plugin {} {}", plugin.name(), plugin.version());
+        self.plugins.push(plugin);
+    }
+
+    /// Registers a `cpu::CpuPlugin` with a worker per available core if nothing else was loaded, so machines
+    /// without GPUs can still mine, slowly. Returns whether it did.
+    pub fn ensure_cpu_fallback(&mut self) -> bool {
+        if !self.plugins.is_empty() {
+            return false;
+        }
+        let workers = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        self.register_plugin(Box::new(cpu::CpuPlugin::new(workers, CPU_FALLBACK_WORKLOAD)));
+        true
+    }
+
+    /// Collects the worker specs of every enabled plugin, ordered by `Plugin::priority` and then load order.
+    /// Fails if no plugin was loaded at all, or if a plugin panicked while listing its devices.
     pub fn build(&self) -> Result<Vec<Box<dyn WorkerSpec + 'static>>, Error> {
-        let mut specs = Vec::<Box<dyn WorkerSpec + 'static>>::new();
-        for plugin in &self.plugins {
+        Ok(self.specs_by_plugin()?.into_iter().map(|(_, spec)| spec).collect())
+    }
+
+    /// Like `build`, keeping only the specs whose `WorkerSpec::device_id` is in `device_ids`, e.g. from `--devices`
+    /// parsed with `cli::parse_device_selection`.
+    /// Device ids are numbered per plugin, so an id matches on every plugin having it. Fails if an id matches nothing.
+    pub fn build_filtered(&self, device_ids: &[u32]) -> Result<Vec<Box<dyn WorkerSpec + 'static>>, Error> {
+        let specs = self.build()?;
+        if let Some(missing) = device_ids.iter().find(|&&id| !specs.iter().any(|spec| spec.device_id() == id)) {
+            return Err(format!("no device with id {} was found", missing).into());
+        }
+        Ok(specs.into_iter().filter(|spec| device_ids.contains(&spec.device_id())).collect())
+    }
+
+    fn specs_by_plugin(&self) -> Result<Vec<PluginSpec>, KarlsenMinerError> {
+        if self.plugins.is_empty() {
+            return Err(KarlsenMinerError::NoPlugins);
+        }
+        let mut specs = Vec::new();
+        for plugin in self.by_priority() {
             if plugin.enabled() {
-                specs.extend(plugin.get_worker_specs());
+                let name = plugin.name();
+                specs.extend(catch_plugin_panic(name, || plugin.get_worker_specs())?.into_iter().map(|s| (name, s)));
             }
         }
         Ok(specs)
     }
 
+    /// Builds a worker for every spec on the calling thread, skipping devices that fail to initialize,
+    /// including those whose plugin panicked while building them.
+    /// Fails only when specs were available but none of them could be built.
+    pub fn build_workers(&self) -> Result<Vec<Box<dyn Worker>>, Error> {
+        let specs = self.specs_by_plugin()?;
+        let mut workers = Vec::with_capacity(specs.len());
+        let mut last_error = None;
+        for (plugin, spec) in &specs {
+            match catch_plugin_panic(plugin, || spec.build()).unwrap_or_else(|e| Err(e.into())) {
+                Ok(worker) => workers.push(worker),
+                Err(e) => {
+                    log_warn!("Failed initializing device {}, skipping it: {}", spec.id(), e);
+                    last_error = Some(format!("{}: {}", spec.id(), e));
+                }
+            }
+        }
+        match last_error {
+            Some(e) if workers.is_empty() => Err(format!("No device could be initialized, last error: {}", e).into()),
+            _ => Ok(workers),
+        }
+    }
+
+    /// Builds every spec on the calling thread like `build_workers`, but reports each device that failed,
+    /// including those whose plugin panicked while building them, instead of only the last error. Plugins
+    /// panicking while listing their specs are reported and left out.
+    pub fn build_report(&self) -> BuildReport {
+        let mut report = BuildReport { workers: Vec::new(), failures: Vec::new() };
+        for plugin in self.by_priority().into_iter().filter(|plugin| plugin.enabled()) {
+            let name = plugin.name();
+            let specs = match catch_plugin_panic(name, || plugin.get_worker_specs()) {
+                Ok(specs) => specs,
+                Err(e) => {
+                    log_warn!("{}, skipping its devices", e);
+                    continue;
+                }
+            };
+            for spec in specs {
+                match catch_plugin_panic(name, || spec.build()).unwrap_or_else(|e| Err(e.into())) {
+                    Ok(worker) => report.workers.push(worker),
+                    Err(e) => report.failures.push((spec.device_id(), e)),
+                }
+            }
+        }
+        report
+    }
+
+    /// Lists the devices every plugin detected without building workers, so no GPU context is allocated.
+    /// Plugins are listed in the same order as `build`; those panicking while listing are reported and left out.
+    pub fn enumerate(&self) -> Vec<DeviceInfo> {
+        let mut devices = Vec::new();
+        for plugin in self.by_priority() {
+            let specs = match catch_plugin_panic(plugin.name(), || plugin.get_worker_specs()) {
+                Ok(specs) => specs,
+                Err(e) => {
+                    log_warn!("{}, skipping its devices", e);
+                    continue;
+                }
+            };
+            devices.extend(specs.iter().map(|spec| DeviceInfo {
+                plugin: plugin.name(),
+                device_id: spec.device_id(),
+                name: spec.id(),
+                workload: spec.workload(),
+                recommended_workload: spec.recommended_workload(),
+                opencl_platform: spec.opencl_platform(),
+            }));
+        }
+        devices
+    }
+
+    /// Every device each plugin detected, tagged with the plugin's name and ordered like `enumerate`. Unlike
+    /// `enumerate` this includes devices not selected and plugins that aren't enabled. Plugins panicking while
+    /// detecting are reported and left out.
+    pub fn available_devices(&self) -> Vec<(&'static str, RawDevice)> {
+        let mut devices = Vec::new();
+        for plugin in self.by_priority() {
+            match catch_plugin_panic(plugin.name(), || plugin.available_devices()) {
+                Ok(detected) => devices.extend(detected.into_iter().map(|device| (plugin.name(), device))),
+                Err(e) => log_warn!("{}, skipping its devices", e),
+            }
+        }
+        devices
+    }
+
+    /// Highest `Plugin::priority` first; the sort is stable, so ties keep their load order
+    fn by_priority(&self) -> Vec<&dyn Plugin> {
+        let mut plugins: Vec<_> = self.plugins.iter().map(|plugin| plugin.as_ref()).collect();
+        plugins.sort_by_key(|plugin| std::cmp::Reverse(plugin.priority()));
+        plugins
+    }
+
     /**
-    Process the options for a plugin, and reports how many workers are available
+    Process the options for a plugin, and reports how many workers are available.
+    A loaded plugin only sees the arguments it added to the `App` (see `plugin_matches`), built-in ones see all.
+    One declared with `declare_plugin_subcommand!` sees its subcommand's matches, or its defaults if another or no
+    subcommand was given; it is left unconfigured if the subcommand has required arguments and wasn't given.
+    Every plugin processes its options even if an earlier one failed: a single failure is returned as is, several
+    as `OptionErrors` so all of them can be fixed at once. Only then every enabled plugin validates its
+    configuration and the workloads of its specs are range-checked, all rejections are reported together.
+    A plugin panicking in either step fails with `PluginPanicked` instead of unwinding into the caller.
     */
-    pub fn process_options(&mut self, matchs: &ArgMatches) -> Result<usize, Error> {
+    pub fn process_options(&mut self, matchs: &ArgMatches) -> Result<usize, KarlsenMinerError> {
         let mut count = 0usize;
-        self.plugins.iter_mut().for_each(|plugin| {
-            count += match plugin.process_option(matchs) {
-                Ok(n) => n,
-                Err(e) => {
-                    eprintln!(
-                        "WARNING: Failed processing options for {} (ignore if you do not intend to use): {}",
-                        plugin.name(),
-                        e
-                    );
-                    0
+        let mut failures = Vec::new();
+        for plugin in self.plugins.iter_mut() {
+            let name = plugin.name();
+            let view = match plugin_matches(name, &self.arg_owners, &self.valued_args, matchs) {
+                Ok(view) => view,
+                Err(source) => {
+                    failures.push((name, KarlsenMinerError::OptionProcessing { plugin: name, source }));
+                    continue;
+                }
+            };
+            let matchs = match self.subcommands.iter().find(|subcommand| subcommand.plugin == name) {
+                Some(subcommand) => {
+                    match matchs.subcommand_matches(&subcommand.name).or(subcommand.defaults.as_ref()) {
+                        Some(matchs) => matchs,
+                        None => continue,
+                    }
                 }
+                None => view.as_ref().unwrap_or(matchs),
+            };
+            match catch_plugin_panic(name, || plugin.process_option(matchs)) {
+                Ok(Ok(workers)) => count += workers,
+                Ok(Err(source)) => failures.push((name, KarlsenMinerError::OptionProcessing { plugin: name, source })),
+                Err(e) => failures.push((name, e)),
             }
-        });
-        Ok(count)
+        }
+        if failures.len() == 1 {
+            return Err(failures.pop().unwrap().1);
+        }
+        if !failures.is_empty() {
+            let errors = failures
+                .into_iter()
+                .map(|(name, e)| match e {
+                    KarlsenMinerError::OptionProcessing { source, .. } => (name.to_string(), source),
+                    e => (name.to_string(), e.into()),
+                })
+                .collect();
+            return Err(KarlsenMinerError::OptionErrors(errors));
+        }
+        let errors: Vec<_> = self
+            .plugins
+            .iter()
+            .filter(|plugin| plugin.enabled())
+            .filter_map(|plugin| {
+                let name = plugin.name();
+                catch_plugin_panic(name, || {
+                    plugin.validate()?;
+                    plugin.get_worker_specs().iter().try_for_each(|spec| check_workload(spec.as_ref()))
+                })
+                .unwrap_or_else(|e| Err(e.into()))
+                .err()
+                .map(|e| (name, e))
+            })
+            .collect();
+        match errors.is_empty() {
+            true => Ok(count),
+            false => Err(KarlsenMinerError::InvalidConfiguration(errors)),
+        }
+    }
+
+    /// Hands `config` to every plugin, in load order; the host calls this after `process_options`
+    pub fn configure(&mut self, config: &PluginConfig) -> Result<(), KarlsenMinerError> {
+        for plugin in self.plugins.iter_mut() {
+            let name = plugin.name();
+            catch_plugin_panic(name, || plugin.configure(config))?
+                .map_err(|source| KarlsenMinerError::OptionProcessing { plugin: name, source })?;
+        }
+        self.config = Some(config.clone());
+        Ok(())
+    }
+
+    /// Runs `process_options` again over matches parsed at runtime, then `configure` with the last config it
+    /// succeeded with, so settings can change without a restart. The host must pause its workers first; they
+    /// stay alive, built from the earlier options, and rebuilding any of them is up to the host. On failure
+    /// the plugins may be left partly reconfigured, like after a failed `process_options`.
+    pub fn reprocess_options(&mut self, matchs: &ArgMatches) -> Result<(), KarlsenMinerError> {
+        self.process_options(matchs)?;
+        if let Some(config) = self.config.clone() {
+            self.configure(&config)?;
+        }
+        Ok(())
     }
 
     pub fn has_specs(&self) -> bool {
         !self.plugins.is_empty()
     }
-}
 
-pub trait Plugin: Any + Send + Sync {
-    fn name(&self) -> &'static str;
-    fn enabled(&self) -> bool;
-    fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>>;
-    fn process_option(&mut self, matchs: &ArgMatches) -> Result<usize, Error>;
-}
+    /// Names of the arguments the given plugin added to the `App` returned by `load_plugins`
+    pub fn plugin_args(&self, plugin: &str) -> Vec<&str> {
+        self.arg_owners.iter().filter(|(_, owner)| *owner == plugin).map(|(arg, _)| arg.as_str()).collect()
+    }
 
-pub trait WorkerSpec: Any + Send + Sync {
-    /*type_: GPUWorkType,
-    opencl_platform: u16,
-    device_id: u32,
-    workload: f32,
-    is_absolute: bool*/
-    fn id(&self) -> String;
-    fn build(&self) -> Box<dyn Worker>;
-}
+    /// Names of the loaded plugins, in load order
+    pub fn plugin_names(&self) -> Vec<&'static str> {
+        self.plugins.iter().map(|plugin| plugin.name()).collect()
+    }
 
-pub trait Worker {
-    //fn new(device_id: u32, workload: f32, is_absolute: bool) -> Result<Self, Error>;
-    fn id(&self) -> String;
-    fn load_block_constants(&mut self, hash_header: &[u8; 72], matrix: &[[u16; 64]; 64], target: &[u64; 4]);
+    /// Where the loaded plugins' libraries were loaded from, canonicalized and in load order. Plugins loaded
+    /// with `load_from_memory` show up as `memory:<name>`, those added with `register_plugin` not at all.
+    pub fn loaded_paths(&self) -> &[PathBuf] {
+        &self.loaded_paths
+    }
 
-    fn calculate_hash(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64);
-    fn sync(&self) -> Result<(), Error>;
+    /// `(name, version)` of the loaded plugins, in load order
+    pub fn plugin_versions(&self) -> Vec<(&'static str, &'static str)> {
+        self.plugins.iter().map(|plugin| (plugin.name(), plugin.version())).collect()
+    }
 
-    fn get_workload(&self) -> usize;
-    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<(), Error>;
+    pub fn plugin_count(&self) -> usize {
+        self.plugins.len()
+    }
 }
 
-pub fn load_plugins<'help>(
-    app: clap::App<'help>,
-    paths: &[String],
-) -> Result<(clap::App<'help>, PluginManager), Error> {
-    let mut factory = PluginManager::new();
-    let mut app = app;
-    for path in paths {
-        app = unsafe {
-            factory.load_single_plugin(app, path.as_str()).unwrap_or_else(|(app, e)| {
-                eprintln!("WARNING: Failed loading plugin {} (ignore if you do not intend to use): {}", path, e);
-                app
-            })
+impl PluginManager {
+    /// Tears everything down: every plugin is shut down in reverse load order, then all plugins are dropped,
+    /// then their libraries are unloaded. Failing shutdowns are reported and don't stop the others. The manager
+    /// is empty afterwards, dropping it does the same if this wasn't called.
+    pub fn shutdown_all(&mut self) {
+        for plugin in self.plugins.iter_mut().rev() {
+            shutdown_plugin(plugin.as_mut());
+        }
+        // Plugin vtables live inside the libraries, so the plugins must go first.
+        self.plugins.clear();
+        self.loaded_libraries.clear();
+        self.loaded_paths.clear();
+        self.arg_owners.clear();
+        self.valued_args.clear();
+        self.subcommands.clear();
+    }
+}
+
+fn shutdown_plugin(plugin: &mut dyn Plugin) {
+    let name = plugin.name();
+    match catch_plugin_panic(name, || plugin.shutdown()) {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log_warn!("Shutting down plugin {} failed: {}", name, e),
+        Err(e) => log_warn!("{} while shutting down", e),
+    }
+}
+
+impl Drop for PluginManager {
+    fn drop(&mut self) {
+        self.shutdown_all();
+    }
+}
+
+pub trait Plugin: Any + Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Plugins should override this with `env!("CARGO_PKG_VERSION")` so bug reports name the exact backend build
+    fn version(&self) -> &'static str {
+        "unknown"
+    }
+    fn enabled(&self) -> bool;
+    /// Plugins with a higher priority get their specs ahead of the others in `PluginManager::build` and
+    /// `PluginManager::enumerate`, so device order doesn't depend on the order of `--gpu-plugin` flags
+    fn priority(&self) -> i32 {
+        0
+    }
+    fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>>;
+    /// Every device the backend can see, queried from the driver on each call. Works before `process_option`.
+    fn available_devices(&self) -> Vec<RawDevice> {
+        Vec::new()
+    }
+    /// Applies the plugin's options and reports how many workers it can build. May be called again mid-run by
+    /// `PluginManager::reprocess_options`, with workers built from the earlier options still alive: backends
+    /// must replace their previous settings rather than add to them, and leave those workers alone.
+    fn process_option(&mut self, matchs: &ArgMatches) -> Result<usize, Error>;
+    /// Reads settings that aren't clap arguments, called by `PluginManager::configure` after `process_options`.
+    /// Keys a plugin doesn't know should be ignored, the config is shared by all of them.
+    fn configure(&mut self, _config: &PluginConfig) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Rejects nonsensical configurations with a clear message before any device is touched. Called by
+    /// `PluginManager::process_options` once every plugin has processed its options.
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Releases global resources (driver handles, temporary files) while the plugin's library is still loaded,
+    /// instead of leaving it to `Drop` order. Called once, by `PluginManager::shutdown_all` or on unload.
+    fn shutdown(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities::default()
+    }
+}
+
+pub trait WorkerSpec: Any + Send + Sync {
+    fn id(&self) -> String;
+    /// Allocates the device context; fails instead of panicking when the device can't be initialized. Every call
+    /// allocates a fresh context, so a spec can be built again, e.g. after a reconnect.
+    fn build(&self) -> Result<Box<dyn Worker>, Error>;
+    /// A copy the host can cache to rebuild workers later without enumerating the plugins again
+    fn clone_spec(&self) -> Box<dyn WorkerSpec>;
+
+    /// The metadata below is available before `build()`, so the host can list devices without allocating them
+    fn device_id(&self) -> u32 {
+        0
+    }
+    fn gpu_type(&self) -> &str {
+        "unknown"
+    }
+    /// How much one launch covers, interpreted according to `workload_is_absolute`
+    fn workload(&self) -> f32 {
+        1.0
+    }
+    /// When false, `workload` is a multiple of the device's preferred parallelism (e.g. its maximum work group
+    /// size), so fractions are meaningful. When true, it is the absolute global work size, a whole number of nonces.
+    fn workload_is_absolute(&self) -> bool {
+        false
+    }
+    /// A relative workload (see `workload_is_absolute`) suiting the device, derived by the backend from its
+    /// properties, for when the user gave none
+    fn recommended_workload(&self) -> f32 {
+        1.0
+    }
+    /// Index of the OpenCL platform the device belongs to, `None` for other backends
+    fn opencl_platform(&self) -> Option<u16> {
+        None
+    }
+    /// NUMA node the device is attached to, the host pins the worker's thread to it
+    /// (see `topology::pin_thread_to_node`). `None` if unknown.
+    fn numa_node(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Builds `spec` again to replace `previous`, e.g. after it failed with `WorkerError::DeviceLost`, carrying over
+/// the workload, batch size and nonce endianness the host configured on it. The new worker has a device context
+/// of its own and no job loaded; drop `previous` as soon as this returns, it still holds the old context.
+pub fn respawn(spec: &dyn WorkerSpec, previous: &dyn Worker) -> Result<Box<dyn Worker>, Error> {
+    let mut worker = spec.build()?;
+    let workload = previous.get_workload();
+    if worker.get_workload() != workload {
+        worker.set_workload(workload)?;
+    }
+    worker.set_batch_size(previous.batch_size());
+    worker.set_nonce_endianness(previous.nonce_endianness());
+    Ok(worker)
+}
+
+/// Range-checks `spec.workload()` for its mode: a relative workload must be positive and finite,
+/// an absolute one a whole number of nonces
+pub fn check_workload(spec: &dyn WorkerSpec) -> Result<(), Error> {
+    let workload = spec.workload();
+    match spec.workload_is_absolute() {
+        false if !(workload.is_finite() && workload > 0.) => {
+            Err(format!("relative workload of {} must be positive, got {}", spec.id(), workload).into())
+        }
+        true if !(workload >= 1. && workload.fract() == 0. && workload <= u32::MAX as f32) => {
+            Err(format!("absolute workload of {} must be a whole number of nonces, got {}", spec.id(), workload).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Point-in-time view of a worker, taken in one call so the fields are consistent with each other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WorkerStats {
+    pub hashes: u64,
+    /// Nonces meeting the target since the worker was built
+    pub found: u64,
+    pub workload: usize,
+    pub paused: bool,
+}
+
+/// Everything a worker needs to mine a job, as produced by the `stratum` and `solo` clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiningJob {
+    pub header: [u8; 72],
+    pub matrix: [[u16; 64]; 64],
+    pub target: [u64; 4],
+    pub job_id: u64,
+    /// When the job was received
+    pub timestamp: Instant,
+}
+
+impl MiningJob {
+    pub fn new(header: [u8; 72], matrix: [[u16; 64]; 64], target: [u64; 4], job_id: u64) -> Self {
+        Self { header, matrix, target, job_id, timestamp: Instant::now() }
+    }
+
+    pub fn constants(&self) -> JobConstants {
+        JobConstants { header: self.header, matrix: self.matrix, target: self.target, job_id: self.job_id }
+    }
+
+    /// The job in a fixed layout, e.g. to hand it to worker processes over a pipe, all integers little-endian:
+    ///
+    /// ```text
+    /// offset  size  field
+    ///      0     4  magic "KMJ" and layout version 1
+    ///      4    72  header
+    ///     76  8192  matrix, row by row
+    ///   8268    32  target, least significant word first
+    ///   8300     8  job_id
+    /// ```
+    ///
+    /// `timestamp` is local to the process and isn't included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(JOB_BYTES);
+        bytes.extend_from_slice(JOB_MAGIC);
+        bytes.extend_from_slice(&self.header);
+        bytes.extend(self.matrix.iter().flatten().flat_map(|element| element.to_le_bytes()));
+        bytes.extend_from_slice(&target::target_to_le_bytes(&self.target));
+        bytes.extend_from_slice(&self.job_id.to_le_bytes());
+        bytes
+    }
+
+    /// Reads back what `to_bytes` wrote, stamping the job as received now
+    pub fn from_bytes(bytes: &[u8]) -> Result<MiningJob, Error> {
+        if bytes.len() != JOB_BYTES {
+            return Err(format!("a job is {} bytes, got {}", JOB_BYTES, bytes.len()).into());
+        }
+        let (magic, rest) = bytes.split_at(JOB_MAGIC.len());
+        if magic != JOB_MAGIC {
+            return Err(format!("not a job or a different layout version, starts with {:02x?}", magic).into());
+        }
+        let (header, rest) = rest.split_at(72);
+        let (matrix_bytes, rest) = rest.split_at(64 * 64 * 2);
+        let (target_bytes, job_id) = rest.split_at(32);
+
+        let mut matrix = [[0u16; 64]; 64];
+        for (element, chunk) in matrix.iter_mut().flatten().zip(matrix_bytes.chunks_exact(2)) {
+            *element = u16::from_le_bytes(chunk.try_into().unwrap());
+        }
+        let target = target::target_from_le_bytes(target_bytes.try_into().unwrap());
+        let job_id = u64::from_le_bytes(job_id.try_into().unwrap());
+        Ok(MiningJob::new(header.try_into().unwrap(), matrix, target, job_id))
+    }
+}
+
+const JOB_MAGIC: &[u8; 4] = b"KMJ\x01";
+/// Size of `MiningJob::to_bytes`
+const JOB_BYTES: usize = 4 + 72 + 64 * 64 * 2 + 32 + 8;
+
+/// Block constants of one of several jobs mined at once with `Worker::load_jobs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobConstants {
+    pub header: [u8; 72],
+    pub matrix: [[u16; 64]; 64],
+    pub target: [u64; 4],
+    pub job_id: u64,
+}
+
+// Low half of every uuid handed out by `assign_worker_uuid`, so rebuilding a worker never reuses one
+static NEXT_WORKER_SERIAL: AtomicU64 = AtomicU64::new(1);
+
+/// A fresh `Worker::uuid` for a worker being built for `device_id` by the plugin named `plugin`: the top 32 bits
+/// hash the plugin name, the next 32 are the device id and the low 64 a process-wide build counter. Backends call
+/// this once when constructing the worker and keep the result.
+pub fn assign_worker_uuid(plugin: &str, device_id: u32) -> u128 {
+    // FNV-1a, plugins don't know their load order but their names are unique
+    let plugin_hash = plugin.bytes().fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+    let serial = NEXT_WORKER_SERIAL.fetch_add(1, Ordering::Relaxed);
+    (plugin_hash as u128) << 96 | (device_id as u128) << 64 | serial as u128
+}
+
+/// A nonce reported by `Worker::copy_job_output_to`, tagged with the job it meets the target of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoundNonce {
+    pub job_id: u64,
+    pub nonce: u64,
+}
+
+pub trait Worker {
+    //fn new(device_id: u32, workload: f32, is_absolute: bool) -> Result<Self, Error>;
+    fn id(&self) -> String;
+    /// `Plugin::name` of the plugin that built this worker, so a misbehaving backend can be told apart
+    fn source_plugin(&self) -> &'static str {
+        "unknown"
+    }
+    /// Unique among all workers built in this process and fixed for the worker's lifetime, unlike `id` which is
+    /// only a label, so the host keys metrics by it. Backends assign it with `assign_worker_uuid` when built;
+    /// the default `0` means the backend doesn't, and the host has to fall back to `id`.
+    fn uuid(&self) -> u128 {
+        0
+    }
+    /// Byte order the worker writes found nonces in where it serializes them for a pool, picked by the host per
+    /// pool as they disagree; hashing always uses the consensus little-endian placement regardless. Little-endian
+    /// by default, backends that never serialize nonces ignore it.
+    fn set_nonce_endianness(&mut self, _le: bool) {}
+    /// `true` for little-endian; backends honoring `set_nonce_endianness` report back what they were set to
+    fn nonce_endianness(&self) -> bool {
+        true
+    }
+    /// Confirms the device computes correct hashes before it's used for mining, failing if it doesn't.
+    /// Backends implement it with `planted_self_check` unless they have a cheaper check of their own; it
+    /// replaces the loaded constants. The default, for backends without one, passes.
+    fn self_check(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Runs a throwaway launch so kernel compilation and lazy allocations happen now rather than during the first
+    /// job or timed sample. Called once after the worker is built; leaves no job loaded and no output behind.
+    fn warmup(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Fails on constants the backend can't mine correctly, e.g. a singular matrix, so the host can skip the job
+    fn load_block_constants(
+        &mut self,
+        hash_header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+    ) -> Result<(), Error>;
+    /// Discards everything left over from the previous job, so its nonces are never submitted for the next one.
+    /// The host calls this once the last launch synced, right before loading a new job. The default drains the
+    /// output; backends override it to also reset device buffers and nonce counters of their own.
+    fn reset_for_new_job(&mut self) -> Result<(), Error> {
+        let mut stale = vec![0u64; self.max_output_nonces()];
+        self.copy_output_to(&mut stale).map(drop)
+    }
+    /// `load_block_constants` with the constants of `job`. Workers reporting `current_job_id` override it to
+    /// remember `job.job_id`.
+    fn load_job(&mut self, job: &MiningJob) -> Result<(), Error> {
+        self.load_block_constants(&job.header, &job.matrix, &job.target)
+    }
+    /// The id of the job being mined, to attribute found nonces while workers switch jobs one after the other.
+    /// Jobs loaded with `load_block_constants` carry no id and report 0; with several jobs loaded by `load_jobs`,
+    /// the last of them. `None` before any job was loaded, or if the worker doesn't keep track.
+    fn current_job_id(&self) -> Option<u64> {
+        None
+    }
+    /// Mines every job at once, each nonce being checked against all of them, e.g. for pools handing out
+    /// several jobs with different difficulties. The default only handles a single job.
+    fn load_jobs(&mut self, jobs: &[JobConstants]) -> Result<(), Error> {
+        match jobs {
+            [job] => self.load_block_constants(&job.header, &job.matrix, &job.target),
+            _ => Err(format!("mining {} jobs at once is unsupported by this worker", jobs.len()).into()),
+        }
+    }
+
+    fn calculate_hash(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64);
+    /// Hashes every nonce in `start..start + count`, clamped to `u64::MAX`. The default materializes the
+    /// range and hands it to `calculate_hash`; backends should launch over the range directly instead.
+    fn calculate_hash_range(&mut self, start: u64, count: u64) {
+        let nonces = (start..start.saturating_add(count)).collect();
+        self.calculate_hash(Some(&nonces), 0, 0)
+    }
+    /// The parts of the nonce space `calculate_hash_range` actually scanned for the current job, in scan order
+    /// with adjacent ranges merged, so the host can persist progress and skip them after a restart. Loading new
+    /// constants starts over. The default tracks nothing and reports no ranges.
+    fn covered_ranges(&self) -> Vec<NonceRange> {
+        Vec::new()
+    }
+    /// The state of the generator random nonces are drawn from, for `session::SessionState`
+    fn rng_state(&self) -> Option<[u64; 4]> {
+        None
+    }
+    /// Reseeds that generator with `Xoshiro256StarStar::from_seed_u64(seed)`, so tests get the same nonces from
+    /// every run. The default, for backends drawing nonces elsewhere, ignores it.
+    fn set_rng_seed(&mut self, _seed: u64) {}
+    /// Carries on from a session saved before a restart: the covered ranges and generator state of the job
+    /// loaded again right before
+    fn restore_session(&mut self, _session: &session::WorkerSession) -> Result<(), Error> {
+        Err("restoring a session is unsupported by this worker".into())
+    }
+    fn sync(&self) -> Result<(), WorkerError>;
+    /// Launches and syncs batches until `deadline` passes or `request_stop` is called, keeping a job within
+    /// its block's validity window. The deadline is only checked between launches, so it can be overrun by up
+    /// to one batch; shrink `set_batch_size` for a tighter budget.
+    fn calculate_hash_until(&mut self, deadline: Instant) -> Result<(), WorkerError> {
+        while Instant::now() < deadline && !self.stop_requested() {
+            self.calculate_hash(None, u64::MAX, 0);
+            events::reported_sync(self)?;
+        }
+        Ok(())
+    }
+
+    /// Enqueues a `calculate_hash` launch without waiting for it, so the host can drive several devices
+    /// from one thread. Every launch must be awaited with `sync` (or `try_sync` returning `Ok(true)`)
+    /// before calling `copy_output_to`; reading the output earlier may return stale nonces. Backends whose
+    /// `calculate_hash` already returns before the kernel finishes can keep the default.
+    fn calculate_hash_async(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64) {
+        self.calculate_hash(nonces, nonce_mask, nonce_fixed)
+    }
+    /// Non-blocking `sync`: `Ok(false)` while the last launch is still running, `Ok(true)` once it
+    /// completed. The default blocks in `sync`, which is correct but gives up the overlap.
+    fn try_sync(&self) -> Result<bool, WorkerError> {
+        self.sync().map(|()| true)
+    }
+    /// Caps how many `calculate_hash_async` launches may be queued before one is synced, bounding the work
+    /// wasted on a stale job. Backends that queue don't enqueue a launch beyond the cap, so the host should
+    /// check `inflight` first. The default ignores it.
+    fn set_max_inflight(&mut self, _max_inflight: u32) {}
+    /// Launches enqueued but not synced yet; the default, for backends that don't queue, reports none
+    fn inflight(&self) -> u32 {
+        0
+    }
+
+    fn get_workload(&self) -> usize;
+    /// Resizes the launch, reallocating whatever buffers depend on it. The host must `sync` first so no
+    /// launch is in flight; backends that can't resize keep the default, which fails.
+    fn set_workload(&mut self, _workload: usize) -> Result<(), Error> {
+        Err("changing the workload is unsupported by this worker".into())
+    }
+    /// Hashes one `calculate_hash` covers. Backends only look at `request_stop` between chunks of work, so
+    /// smaller batches cancel stale jobs sooner, at the cost of more launches and syncs; large ones suit
+    /// backends with a high per-launch overhead. Unlike `set_workload` this may be called between any two
+    /// launches. The default ignores the setting and reports the workload.
+    fn set_batch_size(&mut self, _hashes_per_launch: u64) {}
+    fn batch_size(&self) -> u64 {
+        self.get_workload() as u64
+    }
+    /// Keeps the worker under `max_hs` hashes per second by sleeping between launches, leaving the workload as
+    /// is; `0` lifts the cap. The default ignores it and keeps reporting `None`, the host wraps such backends
+    /// in a `throttle::ThrottledWorker` instead.
+    fn set_hashrate_cap(&mut self, _max_hs: u64) {}
+    fn hashrate_cap(&self) -> Option<u64> {
+        None
+    }
+    /// Offsets the core and memory clocks from their defaults, e.g. through NVML or ADL, as listed for the
+    /// device in a `profile::ClockProfile`. Fails by default.
+    fn apply_clock_profile(&mut self, _core_offset_mhz: i32, _mem_offset_mhz: i32) -> Result<(), Error> {
+        Err("clock profiles are unsupported by this worker".into())
+    }
+    /// Total number of hashes evaluated since the worker was built, sampled by the host to compute the hashrate.
+    /// Backends should override this and bump the counter in `calculate_hash`; the default reports nothing.
+    fn hashes_computed(&self) -> u64 {
+        0
+    }
+    /// Whether the backend only reports nonces it already checked against the target, letting the host skip
+    /// recomputing them with `cpu::verify_nonces` (see `verified_nonces`). The default is re-checked.
+    fn self_verifies(&self) -> bool {
+        false
+    }
+
+    /// Idles the worker without tearing down its device context; while paused `calculate_hash` should
+    /// return without launching anything. The host may call `pause`/`resume` from a different thread than
+    /// the mining loop, so implementors need interior synchronization if their state is shared.
+    fn pause(&mut self) {}
+    fn resume(&mut self) {}
+    fn is_paused(&self) -> bool {
+        false
+    }
+    /// Asks an in-flight `calculate_hash` to return early, e.g. on shutdown or when a new block template
+    /// arrives. Backends should set a flag here (it takes `&self`, so use an atomic) and poll it while
+    /// hashing; the flag stays set until the next `load_block_constants`. The default is a no-op.
+    fn request_stop(&self) {}
+    /// Whether `request_stop` was called since the last `load_block_constants`, backends overriding
+    /// `request_stop` should report their flag here
+    fn stop_requested(&self) -> bool {
+        false
+    }
+    /// Device memory the worker allocated for its buffers, in bytes, so the host can warn before raising the
+    /// workload runs the device out of memory. `None` when the backend doesn't track it.
+    fn memory_usage_bytes(&self) -> Option<u64> {
+        None
+    }
+    /// Sensor readings for throttling and safety shutdowns, `None` when the backend has no sensor access
+    fn temperature_celsius(&self) -> Option<u32> {
+        None
+    }
+    fn power_watts(&self) -> Option<u32> {
+        None
+    }
+    /// When the last `calculate_hash` (or `sync`) completed, for a host watchdog to spot a hung kernel.
+    /// The default always reports progress, so workers that don't track it are never flagged.
+    fn last_progress(&self) -> Instant {
+        Instant::now()
+    }
+    /// The default assembles the snapshot from the other getters and reports no found nonces,
+    /// backends counting them should override it.
+    fn stats(&self) -> WorkerStats {
+        WorkerStats {
+            hashes: self.hashes_computed(),
+            found: 0,
+            workload: self.get_workload(),
+            paused: self.is_paused(),
+        }
+    }
+    /// Samples for `metrics::render_prometheus`, which adds the `worker` label. The default exposes `stats`;
+    /// backends with more to report, like per-sensor readings, override it and usually keep those too.
+    fn metrics(&self) -> Vec<metrics::Metric> {
+        metrics::from_stats(&self.stats())
+    }
+    /// Hands the worker the sink to push `events::MinerEvent`s on as they happen, e.g. a found nonce or a lost
+    /// device; the host calls it once after building the worker. The default drops it, leaving only polling.
+    fn set_event_sink(&mut self, _sink: events::EventSink) {}
+    /// The sink from `set_event_sink`, so the host loops driving the worker can report on it too
+    fn event_sink(&self) -> Option<events::EventSink> {
+        None
+    }
+    /// Writes the nonces found since the previous call into `nonces` from the front, a slot of `0` meaning
+    /// nothing was found, and returns how many were written. Clears the device-side buffer, so a nonce is
+    /// only ever reported once. Never writes more than `max_output_nonces` slots; if the device found more,
+    /// it fails with `KarlsenMinerError::OutputOverflow`.
+    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error>;
+    /// How many nonces one `copy_output_to` call can report, the host sizes its buffer to match
+    fn max_output_nonces(&self) -> usize {
+        1
+    }
+    /// Found nonces waiting for `copy_output_to`. Once this nears `max_output_nonces` the host should drain the
+    /// worker before launching it again, or the next finds overflow. The default reports none, for backends
+    /// that can't tell without reading the device buffer.
+    fn output_pending(&self) -> usize {
+        0
+    }
+    /// Like `copy_output_to`, but appends the found nonces to `found` with the id of the job they were found
+    /// for. `jobs` must be the slice last passed to `load_jobs`, the default tags its single job's nonces.
+    fn copy_job_output_to(&mut self, jobs: &[JobConstants], found: &mut Vec<FoundNonce>) -> Result<usize, Error> {
+        let job_id = match jobs {
+            [job] => job.job_id,
+            _ => return Err(format!("mining {} jobs at once is unsupported by this worker", jobs.len()).into()),
+        };
+        let mut nonces = vec![0u64; self.max_output_nonces()];
+        let count = self.copy_output_to(&mut nonces)?;
+        found.extend(nonces.iter().take(count).map(|&nonce| FoundNonce { job_id, nonce }));
+        Ok(count)
+    }
+}
+
+/// The `nonces` `worker` found for `job` that meet its target. They are recomputed on the CPU unless the worker
+/// self-verifies, in which case they're trusted as is.
+pub fn verified_nonces(worker: &dyn Worker, job: &MiningJob, nonces: &[u64]) -> Vec<u64> {
+    if worker.self_verifies() {
+        return nonces.to_vec();
+    }
+    cpu::verify_nonces(&job.header, &job.matrix, &job.target, nonces)
+}
+
+/// Sums `Worker::memory_usage_bytes` of the workers sharing a device, given with their `WorkerSpec::device_id`.
+/// Devices only running workers that don't report usage are left out.
+pub fn memory_usage_per_device<'a>(workers: impl IntoIterator<Item = (u32, &'a dyn Worker)>) -> BTreeMap<u32, u64> {
+    let mut usage = BTreeMap::new();
+    for (device_id, worker) in workers {
+        if let Some(bytes) = worker.memory_usage_bytes() {
+            *usage.entry(device_id).or_default() += bytes;
+        }
+    }
+    usage
+}
+
+/// The nonce planted by `planted_self_check`
+const SELF_CHECK_NONCE: u64 = 0x5eed_c0de_1234_abcd;
+
+/// Loads a fixed job whose target is exactly the CPU's pow hash of a planted nonce, launches `worker` with every
+/// nonce bit fixed to it and fails unless the planted nonce comes back, catching devices that launch fine but
+/// hash wrong. Workers must honour `calculate_hash`'s `nonce_mask`/`nonce_fixed` for this to work.
+pub fn planted_self_check(worker: &mut (impl Worker + ?Sized)) -> Result<(), Error> {
+    let mut header = [0u8; header::HEADER_SIZE];
+    header[..32].copy_from_slice(&[0x4b; 32]);
+    header[32..40].copy_from_slice(&1_700_000_000_000u64.to_le_bytes());
+    let matrix = header::generate_matrix(header[..32].try_into().unwrap());
+    let target = cpu::pow_hash(&header, &matrix, SELF_CHECK_NONCE).0;
+    worker.load_block_constants(&header, &matrix, &target)?;
+    worker.calculate_hash(None, 0, SELF_CHECK_NONCE);
+    events::reported_sync(worker)?;
+    let mut nonces = vec![0u64; worker.max_output_nonces()];
+    let count = worker.copy_output_to(&mut nonces)?;
+    match nonces[..count.min(nonces.len())].contains(&SELF_CHECK_NONCE) {
+        true => Ok(()),
+        false => Err(format!("{} failed its self check: the planted nonce was not found", worker.id()).into()),
+    }
+}
+
+/// Nonces a fallback `CpuWorker` hashes per launch, small enough for a launch to stay well under a second
+const CPU_FALLBACK_WORKLOAD: usize = 256;
+
+const RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Launches `worker` over the whole nonce space and waits for it. Transient errors (see
+/// `WorkerError::is_transient`) relaunch it up to `max_retries` times, doubling the pause in between;
+/// anything else, in particular `DeviceLost`, is returned right away.
+pub fn mine_with_retry(worker: &mut dyn Worker, max_retries: u32) -> Result<(), WorkerError> {
+    mine_with_backoff(worker, max_retries, RETRY_INITIAL_BACKOFF)
+}
+
+fn mine_with_backoff(worker: &mut dyn Worker, max_retries: u32, initial_backoff: Duration) -> Result<(), WorkerError> {
+    let mut backoff = initial_backoff;
+    let mut retries = 0;
+    loop {
+        worker.calculate_hash(None, u64::MAX, 0);
+        match worker.sync() {
+            Err(e) if e.is_transient() && retries < max_retries => {
+                log_warn!("{} failed, retrying in {:?}: {}", worker.id(), backoff, e);
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                retries += 1;
+            }
+            Err(e) => {
+                events::report_device_error(worker, &e);
+                return Err(e);
+            }
+            Ok(()) => return Ok(()),
+        }
+    }
+}
+
+/// The whole load, launch, sync and read cycle over `job`, calling `on_found` with every nonce found; submitting
+/// them is up to the callback. Runs until `on_found` breaks or `Worker::request_stop` is called, e.g. from the
+/// thread receiving new templates, and fails on the first error of `worker`.
+pub fn mine_loop(
+    worker: &mut dyn Worker,
+    job: &MiningJob,
+    mut on_found: impl FnMut(u64) -> ControlFlow<()>,
+) -> Result<(), Error> {
+    worker.reset_for_new_job()?;
+    worker.load_job(job)?;
+    let mut nonces = vec![0u64; worker.max_output_nonces()];
+    while !worker.stop_requested() {
+        worker.calculate_hash(None, u64::MAX, 0);
+        events::reported_sync(worker)?;
+        let count = worker.copy_output_to(&mut nonces)?;
+        for &nonce in nonces.iter().take(count).filter(|&&nonce| nonce != 0) {
+            if on_found(nonce).is_break() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Mines `job` on all `workers` for `duration`, e.g. for `--run-for` or CI, calling `on_found` with every nonce
+/// found. Once the time is up every worker is asked to stop, synced and drained, so nothing found by the last
+/// launches is lost. Workers keep one launch each in flight, which can overrun `duration` by a batch. Fails on
+/// the first error of any worker.
+pub fn run_for(
+    workers: &mut [Box<dyn Worker>],
+    job: &MiningJob,
+    duration: Duration,
+    mut on_found: impl FnMut(u64),
+) -> Result<(), Error> {
+    let deadline = Instant::now() + duration;
+    for worker in workers.iter_mut() {
+        worker.reset_for_new_job()?;
+        worker.load_job(job)?;
+        worker.calculate_hash_async(None, u64::MAX, 0);
+    }
+    let mut outputs: Vec<Vec<u64>> = workers.iter().map(|worker| vec![0; worker.max_output_nonces()]).collect();
+    let mut drain = |worker: &mut Box<dyn Worker>, nonces: &mut Vec<u64>| -> Result<(), Error> {
+        events::reported_sync(worker.as_ref())?;
+        let count = worker.copy_output_to(nonces)?;
+        nonces.iter().take(count).filter(|&&nonce| nonce != 0).for_each(|&nonce| on_found(nonce));
+        Ok(())
+    };
+    while Instant::now() < deadline {
+        for (worker, nonces) in workers.iter_mut().zip(&mut outputs) {
+            drain(worker, nonces)?;
+            worker.calculate_hash_async(None, u64::MAX, 0);
+        }
+    }
+    workers.iter().for_each(|worker| worker.request_stop());
+    for (worker, nonces) in workers.iter_mut().zip(&mut outputs) {
+        drain(worker, nonces)?;
+    }
+    Ok(())
+}
+
+/// A plugin is handed the `App` with everything registered before it and may only add to it. Checked before
+/// the conflicts, which expect the earlier arguments and subcommands to still come first.
+fn check_kept_previous(previous: &clap::App, app: &clap::App, plugin: &str) -> Result<(), Error> {
+    let args: Vec<&str> = app.get_arguments().map(|arg| arg.get_name()).collect();
+    let subcommands: Vec<&str> = app.get_subcommands().map(|subcommand| subcommand.get_name()).collect();
+    let kept_args = args.starts_with(&previous.get_arguments().map(|arg| arg.get_name()).collect::<Vec<_>>());
+    let kept_subcommands = subcommands
+        .starts_with(&previous.get_subcommands().map(|subcommand| subcommand.get_name()).collect::<Vec<_>>());
+    match kept_args && kept_subcommands {
+        true => Ok(()),
+        false => Err(format!("plugin {} dropped arguments registered before it", plugin).into()),
+    }
+}
+
+/// clap panics at `get_matches` when two arguments share a name or a long flag, without saying which plugin
+/// declared them. This runs right after a plugin augmented `app` (its arguments start at `first_new`)
+/// and reports both owners instead.
+fn check_arg_conflicts(
+    app: &clap::App,
+    first_new: usize,
+    plugin: &str,
+    owners: &[(String, &'static str)],
+) -> Result<(), Error> {
+    let args: Vec<&clap::Arg> = app.get_arguments().collect();
+    for (i, arg) in args.iter().enumerate().skip(first_new) {
+        let clash = args[..i].iter().position(|other| {
+            other.get_name() == arg.get_name() || (arg.get_long().is_some() && other.get_long() == arg.get_long())
+        });
+        if let Some(j) = clash {
+            let owner = match j >= first_new {
+                true => plugin,
+                false => owners.iter().find(|(name, _)| name == args[j].get_name()).map_or("the miner", |(_, o)| *o),
+            };
+            let flag = arg.get_long().map_or_else(|| arg.get_name().to_string(), |long| format!("--{}", long));
+            return Err(format!("argument {} of plugin {} is already declared by {}", flag, plugin, owner).into());
+        }
+    }
+    Ok(())
+}
+
+/// Like `check_arg_conflicts` for the subcommands `plugin` added from `first_new` on, which clap would also
+/// only reject at `get_matches`
+fn check_subcommand_conflicts(
+    app: &clap::App,
+    first_new: usize,
+    plugin: &str,
+    owners: &[PluginSubcommand],
+) -> Result<(), Error> {
+    let subcommands: Vec<&clap::App> = app.get_subcommands().collect();
+    for (i, subcommand) in subcommands.iter().enumerate().skip(first_new) {
+        let name = subcommand.get_name();
+        if let Some(j) = subcommands[..i].iter().position(|other| other.get_name() == name) {
+            let owner = match j >= first_new {
+                true => plugin,
+                false => owners.iter().find(|owned| owned.name == name).map_or("the miner", |owned| owned.plugin),
+            };
+            return Err(format!("subcommand {} of plugin {} is already declared by {}", name, plugin, owner).into());
+        }
+    }
+    Ok(())
+}
+
+/// The host can't narrow down `ArgMatches`, so the arguments `plugin` owns are replayed from `matchs` into an
+/// `App` declaring only plugin arguments. Values given on the command line are replayed as such and defaults are
+/// declared as defaults again, so `occurrences_of` tells the two apart like it does on `matchs`. Other plugins'
+/// arguments are declared but never set, reading them yields nothing, which keeps plugins from depending on each
+/// other's options. Argument groups aren't carried over. `None` for plugins owning no arguments, such as built-in
+/// ones, which get `matchs` as is.
+fn plugin_matches(
+    plugin: &str,
+    owners: &[(String, &'static str)],
+    valued_args: &[(String, Vec<String>)],
+    matchs: &ArgMatches,
+) -> Result<Option<ArgMatches>, Error> {
+    if owners.iter().all(|(_, owner)| *owner != plugin) {
+        return Ok(None);
+    }
+    let mut app = clap::App::new(plugin);
+    let mut tokens = vec![plugin.to_string()];
+    for (name, owner) in owners {
+        let arg = clap::Arg::new(name.as_str()).long(name).multiple_occurrences(true);
+        let owned = *owner == plugin;
+        match valued_args.iter().find(|(valued, _)| valued == name) {
+            Some((_, defaults)) => {
+                let mut arg = arg.takes_value(true).allow_hyphen_values(true);
+                if owned && matchs.occurrences_of(name) > 0 {
+                    let values = matchs.values_of(name).into_iter().flatten();
+                    tokens.extend(values.map(|value| format!("--{}={}", name, value)));
+                } else if owned && !defaults.is_empty() {
+                    arg = arg.default_values(&defaults.iter().map(String::as_str).collect::<Vec<_>>());
+                }
+                app = app.arg(arg);
+            }
+            None => {
+                app = app.arg(arg);
+                if owned {
+                    tokens.extend((0..matchs.occurrences_of(name)).map(|_| format!("--{}", name)));
+                }
+            }
+        }
+    }
+    Ok(Some(app.try_get_matches_from(tokens)?))
+}
+
+/// An anonymous in-memory file holding `bytes`
+#[cfg(target_os = "linux")]
+fn memory_file(bytes: &[u8], name: &str) -> Result<std::fs::File, Error> {
+    use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+
+    let name = std::ffi::CString::new(name)?;
+    let fd = memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC)?;
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    file.write_all(bytes)?;
+    Ok(file)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn memory_file(_bytes: &[u8], _name: &str) -> Result<std::fs::File, Error> {
+    Err("loading plugins from memory is only supported on Linux".into())
+}
+
+/// Files in `dir` carrying the platform's shared library extension, sorted so loading is deterministic
+fn plugin_candidates(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut candidates = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION) {
+            candidates.push(path);
+        }
+    }
+    candidates.sort();
+    Ok(candidates)
+}
+
+/// The whole startup sequence in order: loads the plugins in `plugin_paths` into `base_app`, parses `argv`
+/// (program name first) against the augmented `App`, processes the options and builds the worker specs.
+/// The specs run code from the plugins' libraries, so they must be dropped before the manager.
+pub fn init_from_args(
+    base_app: clap::App,
+    plugin_paths: &[String],
+    argv: &[String],
+) -> Result<(PluginManager, Vec<Box<dyn WorkerSpec>>), Error> {
+    let (app, manager) = load_plugins(base_app, plugin_paths)?;
+    init_manager(app, manager, argv)
+}
+
+/// `init_from_args` past loading, for a manager that may also hold built-in plugins
+fn init_manager(
+    app: clap::App,
+    mut manager: PluginManager,
+    argv: &[String],
+) -> Result<(PluginManager, Vec<Box<dyn WorkerSpec>>), Error> {
+    let matches = app.try_get_matches_from(argv)?;
+    manager.process_options(&matches)?;
+    let specs = manager.build()?;
+    Ok((manager, specs))
+}
+
+/// Loads every plugin in `paths`, returning `app` augmented with their arguments alongside the manager.
+/// The arguments each plugin registered can be listed with `PluginManager::plugin_args`. A plugin that fails to
+/// load, whether it errors out, panics or mangles the `App`, leaves it as the plugins before it made it.
+pub fn load_plugins<'help>(
+    app: clap::App<'help>,
+    paths: &[String],
+) -> Result<(clap::App<'help>, PluginManager), Error> {
+    let mut factory = PluginManager::with_capacity(paths.len());
+    let mut app = app;
+    // dlopen in parallel, then construct the plugins in the order they were given
+    for (path, opened) in paths.iter().zip(OpenedPlugin::open_all(paths)) {
+        let loaded = match opened {
+            Ok(opened) => unsafe { factory.register_opened(app, path, opened) },
+            Err(e) => Err((app, e.into())),
         };
+        app = loaded.unwrap_or_else(|(app, e)| {
+            log_warn!("Failed loading plugin {} (ignore if you do not intend to use): {}", path, e);
+            app
+        });
     }
     Ok((app, factory))
 }
 
 #[macro_export]
+/// Exports the plugin entry points for `$plugin_type`. Every `$args` type adds its arguments to the host's `App`,
+/// in the order given, so a plugin can split its options into several groups. The generated `_plugin_create`
+/// takes back the host's boxed `App` and always hands one back boxed, augmented or not, as the loader expects.
+///
+/// `@create` only generates the constructor, under the given name, e.g. to call it from tests.
 macro_rules! declare_plugin {
-    ($plugin_type:ty, $constructor:path, $args:ty) => {
-        use clap::Args;
+    ($plugin_type:ty, $constructor:path, $($args:ty),+ $(,)?) => {
         #[no_mangle]
-        pub unsafe extern "C" fn _plugin_create(
+        #[allow(non_upper_case_globals)]
+        pub static _plugin_abi_version: u32 = $crate::PLUGIN_ABI_VERSION;
+
+        $crate::declare_plugin!(@create #[no_mangle] pub _plugin_create, $plugin_type, $constructor, $($args),+);
+    };
+    // `$augment` turns the host's `$app` into the augmented one, `$name` is the plugin's `Plugin::name`
+    (@create $(#[$attr:meta])* $vis:vis $create:ident, $plugin_type:ty, $constructor:path,
+        |$app:ident, $name:ident| $augment:block) => {
+        $(#[$attr])*
+        #[allow(improper_ctypes_definitions)] // Both sides are Rust, see `PluginCreate`
+        $vis unsafe extern "C" fn $create(
             app: *mut clap::App,
         ) -> (*mut clap::App, *mut dyn $crate::Plugin, *const $crate::Error) {
             // make sure the constructor is the correct type.
             let constructor: fn() -> Result<$plugin_type, $crate::Error> = $constructor;
+            unsafe {
+                $crate::create_plugin(app, constructor, |$app, $name| $augment)
+            }
+        }
+    };
+    (@create $(#[$attr:meta])* $vis:vis $create:ident, $plugin_type:ty, $constructor:path, $($args:ty),+ $(,)?) => {
+        $crate::declare_plugin!(@create $(#[$attr])* $vis $create, $plugin_type, $constructor, |app, _name| {
+            $(let app = <$args as clap::Args>::augment_args(app);)+
+            app
+        });
+    };
+}
 
-            let object = match constructor() {
-                Ok(obj) => obj,
-                Err(e) => {
-                    return (
-                        app,
-                        unsafe { std::mem::MaybeUninit::zeroed().assume_init() }, // Translates to null pointer
-                        Box::into_raw(Box::new(e)),
-                    );
-                }
-            };
+#[macro_export]
+/// Like `declare_plugin!`, but the `$args` go under a subcommand named after `Plugin::name` instead of the top
+/// level, e.g. `karlsen-miner cuda --device 0`, which keeps the main help readable with many backends. The
+/// plugin is handed that subcommand's matches; `PluginManager::process_options` has the details.
+macro_rules! declare_plugin_subcommand {
+    ($plugin_type:ty, $constructor:path, $($args:ty),+ $(,)?) => {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static _plugin_abi_version: u32 = $crate::PLUGIN_ABI_VERSION;
 
-            let boxed: Box<dyn $crate::Plugin> = Box::new(object);
+        $crate::declare_plugin_subcommand!(@create #[no_mangle] pub _plugin_create, $plugin_type, $constructor, $($args),+);
+    };
+    (@create $(#[$attr:meta])* $vis:vis $create:ident, $plugin_type:ty, $constructor:path, $($args:ty),+ $(,)?) => {
+        $crate::declare_plugin!(@create $(#[$attr])* $vis $create, $plugin_type, $constructor, |app, name| {
+            let subcommand = clap::App::new(name);
+            $(let subcommand = <$args as clap::Args>::augment_args(subcommand);)+
+            app.subcommand(subcommand)
+        });
+    };
+}
 
-            let boxed_app = Box::new(<$args>::augment_args(unsafe { *Box::from_raw(app) }));
-            (Box::into_raw(boxed_app), Box::into_raw(boxed), std::ptr::null::<Error>())
-        }
+#[cfg(test)]
+mod tests {
+    use crate::testing::NullWorker;
+    use crate::{
+        abi_version, check_abi_version, check_arg_conflicts, check_workload, construct_plugin, events, init_from_args,
+        init_manager, load_plugins, memory_usage_per_device, mine_loop, mine_with_backoff, mine_with_retry, partition,
+        partition_weighted, plugin_candidates, plugin_matches, respawn, run_for, verified_nonces, DeviceInfo, Error,
+        FoundNonce, JobConstants, KarlsenMinerError, MiningJob, NonceRange, OpenedPlugin, Plugin, PluginCapabilities,
+        PluginConfig, PluginCreate, PluginManager, RawDevice, Worker, WorkerError, WorkerSpec, WorkerStats,
     };
+    use clap::ArgMatches;
+    use sha2::{Digest, Sha256};
+    use std::collections::{BTreeMap, HashMap};
+    use std::error::Error as _;
+    use std::ops::ControlFlow;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    struct StubPlugin {
+        name: &'static str,
+        specs: usize,
+        priority: i32,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl StubPlugin {
+        fn named(name: &'static str) -> Self {
+            StubPlugin { name, specs: 0, priority: 0, dropped: Arc::new(AtomicBool::new(false)) }
+        }
+
+        fn with_specs(mut self, specs: usize) -> Self {
+            self.specs = specs;
+            self
+        }
+
+        fn with_priority(mut self, priority: i32) -> Self {
+            self.priority = priority;
+            self
+        }
+    }
+
+    impl Drop for StubPlugin {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    impl Plugin for StubPlugin {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn enabled(&self) -> bool {
+            true
+        }
+
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            if self.name.starts_with("panicking") {
+                panic!("device query failed");
+            }
+            (0..self.specs)
+                .map(|i| Box::new(StubSpec { id: format!("{}-{}", self.name, i) }) as Box<dyn WorkerSpec>)
+                .collect()
+        }
+
+        fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+            if self.name.starts_with("panicking-options") {
+                panic!("bad option");
+            }
+            if self.name.starts_with("invalid") {
+                return Err("--stub-workload must be positive".into());
+            }
+            Ok(self.specs)
+        }
+
+        fn validate(&self) -> Result<(), Error> {
+            match self.specs {
+                0..=8 => Ok(()),
+                specs => Err(format!("at most 8 stub devices are supported, got {}", specs).into()),
+            }
+        }
+    }
+
+    /// Keeps the configured `pool.user` where the test can see it
+    struct ConfigPlugin {
+        user: Arc<Mutex<Option<String>>>,
+    }
+
+    impl Plugin for ConfigPlugin {
+        fn name(&self) -> &'static str {
+            "config"
+        }
+
+        fn enabled(&self) -> bool {
+            true
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            Vec::new()
+        }
+
+        fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+            Ok(0)
+        }
+
+        fn configure(&mut self, config: &PluginConfig) -> Result<(), Error> {
+            let user = config.get("pool.user").ok_or("pool.user is required")?;
+            *self.user.lock().unwrap() = Some(user.to_string());
+            Ok(())
+        }
+    }
+
+    /// Keeps the last `--pool` it was given and the last `log.level` it was configured with
+    struct ReconfigPlugin {
+        latest: Arc<Mutex<(String, String)>>,
+    }
+
+    impl Plugin for ReconfigPlugin {
+        fn name(&self) -> &'static str {
+            "reconfig"
+        }
+
+        fn enabled(&self) -> bool {
+            true
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            Vec::new()
+        }
+
+        fn process_option(&mut self, matchs: &ArgMatches) -> Result<usize, Error> {
+            self.latest.lock().unwrap().0 = matchs.value_of("pool").unwrap_or_default().to_string();
+            Ok(0)
+        }
+
+        fn configure(&mut self, config: &PluginConfig) -> Result<(), Error> {
+            self.latest.lock().unwrap().1 = config.get("log.level").unwrap_or_default().to_string();
+            Ok(())
+        }
+    }
+
+    /// Appends "<event> <name>" to `events` on shutdown and drop
+    struct RecordingPlugin {
+        name: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Drop for RecordingPlugin {
+        fn drop(&mut self) {
+            self.events.lock().unwrap().push(format!("drop {}", self.name));
+        }
+    }
+
+    impl Plugin for RecordingPlugin {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn enabled(&self) -> bool {
+            true
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            Vec::new()
+        }
+
+        fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+            Ok(0)
+        }
+
+        fn shutdown(&mut self) -> Result<(), Error> {
+            self.events.lock().unwrap().push(format!("shutdown {}", self.name));
+            match self.name {
+                "failing" => Err("handle already closed".into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    struct VersionedPlugin;
+
+    impl Plugin for VersionedPlugin {
+        fn name(&self) -> &'static str {
+            "versioned"
+        }
+
+        fn version(&self) -> &'static str {
+            "1.2.3"
+        }
+
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities { pausable: true, self_verifying: true, ..Default::default() }
+        }
+
+        fn enabled(&self) -> bool {
+            true
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            Vec::new()
+        }
+
+        fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+            Ok(0)
+        }
+    }
+
+    /// One spec per device id below `.0`, like a backend with that many GPUs
+    struct IndexedPlugin(u32);
+
+    impl Plugin for IndexedPlugin {
+        fn name(&self) -> &'static str {
+            "indexed"
+        }
+
+        fn enabled(&self) -> bool {
+            true
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            (0..self.0).map(|device_id| Box::new(IndexedSpec(device_id)) as Box<dyn WorkerSpec>).collect()
+        }
+
+        fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+            Ok(self.0 as usize)
+        }
+    }
+
+    #[derive(Clone)]
+    struct IndexedSpec(u32);
+
+    impl WorkerSpec for IndexedSpec {
+        fn id(&self) -> String {
+            format!("gpu-{}", self.0)
+        }
+
+        fn build(&self) -> Result<Box<dyn Worker>, Error> {
+            StubSpec { id: self.id() }.build()
+        }
+
+        fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+            Box::new(self.clone())
+        }
+
+        fn device_id(&self) -> u32 {
+            self.0
+        }
+
+        /// As if higher device ids had more multiprocessors
+        fn recommended_workload(&self) -> f32 {
+            32.0 * (self.0 + 1) as f32
+        }
+    }
+
+    /// `devices` devices numbered from 0, those listed in `broken` fail to build
+    struct FlakyPlugin {
+        devices: u32,
+        broken: &'static [u32],
+    }
+
+    impl Plugin for FlakyPlugin {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn enabled(&self) -> bool {
+            true
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            (0..self.devices)
+                .map(|device_id| match self.broken.contains(&device_id) {
+                    true => Box::new(BrokenSpec(device_id)) as Box<dyn WorkerSpec>,
+                    false => Box::new(IndexedSpec(device_id)),
+                })
+                .collect()
+        }
+
+        fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+            Ok(self.devices as usize)
+        }
+    }
+
+    #[derive(Clone)]
+    struct BrokenSpec(u32);
+
+    impl WorkerSpec for BrokenSpec {
+        fn id(&self) -> String {
+            format!("gpu-{}", self.0)
+        }
+
+        fn build(&self) -> Result<Box<dyn Worker>, Error> {
+            Err(format!("device {} is lost", self.0).into())
+        }
+
+        fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+            Box::new(self.clone())
+        }
+
+        fn device_id(&self) -> u32 {
+            self.0
+        }
+    }
+
+    #[derive(Clone)]
+    struct StubSpec {
+        id: String,
+    }
+
+    impl WorkerSpec for StubSpec {
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+
+        fn opencl_platform(&self) -> Option<u16> {
+            self.id.starts_with("opencl").then_some(1)
+        }
+
+        fn build(&self) -> Result<Box<dyn Worker>, Error> {
+            if self.id.starts_with("broken") {
+                return Err("failed to allocate context".into());
+            }
+            let worker = NullWorker::new(&self.id, 1024).with_zero_matrix_rejected().with_batch_size_control();
+            Ok(Box::new(match self.id.ends_with("-lost") {
+                true => worker.with_device_lost(),
+                false => worker,
+            }))
+        }
+
+        fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+            Box::new(self.clone())
+        }
+    }
+
+    /// A `StubSpec` worker with a job loaded, so its launches hash
+    fn loaded_stub(id: &str) -> Box<dyn Worker> {
+        let mut worker = StubSpec { id: id.into() }.build().unwrap();
+        worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        worker
+    }
+
+    #[derive(Clone)]
+    struct DeviceSpec;
+
+    impl WorkerSpec for DeviceSpec {
+        fn id(&self) -> String {
+            "#3 (RTX 4090)".into()
+        }
+
+        fn build(&self) -> Result<Box<dyn Worker>, Error> {
+            StubSpec { id: self.id() }.build()
+        }
+
+        fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+            Box::new(self.clone())
+        }
+
+        fn device_id(&self) -> u32 {
+            3
+        }
+
+        fn numa_node(&self) -> Option<u32> {
+            Some(1)
+        }
+
+        fn gpu_type(&self) -> &str {
+            "CUDA"
+        }
+
+        fn workload(&self) -> f32 {
+            0.75
+        }
+    }
+
+    #[test]
+    fn test_load_missing_plugin() {
+        let mut manager = PluginManager::new();
+        let path = "/nonexistent/libmissing_plugin.so";
+        match unsafe { manager.load_single_plugin(clap::App::new("test"), path) } {
+            Ok(_) => panic!("Loading a missing plugin should fail"),
+            Err((_, e)) => assert!(e.to_string().contains(path)),
+        }
+        assert!(!manager.has_specs());
+    }
+
+    #[test]
+    fn test_drop_unloads_plugins() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let mut manager = PluginManager::new();
+        manager.plugins.push(Box::new(StubPlugin { dropped: dropped.clone(), ..StubPlugin::named("stub") }));
+        drop(manager);
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_abi_version_guard() {
+        let host = abi_version(1, 2);
+        let plugin = abi_version(1, 3);
+        assert!(check_abi_version("libstub.so", Some(host), host).is_ok());
+        assert!(check_abi_version("libstub.so", Some(plugin), host).unwrap_err().to_string().contains("ABI version"));
+        assert!(check_abi_version("libstub.so", None, host).unwrap_err().to_string().contains("too old"));
+    }
+
+    #[test]
+    fn test_plugin_names() {
+        let mut manager = PluginManager::new();
+        manager.plugins.push(Box::new(StubPlugin::named("cuda")));
+        manager.plugins.push(Box::new(StubPlugin::named("opencl")));
+        assert_eq!(manager.plugin_count(), 2);
+        assert_eq!(manager.plugin_names(), vec!["cuda", "opencl"]);
+    }
+
+    #[test]
+    fn test_build_collects_specs_from_all_plugins() {
+        let mut manager = PluginManager::new();
+        manager.plugins.push(Box::new(StubPlugin::named("cuda").with_specs(1)));
+        manager.plugins.push(Box::new(StubPlugin::named("opencl").with_specs(1)));
+        let ids: Vec<String> = manager.build().unwrap().iter().map(|spec| spec.id()).collect();
+        assert_eq!(ids, vec!["cuda-0", "opencl-0"]);
+    }
+
+    #[test]
+    fn test_reject_duplicate_plugin_path() {
+        let path = std::env::temp_dir().join("karlsen_miner_duplicate_plugin.so");
+        std::fs::write(&path, b"").unwrap();
+        let mut manager = PluginManager::new();
+        manager.plugins.push(Box::new(StubPlugin::named("cuda")));
+        manager.loaded_paths.push(std::fs::canonicalize(&path).unwrap());
+
+        let path = path.to_str().unwrap();
+        match unsafe { manager.load_single_plugin(clap::App::new("test"), path) } {
+            Ok(_) => panic!("Loading the same plugin twice should fail"),
+            Err((_, e)) => assert!(e.to_string().contains("already loaded")),
+        }
+        assert_eq!(manager.plugin_count(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unload_plugin() {
+        let mut manager = PluginManager::new();
+        let cuda = StubPlugin::named("cuda").with_specs(1);
+        let dropped = cuda.dropped.clone();
+        manager.register_plugin(Box::new(cuda));
+        manager.register_plugin(Box::new(StubPlugin::named("opencl").with_specs(1)));
+        manager.arg_owners.push(("cuda-device".into(), "cuda"));
+
+        let workers = manager.build_workers().unwrap();
+        let live = |plugin| workers.iter().filter(|worker| worker.id().starts_with(plugin)).count();
+        assert!(manager.unload_plugin("cuda", live("cuda")).unwrap_err().to_string().contains("still alive"));
+        assert_eq!(manager.plugin_names(), ["cuda", "opencl"]);
+        drop(workers);
+
+        manager.unload_plugin("cuda", 0).unwrap();
+        assert!(dropped.load(Ordering::SeqCst));
+        assert_eq!(manager.plugin_names(), ["opencl"]);
+        assert!(manager.plugin_args("cuda").is_empty());
+        assert!(manager.unload_plugin("cuda", 0).is_err());
+    }
+
+    #[test]
+    fn test_load_verified_plugin() {
+        let path = std::env::temp_dir().join("karlsen_miner_verified_plugin.so");
+        std::fs::write(&path, b"abc").unwrap();
+        let path = path.to_str().unwrap();
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let mut manager = PluginManager::new();
+
+        let (_, e) = unsafe { manager.load_single_plugin_verified(clap::App::new("test"), path, &"0".repeat(64)) }
+            .err()
+            .unwrap();
+        assert!(matches!(
+            e.downcast_ref::<KarlsenMinerError>(),
+            Some(KarlsenMinerError::ChecksumMismatch { found, .. }) if found == sha256
+        ));
+
+        // A matching hash gets the file as far as the dynamic loader, which rejects it for not being a library
+        let (_, e) =
+            unsafe { manager.load_single_plugin_verified(clap::App::new("test"), path, &sha256.to_uppercase()) }
+                .err()
+                .unwrap();
+        assert!(matches!(e.downcast_ref::<KarlsenMinerError>(), Some(KarlsenMinerError::PluginLoad { .. })));
+        assert_eq!(manager.plugin_count(), 0);
+        std::fs::remove_file(path).unwrap();
+
+        // A real library with a matching hash is opened from the bytes that were hashed and gets as far as the ABI check
+        #[cfg(target_os = "linux")]
+        {
+            let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+            let libm =
+                maps.lines().filter_map(|line| line.split_whitespace().nth(5)).find(|path| path.contains("/libm."));
+            if let Some(libm) = libm {
+                let bytes = std::fs::read(libm).unwrap();
+                let bytes_sha256 = hex::encode(Sha256::digest(&bytes));
+                std::fs::write(path, &bytes).unwrap();
+                let (_, e) =
+                    unsafe { manager.load_single_plugin_verified(clap::App::new("test"), path, &bytes_sha256) }
+                        .err()
+                        .unwrap();
+                assert!(matches!(
+                    e.downcast_ref::<KarlsenMinerError>(),
+                    Some(KarlsenMinerError::AbiMismatch { found: None, .. })
+                ));
+                std::fs::remove_file(path).unwrap();
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_load_from_memory() {
+        let mut manager = PluginManager::new();
+        let (_, e) = unsafe { manager.load_from_memory(clap::App::new("test"), b"abc", "garbage") }.err().unwrap();
+        assert!(matches!(
+            e.downcast_ref::<KarlsenMinerError>(),
+            Some(KarlsenMinerError::PluginLoad { path, .. }) if path == "memory:garbage"
+        ));
+
+        // A real library, already mapped by the test binary, opens fine but exports no ABI version
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let libm = maps.lines().filter_map(|line| line.split_whitespace().nth(5)).find(|path| path.contains("/libm."));
+        if let Some(libm) = libm {
+            let bytes = std::fs::read(libm).unwrap();
+            let (_, e) = unsafe { manager.load_from_memory(clap::App::new("test"), &bytes, "libm") }.err().unwrap();
+            assert!(matches!(
+                e.downcast_ref::<KarlsenMinerError>(),
+                Some(KarlsenMinerError::AbiMismatch { found: None, .. })
+            ));
+        }
+        assert_eq!(manager.plugin_count(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_load_two_plugins_from_memory() {
+        // Built by `cargo test` along with the other targets, next to the test binary's `deps` directory
+        let exe = std::env::current_exe().unwrap();
+        let name = format!("{}memory_plugin{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX);
+        let path = exe.parent().unwrap().parent().unwrap().join("examples").join(name);
+        let first = std::fs::read(&path).unwrap_or_else(|e| panic!("{} isn't built: {}", path.display(), e));
+        // A different library: same code, another name
+        let mut second = first.clone();
+        let (from, to) = (b"memory plugin A", b"memory plugin B");
+        let mut patched = 0;
+        for at in 0..second.len() - from.len() {
+            if second[at..at + from.len()] == from[..] {
+                second[at..at + to.len()].copy_from_slice(to);
+                patched += 1;
+            }
+        }
+        assert!(patched > 0);
+
+        // Each loaded one after the other, the first staying loaded while the second is opened
+        let mut manager = PluginManager::new();
+        let app = unsafe { manager.load_from_memory(clap::App::new("test"), &first, "first") }.ok().unwrap();
+        unsafe { manager.load_from_memory(app, &second, "second") }.ok().unwrap();
+        assert_eq!(manager.plugin_names(), ["memory plugin A", "memory plugin B"]);
+        assert_eq!(manager.loaded_paths(), [std::path::PathBuf::from("memory:first"), "memory:second".into()]);
+    }
+
+    #[test]
+    fn test_shutdown_all() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = PluginManager::new();
+        for name in ["cuda", "failing", "opencl"] {
+            manager.register_plugin(Box::new(RecordingPlugin { name, events: events.clone() }));
+        }
+        manager.shutdown_all();
+        // A failed shutdown doesn't keep the others from running
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["shutdown opencl", "shutdown failing", "shutdown cuda", "drop cuda", "drop failing", "drop opencl"]
+        );
+        assert_eq!(manager.plugin_count(), 0);
+
+        // Dropping the manager shuts down whatever is left, once
+        events.lock().unwrap().clear();
+        manager.register_plugin(Box::new(RecordingPlugin { name: "cpu", events: events.clone() }));
+        drop(manager);
+        assert_eq!(*events.lock().unwrap(), ["shutdown cpu", "drop cpu"]);
+    }
+
+    /// Records what `process_option` finds under `arg`
+    struct OptionReader {
+        name: &'static str,
+        arg: &'static str,
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Plugin for OptionReader {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn enabled(&self) -> bool {
+            true
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            Vec::new()
+        }
+
+        fn process_option(&mut self, matchs: &ArgMatches) -> Result<usize, Error> {
+            let values = matchs.values_of(self.arg).into_iter().flatten().collect::<Vec<_>>();
+            let seen = match values.is_empty() {
+                true => format!("{}x{}", self.arg, matchs.occurrences_of(self.arg)),
+                false => values.join(","),
+            };
+            self.seen.lock().unwrap().push(format!("{}: {}", self.name, seen));
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_init_from_args() {
+        let argv = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let app = || clap::App::new("test").arg(clap::Arg::new("threads").long("threads").takes_value(true));
+
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("cuda").with_specs(2)));
+        manager.register_plugin(Box::new(StubPlugin::named("opencl").with_specs(1)));
+        let (manager, specs) = init_manager(app(), manager, &argv(&["test", "--threads", "2"])).unwrap();
+        assert_eq!(specs.iter().map(|spec| spec.id()).collect::<Vec<_>>(), ["cuda-0", "cuda-1", "opencl-0"]);
+        drop(specs);
+        assert_eq!(manager.plugin_count(), 2);
+
+        // Bad arguments are caught before any plugin sees them, invalid options by `process_options`
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("cuda").with_specs(9)));
+        assert!(init_manager(app(), PluginManager::new(), &argv(&["test", "--bogus"])).is_err());
+        let e = init_manager(app(), manager, &argv(&["test"])).err().unwrap();
+        assert!(matches!(e.downcast_ref::<KarlsenMinerError>(), Some(KarlsenMinerError::InvalidConfiguration(_))));
+
+        // Plugins that fail to load are skipped, which leaves nothing to build
+        let paths = [std::env::temp_dir().join("karlsen_miner_missing_plugin.so").display().to_string()];
+        let e = init_from_args(app(), &paths, &argv(&["test"])).err().unwrap();
+        assert!(matches!(e.downcast_ref::<KarlsenMinerError>(), Some(KarlsenMinerError::NoPlugins)));
+    }
+
+    #[test]
+    fn test_plugins_see_only_their_args() {
+        let app = clap::App::new("test")
+            .arg(clap::Arg::new("cuda-device").long("cuda-device").takes_value(true).use_delimiter(true))
+            .arg(clap::Arg::new("cuda-workload").long("cuda-workload").takes_value(true).default_value("1"))
+            .arg(clap::Arg::new("opencl-enable").long("opencl-enable").multiple_occurrences(true));
+        let matches =
+            app.try_get_matches_from(["test", "--cuda-device", "0,-1", "--opencl-enable", "--opencl-enable"]).unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = PluginManager::new();
+        for (name, arg) in [("cuda", "cuda-device"), ("cuda", "cuda-workload"), ("opencl", "opencl-enable")] {
+            manager.plugins.push(Box::new(OptionReader { name, arg, seen: seen.clone() }));
+            manager.arg_owners.push((arg.to_string(), name));
+        }
+        manager
+            .valued_args
+            .extend([("cuda-device".to_string(), vec![]), ("cuda-workload".to_string(), vec!["1".into()])]);
+        // Built-in plugins own no arguments and get everything
+        manager.plugins.push(Box::new(OptionReader { name: "builtin", arg: "cuda-device", seen: seen.clone() }));
+        manager.process_options(&matches).unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["cuda: 0,-1", "cuda: 1", "opencl: opencl-enablex2", "builtin: 0,-1"]);
+
+        // A default stays a default, an explicit value stays explicit
+        let view = plugin_matches("cuda", &manager.arg_owners, &manager.valued_args, &matches).unwrap().unwrap();
+        assert_eq!((view.value_of("cuda-workload"), view.occurrences_of("cuda-workload")), (Some("1"), 0));
+        assert!(view.occurrences_of("cuda-device") > 0);
+        let given = clap::App::new("test")
+            .arg(clap::Arg::new("cuda-device").long("cuda-device").takes_value(true))
+            .arg(clap::Arg::new("cuda-workload").long("cuda-workload").takes_value(true).default_value("1"))
+            .try_get_matches_from(["test", "--cuda-workload", "1"])
+            .unwrap();
+        let view = plugin_matches("cuda", &manager.arg_owners, &manager.valued_args, &given).unwrap().unwrap();
+        assert_eq!((view.value_of("cuda-workload"), view.occurrences_of("cuda-workload")), (Some("1"), 1));
+
+        // Another plugin's argument reads as absent rather than tripping clap's debug assertions
+        seen.lock().unwrap().clear();
+        let mut manager = PluginManager::new();
+        manager.plugins.push(Box::new(OptionReader { name: "opencl", arg: "cuda-device", seen: seen.clone() }));
+        manager.arg_owners.push(("opencl-enable".to_string(), "opencl"));
+        manager.arg_owners.push(("cuda-device".to_string(), "cuda"));
+        manager.valued_args.push(("cuda-device".to_string(), vec![]));
+        manager.process_options(&matches).unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["opencl: cuda-devicex0"]);
+    }
+
+    #[test]
+    fn test_ensure_cpu_fallback() {
+        let mut manager = PluginManager::new();
+        assert!(manager.ensure_cpu_fallback());
+        assert_eq!(manager.plugin_names(), ["CPU Reference Worker"]);
+        assert!(!manager.build().unwrap().is_empty());
+        // Only when nothing else is there
+        assert!(!manager.ensure_cpu_fallback());
+        assert_eq!(manager.plugin_count(), 1);
+
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("stub").with_specs(1)));
+        assert!(!manager.ensure_cpu_fallback());
+        assert_eq!(manager.plugin_names(), ["stub"]);
+    }
+
+    #[test]
+    fn test_plugin_versions() {
+        let mut manager = PluginManager::new();
+        manager.plugins.push(Box::new(StubPlugin::named("stub")));
+        manager.plugins.push(Box::new(VersionedPlugin));
+        assert_eq!(manager.plugin_versions(), vec![("stub", "unknown"), ("versioned", "1.2.3")]);
+    }
+
+    #[test]
+    fn test_hashes_computed() {
+        let mut worker = loaded_stub("stub-0");
+        assert_eq!(worker.hashes_computed(), 0);
+        worker.calculate_hash(None, 0, 0);
+        worker.calculate_hash(None, 0, 0);
+        assert_eq!(worker.hashes_computed(), 2 * worker.get_workload() as u64);
+    }
+
+    #[test]
+    fn test_paused_worker_skips_hashing() {
+        let mut worker = loaded_stub("stub-0");
+        worker.pause();
+        assert!(worker.is_paused());
+        worker.calculate_hash(None, 0, 0);
+        assert_eq!(worker.hashes_computed(), 0);
+        worker.resume();
+        assert!(!worker.is_paused());
+        worker.calculate_hash(None, 0, 0);
+        assert_eq!(worker.hashes_computed(), worker.get_workload() as u64);
+    }
+
+    mod declared {
+        use crate::{Error, Plugin, WorkerSpec};
+        use clap::ArgMatches;
+
+        #[derive(clap::Args)]
+        struct SelectionOpt {
+            #[clap(long = "declared-device")]
+            _declared_device: Option<u16>,
+        }
+
+        #[derive(clap::Args)]
+        struct TuningOpt {
+            #[clap(long = "declared-workload")]
+            _declared_workload: Option<f32>,
+        }
+
+        pub struct DeclaredPlugin;
+
+        impl DeclaredPlugin {
+            fn new() -> Result<Self, Error> {
+                Ok(DeclaredPlugin)
+            }
+        }
+
+        impl Plugin for DeclaredPlugin {
+            fn name(&self) -> &'static str {
+                "declared"
+            }
+
+            fn enabled(&self) -> bool {
+                false
+            }
+
+            fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+                Vec::new()
+            }
+
+            fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+                Ok(0)
+            }
+        }
+
+        thread_local! {
+            pub static PANIC_ON_AUGMENT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+        }
+
+        /// Adds nothing, or panics if the calling thread set `PANIC_ON_AUGMENT`
+        pub struct NoArgs;
+
+        impl clap::FromArgMatches for NoArgs {
+            fn from_arg_matches(_matches: &ArgMatches) -> Result<Self, clap::Error> {
+                Ok(NoArgs)
+            }
+
+            fn update_from_arg_matches(&mut self, _matches: &ArgMatches) -> Result<(), clap::Error> {
+                Ok(())
+            }
+        }
+
+        impl clap::Args for NoArgs {
+            fn augment_args(app: clap::App<'_>) -> clap::App<'_> {
+                if PANIC_ON_AUGMENT.with(|panic| panic.get()) {
+                    panic!("bad argument definition");
+                }
+                app
+            }
+
+            fn augment_args_for_update(app: clap::App<'_>) -> clap::App<'_> {
+                Self::augment_args(app)
+            }
+        }
+
+        declare_plugin!(DeclaredPlugin, DeclaredPlugin::new, SelectionOpt, NoArgs, TuningOpt);
+    }
+
+    #[test]
+    fn test_declare_plugin_with_arg_groups() {
+        let (app, plugin, error) = unsafe { declared::_plugin_create(Box::into_raw(Box::new(clap::App::new("test")))) };
+        assert!(error.is_null());
+        let app = unsafe { *Box::from_raw(app) };
+        let plugin = unsafe { Box::from_raw(plugin) };
+        assert_eq!(plugin.name(), "declared");
+        let args: Vec<_> = app.get_arguments().map(|arg| arg.get_name()).collect();
+        // Both groups, in declaration order, after clap's own arguments
+        assert!(args.ends_with(&["declared-device", "declared-workload"]));
+        assert!(app.try_get_matches_from(["test", "--declared-device", "1", "--declared-workload", "0.5"]).is_ok());
+    }
+
+    /// Fails like a plugin whose constructor errors out, handing back the `App` it was given
+    #[allow(improper_ctypes_definitions)]
+    unsafe extern "C" fn failing_plugin_create(app: *mut clap::App) -> (*mut clap::App, *mut dyn Plugin, *const Error) {
+        let app = *Box::from_raw(app);
+        let e: Error = "no devices".into();
+        let null: *mut StubPlugin = std::ptr::null_mut();
+        (Box::into_raw(Box::new(app)), null, Box::into_raw(Box::new(e)))
+    }
+
+    /// Under Miri or valgrind, any leak or double free in the handoff shows up as an error
+    #[test]
+    fn test_construct_plugin_round_trips() {
+        let mut app = clap::App::new("test").arg(clap::Arg::new("host-arg").long("host-arg"));
+        let host_args = app.get_arguments().count();
+        for _ in 0..100 {
+            let (returned, plugin) = unsafe { construct_plugin(declared::_plugin_create, app.clone()) };
+            assert_eq!(plugin.unwrap().name(), "declared");
+            assert!(returned.get_arguments().count() > host_args);
+
+            let (returned, plugin) = unsafe { construct_plugin(failing_plugin_create, app) };
+            assert_eq!(plugin.err().unwrap().to_string(), "no devices");
+            assert_eq!(returned.get_arguments().count(), host_args);
+            app = returned;
+        }
+    }
+
+    mod subcommand_declared {
+        use crate::{Error, Plugin, WorkerSpec};
+        use clap::{ArgMatches, FromArgMatches};
+        use std::cell::RefCell;
+
+        #[derive(clap::Args)]
+        pub struct SubOpt {
+            #[clap(long = "device")]
+            pub device: Option<u16>,
+            #[clap(long = "workload", default_value = "64")]
+            pub workload: f32,
+        }
+
+        thread_local! {
+            /// `device`/`workload` as seen by every `process_option` call on this thread
+            pub static SEEN: RefCell<Vec<(Option<u16>, f32)>> = const { RefCell::new(Vec::new()) };
+        }
+
+        pub struct SubcommandPlugin;
+
+        impl SubcommandPlugin {
+            fn new() -> Result<Self, Error> {
+                Ok(SubcommandPlugin)
+            }
+        }
+
+        impl Plugin for SubcommandPlugin {
+            fn name(&self) -> &'static str {
+                "sub"
+            }
+
+            fn enabled(&self) -> bool {
+                false
+            }
+
+            fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+                Vec::new()
+            }
+
+            fn process_option(&mut self, matchs: &ArgMatches) -> Result<usize, Error> {
+                let opts = SubOpt::from_arg_matches(matchs)?;
+                SEEN.with(|seen| seen.borrow_mut().push((opts.device, opts.workload)));
+                Ok(0)
+            }
+        }
+
+        declare_plugin_subcommand!(@create pub create, SubcommandPlugin, SubcommandPlugin::new, SubOpt);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_declare_plugin_subcommand() {
+        let lib = libloading::Library::from(libloading::os::unix::Library::this());
+        let opened = OpenedPlugin {
+            canonical: "subcommand".into(),
+            lib,
+            constructor: subcommand_declared::create,
+            memory_file: None,
+        };
+        let host = clap::App::new("test").arg(clap::Arg::new("host-arg").long("host-arg"));
+        let mut manager = PluginManager::new();
+        let app = unsafe { manager.register_opened(host, "subcommand", opened) }.ok().unwrap();
+
+        // The plugin's arguments only exist under its subcommand
+        assert!(!app.get_arguments().any(|arg| arg.get_name() == "device"));
+        let subcommand = app.find_subcommand("sub").unwrap();
+        let args: Vec<_> = subcommand.get_arguments().map(|arg| arg.get_name()).collect();
+        assert!(args.ends_with(&["device", "workload"]));
+        assert!(app.clone().try_get_matches_from(["test", "--device", "0"]).is_err());
+
+        let matches = app.clone().try_get_matches_from(["test", "--host-arg", "sub", "--device", "3"]).unwrap();
+        manager.process_options(&matches).unwrap();
+        // Without the subcommand the plugin gets its defaults
+        let matches = app.try_get_matches_from(["test"]).unwrap();
+        manager.process_options(&matches).unwrap();
+        subcommand_declared::SEEN.with(|seen| assert_eq!(*seen.borrow(), [(Some(3), 64.0), (None, 64.0)]));
+    }
+
+    /// Constructs fine but hands back a fresh `App` instead of augmenting the host's
+    #[allow(improper_ctypes_definitions)]
+    unsafe extern "C" fn dropping_plugin_create(
+        app: *mut clap::App,
+    ) -> (*mut clap::App, *mut dyn Plugin, *const Error) {
+        drop(Box::from_raw(app));
+        let app = clap::App::new("stripped").arg(clap::Arg::new("stripped-arg").long("stripped-arg"));
+        let plugin: Box<dyn Plugin> = Box::new(StubPlugin::named("dropping"));
+        (Box::into_raw(Box::new(app)), Box::into_raw(plugin), std::ptr::null())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_load_keeps_earlier_args() {
+        let host = clap::App::new("test").arg(clap::Arg::new("host-arg").long("host-arg"));
+        let mut manager = PluginManager::new();
+        let mut app = host;
+        let mut errors = Vec::new();
+        for (name, constructor) in [
+            ("declared", declared::_plugin_create as PluginCreate),
+            ("failing", failing_plugin_create as PluginCreate),
+            ("dropping", dropping_plugin_create as PluginCreate),
+        ] {
+            let lib = libloading::Library::from(libloading::os::unix::Library::this());
+            let opened = OpenedPlugin { canonical: name.into(), lib, constructor, memory_file: None };
+            app = match unsafe { manager.register_opened(app, name, opened) } {
+                Ok(app) => app,
+                Err((app, e)) => {
+                    errors.push(e.to_string());
+                    app
+                }
+            };
+        }
+        assert_eq!(errors, ["no devices", "plugin dropping dropped arguments registered before it"]);
+        assert_eq!(manager.plugin_names(), ["declared"]);
+        let args: Vec<_> = app.get_arguments().map(|arg| arg.get_name()).collect();
+        // clap's own arguments, the host's and the loaded plugin's, none from the plugin that dropped them
+        assert_eq!(args, ["help", "version", "host-arg", "declared-device", "declared-workload"]);
+        assert!(app.try_get_matches_from(["test", "--host-arg", "--declared-device", "1"]).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_loaded_paths() {
+        let dir = std::env::temp_dir().join("karlsen_miner_loaded_paths");
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = [dir.join("libdeclared.so"), dir.join("libsubcommand.so")];
+        let mut manager = PluginManager::new();
+        let mut app = clap::App::new("test");
+        for (path, constructor) in
+            paths.iter().zip([declared::_plugin_create as PluginCreate, subcommand_declared::create as PluginCreate])
+        {
+            std::fs::write(path, b"").unwrap();
+            // Loaded through a `..` so the reported path has to be the canonical one
+            let given = dir.join("..").join(dir.file_name().unwrap()).join(path.file_name().unwrap());
+            let lib = libloading::Library::from(libloading::os::unix::Library::this());
+            let canonical = std::fs::canonicalize(&given).unwrap();
+            let opened = OpenedPlugin { canonical, lib, constructor, memory_file: None };
+            app = unsafe { manager.register_opened(app, given.to_str().unwrap(), opened) }.ok().unwrap();
+        }
+        let expected: Vec<_> = paths.iter().map(|path| std::fs::canonicalize(path).unwrap()).collect();
+        assert_eq!(manager.loaded_paths(), expected);
+        assert_eq!(manager.plugin_names(), ["declared", "sub"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_declare_plugin_keeps_app_on_augment_panic() {
+        let host = clap::App::new("test").arg(clap::Arg::new("host-arg").long("host-arg"));
+        declared::PANIC_ON_AUGMENT.with(|panic| panic.set(true));
+        let (app, plugin, error) = unsafe { declared::_plugin_create(Box::into_raw(Box::new(host))) };
+        declared::PANIC_ON_AUGMENT.with(|panic| panic.set(false));
+        assert!(plugin.is_null());
+        let error = unsafe { *Box::from_raw(error as *mut Error) };
+        assert_eq!(error.to_string(), "plugin panicked while adding its arguments");
+        // The host's own arguments are there, none of the plugin's
+        let app = unsafe { *Box::from_raw(app) };
+        let args: Vec<_> = app.get_arguments().map(|arg| arg.get_name()).collect();
+        assert!(args.ends_with(&["host-arg"]));
+        assert!(app.try_get_matches_from(["test", "--host-arg"]).is_ok());
+    }
+
+    #[test]
+    fn test_mine_with_retry() {
+        let flaky = |failures, fail_with| {
+            let mut worker = NullWorker::new("flaky", 1).with_flaky_sync(failures, fail_with);
+            worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+            worker
+        };
+        let backoff = Duration::from_millis(1);
+
+        let mut worker = flaky(2, WorkerError::KernelFailure);
+        assert!(mine_with_backoff(&mut worker, 3, backoff).is_ok());
+        assert_eq!(worker.launches(), 3);
+
+        let mut worker = flaky(2, WorkerError::Timeout);
+        assert!(
+            matches!(mine_with_backoff(&mut worker, 1, backoff), Err(WorkerError::Timeout(msg)) if msg == "launch 2")
+        );
+        assert_eq!(worker.launches(), 2);
+
+        // Retrying a dead device is pointless
+        let mut worker = flaky(1, WorkerError::DeviceLost);
+        assert!(matches!(mine_with_retry(&mut worker, 5), Err(WorkerError::DeviceLost(_))));
+        assert_eq!(worker.launches(), 1);
+    }
+
+    #[test]
+    fn test_sync_device_lost() {
+        let worker = StubSpec { id: "stub-lost".into() }.build().unwrap();
+        assert!(matches!(worker.sync(), Err(WorkerError::DeviceLost(_))));
+        assert!(StubSpec { id: "stub-0".into() }.build().unwrap().sync().is_ok());
+
+        let boxed: Error = "driver error".into();
+        let err: WorkerError = boxed.into();
+        assert!(matches!(err, WorkerError::Other(_)));
+        assert_eq!(err.to_string(), "driver error");
+    }
+
+    #[test]
+    fn test_spec_metadata() {
+        let spec = DeviceSpec;
+        assert_eq!((spec.device_id(), spec.gpu_type(), spec.workload()), (3, "CUDA", 0.75));
+        assert_eq!(spec.numa_node(), Some(1));
+        let spec = StubSpec { id: "stub-0".into() };
+        assert_eq!((spec.device_id(), spec.gpu_type(), spec.workload()), (0, "unknown", 1.0));
+        assert_eq!(spec.numa_node(), None);
+    }
+
+    #[derive(Clone)]
+    struct WorkloadSpec {
+        workload: f32,
+        absolute: bool,
+    }
+
+    impl WorkerSpec for WorkloadSpec {
+        fn id(&self) -> String {
+            "workload".into()
+        }
+
+        fn build(&self) -> Result<Box<dyn Worker>, Error> {
+            StubSpec { id: self.id() }.build()
+        }
+
+        fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+            Box::new(self.clone())
+        }
+
+        fn workload(&self) -> f32 {
+            self.workload
+        }
+
+        fn workload_is_absolute(&self) -> bool {
+            self.absolute
+        }
+    }
+
+    #[test]
+    fn test_respawn_keeps_config() {
+        let spec = StubSpec { id: "gpu".into() };
+        let mut previous = spec.build().unwrap();
+        previous.set_workload(4096).unwrap();
+        previous.set_batch_size(512);
+        previous.set_nonce_endianness(false);
+        // What the device reports once it's gone
+        let mut lost =
+            NullWorker::new("gpu-lost", previous.get_workload()).with_device_lost().with_batch_size_control();
+        lost.set_batch_size(previous.batch_size());
+        lost.set_nonce_endianness(previous.nonce_endianness());
+        lost.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        lost.calculate_hash(None, 0, 0);
+        assert!(matches!(lost.sync(), Err(WorkerError::DeviceLost(_))));
+
+        let worker = respawn(&spec, &lost).unwrap();
+        assert_eq!(worker.id(), "gpu");
+        assert_eq!(worker.get_workload(), 4096);
+        assert_eq!(worker.batch_size(), 512);
+        assert!(!worker.nonce_endianness());
+        assert_eq!(worker.hashes_computed(), 0);
+        worker.sync().unwrap();
+
+        let e = respawn(&StubSpec { id: "broken".into() }, &lost).err().unwrap();
+        assert_eq!(e.to_string(), "failed to allocate context");
+    }
+
+    #[test]
+    fn test_check_workload_relative() {
+        assert!(!StubSpec { id: "stub-0".into() }.workload_is_absolute());
+        for workload in [0.25, 1.0, 64.0] {
+            assert!(check_workload(&WorkloadSpec { workload, absolute: false }).is_ok());
+        }
+        for workload in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let e = check_workload(&WorkloadSpec { workload, absolute: false }).unwrap_err();
+            assert!(e.to_string().starts_with("relative workload of workload must be positive"));
+        }
+    }
+
+    #[test]
+    fn test_check_workload_absolute() {
+        for workload in [1.0, 1024.0, 4_194_304.0] {
+            assert!(check_workload(&WorkloadSpec { workload, absolute: true }).is_ok());
+        }
+        // Fractions of a device only make sense for relative workloads
+        for workload in [0.5, 0.0, 1024.5, f32::INFINITY] {
+            let e = check_workload(&WorkloadSpec { workload, absolute: true }).unwrap_err();
+            assert!(e.to_string().starts_with("absolute workload of workload must be a whole number"));
+        }
+    }
+
+    #[test]
+    fn test_build_filtered() {
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(IndexedPlugin(3)));
+        let ids: Vec<_> = manager.build_filtered(&[0, 2]).unwrap().iter().map(|spec| spec.id()).collect();
+        assert_eq!(ids, ["gpu-0", "gpu-2"]);
+        assert!(manager.build_filtered(&[]).unwrap().is_empty());
+
+        let e = manager.build_filtered(&[1, 3]).err().unwrap();
+        assert_eq!(e.to_string(), "no device with id 3 was found");
+    }
+
+    #[test]
+    fn test_enumerate_devices() {
+        let mut manager = PluginManager::new();
+        manager.plugins.push(Box::new(StubPlugin::named("cuda").with_specs(2)));
+        manager.plugins.push(Box::new(StubPlugin::named("opencl")));
+        let device = |name: &str| DeviceInfo {
+            plugin: "cuda",
+            device_id: 0,
+            name: name.into(),
+            workload: 1.0,
+            recommended_workload: 1.0,
+            opencl_platform: None,
+        };
+        assert_eq!(manager.enumerate(), vec![device("cuda-0"), device("cuda-1")]);
+    }
+
+    /// Sees two cards but was configured for none
+    struct DetectingPlugin;
+
+    impl Plugin for DetectingPlugin {
+        fn name(&self) -> &'static str {
+            "detecting"
+        }
+
+        fn enabled(&self) -> bool {
+            false
+        }
+
+        fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+            Vec::new()
+        }
+
+        fn available_devices(&self) -> Vec<RawDevice> {
+            vec![
+                RawDevice { id: 0, name: "RTX 3070".into(), memory_bytes: 8 << 30 },
+                RawDevice { id: 1, name: "RX 6800".into(), memory_bytes: 16 << 30 },
+            ]
+        }
+
+        fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_available_devices() {
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("cuda").with_specs(1)));
+        manager.register_plugin(Box::new(DetectingPlugin));
+        let devices = manager.available_devices();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0], ("detecting", RawDevice { id: 0, name: "RTX 3070".into(), memory_bytes: 8 << 30 }));
+        assert_eq!((devices[1].1.id, devices[1].1.memory_bytes), (1, 16 << 30));
+        // Detected devices don't turn into specs
+        assert_eq!(manager.enumerate().len(), 1);
+    }
+
+    #[test]
+    fn test_enumerate_recommended_workload() {
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(IndexedPlugin(2)));
+        let workloads: Vec<_> =
+            manager.enumerate().iter().map(|device| (device.workload, device.recommended_workload)).collect();
+        assert_eq!(workloads, [(1.0, 32.0), (1.0, 64.0)]);
+    }
+
+    #[test]
+    fn test_priority_orders_specs() {
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("opencl").with_specs(1)));
+        manager.register_plugin(Box::new(StubPlugin::named("cuda").with_specs(2).with_priority(10)));
+        manager.register_plugin(Box::new(StubPlugin::named("cpu").with_specs(1)));
+        manager.register_plugin(Box::new(StubPlugin::named("fpga").with_specs(1).with_priority(-1)));
+        let ids: Vec<_> = manager.build().unwrap().iter().map(|spec| spec.id()).collect();
+        assert_eq!(ids, ["cuda-0", "cuda-1", "opencl-0", "cpu-0", "fpga-0"]);
+        let names: Vec<_> = manager.enumerate().into_iter().map(|device| device.name).collect();
+        assert_eq!(names, ids);
+        // Everything else keeps the load order
+        assert_eq!(manager.plugin_names(), ["opencl", "cuda", "cpu", "fpga"]);
+    }
+
+    #[test]
+    fn test_enumerate_opencl_platform() {
+        let mut manager = PluginManager::new();
+        manager.plugins.push(Box::new(StubPlugin::named("cuda").with_specs(1)));
+        manager.plugins.push(Box::new(StubPlugin::named("opencl").with_specs(1)));
+        let platforms: Vec<_> =
+            manager.enumerate().iter().map(|device| (device.name.clone(), device.opencl_platform)).collect();
+        assert_eq!(platforms, [("cuda-0".to_string(), None), ("opencl-0".to_string(), Some(1))]);
+    }
+
+    #[test]
+    fn test_partition_covers_nonce_space() {
+        for total in [1, 2, 3, 7, 64] {
+            let ranges: Vec<_> = (0..total).map(|i| partition(total, i)).collect();
+            assert_eq!(ranges[0].start, 0);
+            assert_eq!(ranges[total - 1].end(), u64::MAX);
+            for pair in ranges.windows(2) {
+                assert_eq!(pair[0].end(), pair[1].start);
+                assert!(!pair[1].contains(pair[0].end() - 1));
+            }
+        }
+        assert_eq!(partition(7, 6).len, u64::MAX / 7 + u64::MAX % 7);
+    }
+
+    #[test]
+    fn test_partition_weighted() {
+        let ranges = partition_weighted(&[3, 1]);
+        assert_eq!(ranges[0], NonceRange { start: 0, len: (u64::MAX as u128 * 3 / 4) as u64 });
+        assert_eq!(ranges[1].start, ranges[0].end());
+        assert_eq!(ranges[1].end(), u64::MAX);
+
+        assert_eq!(partition_weighted(&[0, 0]), [partition(2, 0), partition(2, 1)]);
+        // A worker reporting nothing gets nothing, unless nobody reports anything
+        assert_eq!(partition_weighted(&[0, 5])[0].len, 0);
+        assert_eq!(partition_weighted(&[u64::MAX, u64::MAX])[1].end(), u64::MAX);
+        assert!(partition_weighted(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_async_launch_ordering() {
+        let mut worker = NullWorker::new("pipelined", 1).with_pipelining(2).with_found_fixed_nonce();
+        let mut nonces = vec![0u64; 1];
+        worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        worker.calculate_hash_async(None, 0, 42);
+        while !worker.try_sync().unwrap() {}
+        assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 1);
+        assert_eq!(nonces[0], 42);
+        assert_eq!(worker.calls(), ["enqueue", "calculate_hash", "try_sync", "try_sync", "try_sync", "copy_output_to"]);
+
+        // The default async path degrades to a blocking launch followed by `sync`
+        let mut worker = loaded_stub("stub-0");
+        worker.calculate_hash_async(None, 0, 0);
+        assert!(worker.try_sync().unwrap());
+        assert_eq!(worker.hashes_computed(), 1024);
+        assert!(matches!(
+            StubSpec { id: "stub-lost".into() }.build().unwrap().try_sync(),
+            Err(WorkerError::DeviceLost(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_workers_skips_failed_devices() {
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("broken").with_specs(2)));
+        manager.register_plugin(Box::new(StubPlugin::named("stub").with_specs(1)));
+        let workers = manager.build_workers().unwrap();
+        assert_eq!(workers.iter().map(|w| w.id()).collect::<Vec<_>>(), ["stub-0"]);
+
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("broken").with_specs(2)));
+        match manager.build_workers() {
+            Ok(_) => panic!("Building only broken devices should fail"),
+            Err(e) => assert!(e.to_string().contains("broken-1: failed to allocate context")),
+        }
+        // Plugins without any device are not an error
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("stub")));
+        assert!(manager.build_workers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_build_report() {
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(FlakyPlugin { devices: 4, broken: &[1, 3] }));
+        manager.register_plugin(Box::new(StubPlugin::named("panicking")));
+        let report = manager.build_report();
+        assert_eq!(report.workers.iter().map(|w| w.id()).collect::<Vec<_>>(), ["gpu-0", "gpu-2"]);
+        let failures: Vec<_> = report.failures.iter().map(|(id, e)| (*id, e.to_string())).collect();
+        assert_eq!(failures, [(1, "device 1 is lost".to_string()), (3, "device 3 is lost".to_string())]);
+
+        let report = PluginManager::new().build_report();
+        assert!(report.workers.is_empty() && report.failures.is_empty());
+    }
+
+    #[test]
+    fn test_request_stop_ends_launch_early() {
+        let mut worker = StubSpec { id: "stub-0".into() }.build().unwrap();
+        worker.request_stop();
+        worker.calculate_hash(None, 0, 0);
+        assert!(worker.hashes_computed() < 1024);
+
+        // A new job clears the request
+        worker.load_block_constants(&[0u8; 72], &[[1u16; 64]; 64], &[0u64; 4]).unwrap();
+        worker.calculate_hash(None, 0, 0);
+        assert_eq!(worker.hashes_computed(), 1024);
+    }
+
+    #[test]
+    fn test_default_manager_is_empty() {
+        for manager in [PluginManager::default(), PluginManager::with_capacity(4)] {
+            assert_eq!(manager.plugin_count(), 0);
+            assert!(!manager.has_specs());
+            assert!(manager.build().is_err());
+            assert!(manager.enumerate().is_empty());
+        }
+        assert!(PluginManager::with_capacity(4).plugins.capacity() >= 4);
+    }
+
+    #[test]
+    fn test_parallel_open_keeps_order() {
+        let paths: Vec<String> = (0..8).map(|i| format!("/nonexistent/libplugin_{}.so", i)).collect();
+        let opened = OpenedPlugin::open_all(&paths);
+        assert_eq!(opened.len(), paths.len());
+        for (path, result) in paths.iter().zip(opened) {
+            match result {
+                Ok(_) => panic!("Opening a missing plugin should fail"),
+                Err(e) => assert!(e.to_string().contains(path.as_str())),
+            }
+        }
+
+        let (_, manager) = load_plugins(clap::App::new("test"), &paths).unwrap();
+        assert_eq!(manager.plugin_count(), 0);
+    }
+
+    #[test]
+    fn test_arg_conflict_names_both_plugins() {
+        use clap::{App, Arg};
+        let owners = vec![("cuda-device".to_string(), "CUDA Worker")];
+        let app = App::new("test").arg(Arg::new("debug").long("debug")).arg(Arg::new("cuda-device").long("device"));
+        // clap registers built-in arguments such as `help` up front
+        let first_new = app.get_arguments().count();
+
+        let ok = app.clone().arg(Arg::new("opencl-device").long("opencl-device"));
+        assert!(check_arg_conflicts(&ok, first_new, "OpenCL Worker", &owners).is_ok());
+
+        let same_long = app.clone().arg(Arg::new("opencl-device").long("device"));
+        let e = check_arg_conflicts(&same_long, first_new, "OpenCL Worker", &owners).unwrap_err();
+        assert_eq!(e.to_string(), "argument --device of plugin OpenCL Worker is already declared by CUDA Worker");
+
+        let host_arg = app.arg(Arg::new("debug"));
+        let e = check_arg_conflicts(&host_arg, first_new, "OpenCL Worker", &owners).unwrap_err();
+        assert_eq!(e.to_string(), "argument debug of plugin OpenCL Worker is already declared by the miner");
+    }
+
+    #[test]
+    fn test_process_options_names_failing_plugin() {
+        let matches = clap::App::new("test").try_get_matches_from(["test"]).unwrap();
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("stub").with_specs(2)));
+        assert_eq!(manager.process_options(&matches).unwrap(), 2);
+
+        manager.register_plugin(Box::new(StubPlugin::named("invalid-stub")));
+        let e = manager.process_options(&matches).unwrap_err();
+        assert_eq!(e.to_string(), "failed processing options for invalid-stub: --stub-workload must be positive");
+    }
+
+    #[test]
+    fn test_process_options_aggregates_failures() {
+        let matches = clap::App::new("test").try_get_matches_from(["test"]).unwrap();
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("invalid-cuda")));
+        manager.register_plugin(Box::new(StubPlugin::named("stub").with_specs(1)));
+        manager.register_plugin(Box::new(StubPlugin::named("invalid-opencl")));
+        manager.register_plugin(Box::new(StubPlugin::named("panicking-options")));
+        let e = manager.process_options(&matches).unwrap_err();
+        match &e {
+            KarlsenMinerError::OptionErrors(errors) => {
+                let names: Vec<_> = errors.iter().map(|(plugin, _)| plugin.as_str()).collect();
+                assert_eq!(names, ["invalid-cuda", "invalid-opencl", "panicking-options"]);
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        assert_eq!(
+            e.to_string(),
+            "failed processing options: invalid-cuda: --stub-workload must be positive; \
+             invalid-opencl: --stub-workload must be positive; panicking-options: plugin panicking-options panicked"
+        );
+    }
+
+    #[test]
+    fn test_plugin_args() {
+        let mut manager = PluginManager::new();
+        manager.arg_owners.push(("cuda-device".into(), "CUDA Worker"));
+        manager.arg_owners.push(("opencl-device".into(), "OpenCL Worker"));
+        manager.arg_owners.push(("cuda-workload".into(), "CUDA Worker"));
+        assert_eq!(manager.plugin_args("CUDA Worker"), ["cuda-device", "cuda-workload"]);
+        assert!(manager.plugin_args("CPU Reference Worker").is_empty());
+    }
+
+    #[test]
+    fn test_reload_plugin_requires_a_library() {
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("stub")));
+        let e = unsafe { manager.reload_plugin("cuda", "/nonexistent/libcuda_plugin.so") }.unwrap_err();
+        assert_eq!(e.to_string(), "no plugin named cuda");
+
+        // Built-in plugins have no library to swap, so they are left untouched
+        let e = unsafe { manager.reload_plugin("stub", "/nonexistent/libstub_plugin.so") }.unwrap_err();
+        assert_eq!(e.to_string(), "plugin stub was not loaded from a library");
+        assert_eq!(manager.plugin_names(), ["stub"]);
+    }
+
+    #[test]
+    fn test_plugin_capabilities() {
+        assert_eq!(StubPlugin::named("stub").capabilities(), PluginCapabilities::default());
+        let caps = VersionedPlugin.capabilities();
+        assert!(caps.pausable && caps.self_verifying);
+        assert!(!caps.async_sync && !caps.nonce_ranges);
+    }
+
+    #[test]
+    fn test_error_variants() {
+        let paths = vec!["/nonexistent/libplugin.so".to_string()];
+        let e = OpenedPlugin::open_all(&paths).pop().unwrap().err().unwrap();
+        assert!(matches!(&e, KarlsenMinerError::PluginLoad { path, .. } if path == "/nonexistent/libplugin.so"));
+        assert!(e.source().is_some());
+
+        let e = KarlsenMinerError::SymbolMissing { path: "libstub.so".into(), symbol: "_plugin_create" };
+        assert_eq!(e.to_string(), "plugin at libstub.so does not export `_plugin_create`");
+
+        let e = check_abi_version("libstub.so", Some(abi_version(1, 3)), abi_version(1, 2)).unwrap_err();
+        assert!(matches!(e, KarlsenMinerError::AbiMismatch { expected: 0x10002, found: Some(0x10003), .. }));
+
+        let matches = clap::App::new("test").try_get_matches_from(["test"]).unwrap();
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("invalid-stub")));
+        let e = manager.process_options(&matches).unwrap_err();
+        assert!(matches!(e, KarlsenMinerError::OptionProcessing { plugin: "invalid-stub", .. }));
+
+        let e: KarlsenMinerError = WorkerError::Timeout("stub-0".into()).into();
+        assert!(matches!(e, KarlsenMinerError::Device(WorkerError::Timeout(_))));
+        assert_eq!(e.to_string(), "timeout: stub-0");
+
+        // Still usable through the boxed alias, and recoverable from it
+        let boxed: Error = e.into();
+        assert!(matches!(boxed.downcast_ref::<KarlsenMinerError>(), Some(KarlsenMinerError::Device(_))));
+    }
+
+    #[test]
+    fn test_worker_stats() {
+        let mut worker = loaded_stub("stub-0");
+        worker.calculate_hash(None, 0, 0);
+        worker.pause();
+        let workload = worker.get_workload();
+        assert_eq!(worker.stats(), WorkerStats { hashes: workload as u64, found: 0, workload, paused: true });
+        assert_eq!(worker.source_plugin(), "Null Worker");
+    }
+
+    #[test]
+    fn test_covered_ranges_default() {
+        let mut worker = NullWorker::new("null", 1);
+        worker.calculate_hash_range(1000, 5);
+        assert!(worker.covered_ranges().is_empty());
+    }
+
+    #[test]
+    fn test_calculate_hash_range_default() {
+        let mut worker = NullWorker::new("null", 1);
+        worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        worker.calculate_hash_range(1000, 5);
+        assert_eq!(worker.scanned_nonces(), [1000, 1001, 1002, 1003, 1004]);
+
+        worker.calculate_hash_range(u64::MAX - 1, 5);
+        assert_eq!(worker.scanned_nonces(), [u64::MAX - 1]);
+    }
+
+    #[test]
+    fn test_max_inflight() {
+        let mut worker = NullWorker::new("queue", 1).with_launch_queue();
+        worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        worker.set_max_inflight(2);
+        for _ in 0..3 {
+            worker.calculate_hash_async(None, 0, 0);
+        }
+        // The third launch was refused
+        assert_eq!((worker.inflight(), worker.launches()), (2, 2));
+        assert!(!worker.try_sync().unwrap());
+        worker.calculate_hash_async(None, 0, 0);
+        assert_eq!((worker.inflight(), worker.launches()), (2, 3));
+        worker.sync().unwrap();
+        assert_eq!(worker.inflight(), 0);
+
+        // Backends that don't queue keep the defaults
+        let mut worker = StubSpec { id: "stub-0".into() }.build().unwrap();
+        worker.set_max_inflight(1);
+        worker.calculate_hash_async(None, 0, 0);
+        assert_eq!(worker.inflight(), 0);
+    }
+
+    #[test]
+    fn test_calculate_hash_until() {
+        let mut worker = loaded_stub("stub-0");
+        let start = Instant::now();
+        worker.calculate_hash_until(start + Duration::from_millis(20)).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20) && elapsed < Duration::from_millis(500), "{:?}", elapsed);
+        assert!(worker.hashes_computed() > 0);
+
+        // A stop request ends it long before the deadline
+        let hashes = worker.hashes_computed();
+        worker.request_stop();
+        let start = Instant::now();
+        worker.calculate_hash_until(start + Duration::from_secs(60)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(worker.hashes_computed(), hashes);
+
+        let mut worker = StubSpec { id: "stub-lost".into() }.build().unwrap();
+        let e = worker.calculate_hash_until(Instant::now() + Duration::from_secs(60)).unwrap_err();
+        assert!(matches!(e, WorkerError::DeviceLost(_)));
+    }
+
+    #[test]
+    fn test_memory_usage_per_device() {
+        let workers = [
+            NullWorker::new("allocating", 1).with_memory_usage(256 << 20),
+            NullWorker::new("allocating", 1).with_memory_usage(64 << 20),
+            NullWorker::new("untracked", 1),
+        ];
+        assert_eq!(workers[0].memory_usage_bytes(), Some(256 << 20));
+        let usage = memory_usage_per_device([
+            (0, &workers[0] as &dyn Worker),
+            (0, &workers[1] as &dyn Worker),
+            (1, &workers[1] as &dyn Worker),
+            (2, &workers[2] as &dyn Worker),
+        ]);
+        assert_eq!(usage, BTreeMap::from([(0, 320 << 20), (1, 64 << 20)]));
+        assert_eq!(StubSpec { id: "stub-0".into() }.build().unwrap().memory_usage_bytes(), None);
+    }
+
+    #[test]
+    fn test_mine_loop() {
+        let job = MiningJob::new([0; 72], [[1; 64]; 64], [u64::MAX; 4], 1);
+        let mut worker = NullWorker::new("null", 16).with_found_nonce(42);
+        let mut found = Vec::new();
+        mine_loop(&mut worker, &job, |nonce| {
+            found.push(nonce);
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        assert_eq!(found, [42]);
+        assert_eq!(worker.hashes_computed(), 16);
+
+        let mut worker = NullWorker::new("stopping", 1).with_found_launch_number().with_stop_after(3);
+        worker.request_stop();
+        let mut found = Vec::new();
+        mine_loop(&mut worker, &job, |nonce| {
+            found.push(nonce);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        // The stop request from before the job was loaded doesn't count
+        assert_eq!(found, [1, 2, 3]);
+
+        let mut worker = StubSpec { id: "stub-lost".into() }.build().unwrap();
+        assert!(mine_loop(&mut *worker, &job, |_| ControlFlow::Continue(())).is_err());
+    }
+
+    #[test]
+    fn test_failed_sync_emits_device_error() {
+        let job = MiningJob::new([0; 72], [[1; 64]; 64], [u64::MAX; 4], 1);
+        let sink = events::EventSink::new();
+        let received = sink.subscribe();
+        let mut worker = NullWorker::new("null", 16).with_device_lost();
+        worker.set_event_sink(sink);
+        assert!(mine_loop(&mut worker, &job, |_| ControlFlow::Continue(())).is_err());
+        assert!(mine_with_retry(&mut worker, 3).is_err());
+
+        let device_errors: Vec<_> =
+            received.try_iter().filter(|event| matches!(event, events::MinerEvent::DeviceError { .. })).collect();
+        let expected = events::MinerEvent::DeviceError { worker: "null".into(), err: "device lost: null".into() };
+        // Reported once per failing loop, not per retry
+        assert_eq!(device_errors, [expected.clone(), expected]);
+    }
+
+    /// Finds a nonce on every launch, its number, and keeps them until drained
+    fn pending() -> NullWorker {
+        let mut worker = NullWorker::new("pending", 1).with_output_buffer(4).with_found_launch_number();
+        worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        worker
+    }
+
+    #[test]
+    fn test_job_bytes_round_trip() {
+        let mut header = [0u8; 72];
+        header.iter_mut().enumerate().for_each(|(i, byte)| *byte = i as u8);
+        let mut matrix = [[0u16; 64]; 64];
+        matrix.iter_mut().flatten().enumerate().for_each(|(i, element)| *element = (i % 16) as u16);
+        let job = MiningJob::new(header, matrix, [1, 2, 3, u64::MAX], 0x0102030405060708);
+
+        let bytes = job.to_bytes();
+        assert_eq!(bytes.len(), 8308);
+        assert_eq!(bytes[..4], *b"KMJ\x01");
+        assert_eq!(bytes[8300..], [8, 7, 6, 5, 4, 3, 2, 1]);
+        let decoded = MiningJob::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.constants(), job.constants());
+
+        let e = MiningJob::from_bytes(&bytes[..8307]).unwrap_err();
+        assert_eq!(e.to_string(), "a job is 8308 bytes, got 8307");
+        assert!(MiningJob::from_bytes(&[]).is_err());
+        let mut other_version = bytes;
+        other_version[3] = 2;
+        assert!(MiningJob::from_bytes(&other_version).unwrap_err().to_string().starts_with("not a job"));
+    }
+
+    #[test]
+    fn test_run_for() {
+        let job = MiningJob::new([0; 72], [[1; 64]; 64], [u64::MAX; 4], 1);
+        let mut workers: Vec<Box<dyn Worker>> = (0..2).map(|_| Box::new(pending()) as Box<dyn Worker>).collect();
+        let mut found = Vec::new();
+        let start = Instant::now();
+        run_for(&mut workers, &job, Duration::from_millis(30), |nonce| found.push(nonce)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        // Every launch's nonce was handed over, the last ones included
+        let launches: u64 = workers.iter().map(|worker| worker.hashes_computed()).sum();
+        assert!(launches > 2);
+        assert_eq!(found.len() as u64, launches);
+        assert!(workers.iter().all(|worker| worker.output_pending() == 0));
+    }
+
+    #[test]
+    fn test_output_pending() {
+        let mut worker = pending();
+        let mut nonces = Vec::new();
+        let mut drained = Vec::new();
+        for launch in 1..=10 {
+            worker.calculate_hash(None, 0, 0);
+            assert_eq!(worker.output_pending(), (launch - 1) % 3 + 1);
+            // Drained one short of full, the next launch could overflow it
+            if worker.output_pending() == worker.max_output_nonces() - 1 {
+                let count = worker.copy_output_to(&mut nonces).unwrap();
+                drained.extend_from_slice(&nonces[..count]);
+                assert_eq!(worker.output_pending(), 0);
+            }
+        }
+        let count = worker.copy_output_to(&mut nonces).unwrap();
+        drained.extend_from_slice(&nonces[..count]);
+        assert_eq!(drained, (1..=10).collect::<Vec<_>>());
+
+        // Without draining the buffer overflows and shares would be lost
+        for _ in 0..5 {
+            worker.calculate_hash(None, 0, 0);
+        }
+        assert!(worker.copy_output_to(&mut nonces).is_err());
+        assert_eq!(StubSpec { id: "stub-0".into() }.build().unwrap().output_pending(), 0);
+    }
+
+    #[test]
+    fn test_reset_for_new_job() {
+        let mut worker = pending();
+        worker.calculate_hash(None, 0, 0);
+        worker.calculate_hash(None, 0, 0);
+        assert_eq!(worker.output_pending(), 2);
+        worker.reset_for_new_job().unwrap();
+        assert_eq!(worker.output_pending(), 0);
+
+        // Only finds of the new job are reported afterwards
+        worker.calculate_hash(None, 0, 0);
+        let mut nonces = Vec::new();
+        assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 1);
+        assert_eq!(nonces[0], 3);
+    }
+
+    #[test]
+    fn test_self_check() {
+        NullWorker::new("planted", 1).with_found_fixed_nonce().with_planted_self_check().self_check().unwrap();
+        // Hashing wrong, it never finds the planted nonce
+        let e = NullWorker::new("planted", 1).with_planted_self_check().self_check().unwrap_err();
+        assert_eq!(e.to_string(), "planted failed its self check: the planted nonce was not found");
+        // Workers without a check of their own pass
+        StubSpec { id: "stub-0".into() }.build().unwrap().self_check().unwrap();
+    }
+
+    #[test]
+    fn test_verified_nonces() {
+        // No hash meets a zero target, so anything that is recomputed gets dropped
+        let job = MiningJob::new([0; 72], [[1; 64]; 64], [0; 4], 1);
+        let nonces = [1, 2, 3];
+        let verifying = NullWorker::new("verifying", 1).with_self_verification();
+        let unverified = NullWorker::new("unverified", 1);
+        assert_eq!(verified_nonces(&verifying, &job, &nonces), nonces);
+        assert!(verified_nonces(&unverified, &job, &nonces).is_empty());
+
+        let job = MiningJob::new([0; 72], [[1; 64]; 64], [u64::MAX; 4], 1);
+        assert_eq!(verified_nonces(&unverified, &job, &nonces), nonces);
+        assert!(!StubSpec { id: "stub-0".into() }.build().unwrap().self_verifies());
+    }
+
+    #[test]
+    fn test_clone_spec() {
+        let spec: Box<dyn WorkerSpec> = Box::new(StubSpec { id: "stub-0".into() });
+        let cached = spec.clone_spec();
+        drop(spec);
+        assert_eq!(cached.id(), "stub-0");
+        let mut first = cached.build().unwrap();
+        let second = cached.build().unwrap();
+        first.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        first.calculate_hash(None, 0, 0);
+        assert_eq!((first.hashes_computed(), second.hashes_computed()), (1024, 0));
+    }
+
+    #[test]
+    fn test_configure() {
+        let user = Arc::new(Mutex::new(None));
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("cuda")));
+        manager.register_plugin(Box::new(ConfigPlugin { user: user.clone() }));
+
+        let mut config = PluginConfig::new();
+        config.set("pool.user", "karlsen:qr0");
+        config.set("log.level", "debug");
+        manager.configure(&config).unwrap();
+        assert_eq!(user.lock().unwrap().as_deref(), Some("karlsen:qr0"));
+
+        let e = manager.configure(&PluginConfig::default()).unwrap_err();
+        assert_eq!(e.to_string(), "failed processing options for config: pool.user is required");
+
+        let config = PluginConfig::from(HashMap::from([("pool.user".to_string(), "karlsen:qr1".to_string())]));
+        manager.configure(&config).unwrap();
+        assert_eq!(user.lock().unwrap().as_deref(), Some("karlsen:qr1"));
+    }
+
+    #[test]
+    fn test_reprocess_options() {
+        let latest = Arc::new(Mutex::new((String::new(), String::new())));
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(ReconfigPlugin { latest: latest.clone() }));
+        let matches = |pool: &str| {
+            let app = clap::App::new("test").arg(clap::Arg::new("pool").long("pool").takes_value(true));
+            app.try_get_matches_from(["test", "--pool", pool]).unwrap()
+        };
+        manager.process_options(&matches("pool-a:5555")).unwrap();
+        // Nothing to configure again before `configure` ran once
+        manager.reprocess_options(&matches("pool-b:5555")).unwrap();
+        assert_eq!(*latest.lock().unwrap(), ("pool-b:5555".to_string(), String::new()));
+
+        let mut config = PluginConfig::new();
+        config.set("log.level", "debug");
+        manager.configure(&config).unwrap();
+        latest.lock().unwrap().1.clear();
+        manager.reprocess_options(&matches("pool-c:5555")).unwrap();
+        assert_eq!(*latest.lock().unwrap(), ("pool-c:5555".to_string(), "debug".to_string()));
+    }
+
+    #[test]
+    fn test_plugin_config_from_env() {
+        std::env::set_var("KARLSEN_MINER_TEST_CONFIG_POOL", "stratum+tcp://pool:5555");
+        let config = PluginConfig::from_env("KARLSEN_MINER_TEST_CONFIG_");
+        assert_eq!(config.get("POOL"), Some("stratum+tcp://pool:5555"));
+        assert_eq!(config.get("KARLSEN_MINER_TEST_CONFIG_POOL"), None);
+    }
+
+    #[test]
+    fn test_set_workload() {
+        let mut worker = loaded_stub("stub-0");
+        worker.set_workload(64).unwrap();
+        assert_eq!(worker.get_workload(), 64);
+        worker.calculate_hash(None, 0, 0);
+        assert_eq!(worker.hashes_computed(), 64);
+
+        let mut worker = NullWorker::new("null", 1).with_fixed_workload();
+        assert!(worker.set_workload(64).unwrap_err().to_string().contains("unsupported"));
+        assert_eq!(worker.get_workload(), 1);
+    }
+
+    #[test]
+    fn test_batch_size() {
+        let mut worker = StubSpec { id: "stub-0".into() }.build().unwrap();
+        assert_eq!(worker.batch_size(), 1024);
+        worker.set_batch_size(1 << 20);
+        assert_eq!(worker.batch_size(), 1 << 20);
+        // Independent of the workload
+        assert_eq!(worker.get_workload(), 1024);
+
+        let mut worker = NullWorker::new("null", 1);
+        worker.set_batch_size(4096);
+        assert_eq!(worker.batch_size(), 1);
+    }
+
+    #[test]
+    fn test_process_options_validates() {
+        let matches = clap::App::new("test").try_get_matches_from(["test"]).unwrap();
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("stub").with_specs(2)));
+        assert_eq!(manager.process_options(&matches).unwrap(), 2);
+
+        manager.register_plugin(Box::new(StubPlugin::named("big-stub").with_specs(9)));
+        manager.register_plugin(Box::new(StubPlugin::named("huge-stub").with_specs(12)));
+        let e = manager.process_options(&matches).unwrap_err();
+        match &e {
+            KarlsenMinerError::InvalidConfiguration(errors) => {
+                assert_eq!(errors.iter().map(|(plugin, _)| *plugin).collect::<Vec<_>>(), ["big-stub", "huge-stub"])
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        assert_eq!(
+            e.to_string(),
+            "invalid configuration: big-stub: at most 8 stub devices are supported, got 9; \
+             huge-stub: at most 8 stub devices are supported, got 12"
+        );
+    }
+
+    #[test]
+    fn test_plugin_panics_are_caught() {
+        let matches = clap::App::new("test").try_get_matches_from(["test"]).unwrap();
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(StubPlugin::named("stub").with_specs(1)));
+        manager.register_plugin(Box::new(StubPlugin::named("panicking").with_specs(1)));
+        // The workload check lists the specs, which is where this plugin panics
+        match manager.process_options(&matches) {
+            Err(KarlsenMinerError::InvalidConfiguration(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].0, "panicking");
+                assert_eq!(errors[0].1.to_string(), "plugin panicking panicked");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let e = manager.build().err().unwrap();
+        assert!(matches!(
+            e.downcast_ref::<KarlsenMinerError>(),
+            Some(KarlsenMinerError::PluginPanicked { plugin: "panicking" })
+        ));
+        assert_eq!(e.to_string(), "plugin panicking panicked");
+        assert!(manager.build_workers().is_err());
+        // The well behaved plugin's devices are still listed
+        assert_eq!(manager.enumerate().iter().map(|device| device.name.as_str()).collect::<Vec<_>>(), ["stub-0"]);
+
+        manager.register_plugin(Box::new(StubPlugin::named("panicking-options")));
+        assert!(matches!(
+            manager.process_options(&matches),
+            Err(KarlsenMinerError::PluginPanicked { plugin: "panicking-options" })
+        ));
+    }
+
+    #[test]
+    fn test_copy_output_reports_count() {
+        let mut worker = NullWorker::new("null", 1).with_found_fixed_nonce();
+        let mut nonces = vec![0u64; 1];
+        worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        worker.calculate_hash(None, 0, 7);
+        assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 1);
+        assert_eq!(nonces, [7]);
+        // The nonce was consumed by the first read
+        assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 0);
+        assert_eq!(nonces, [0]);
+    }
+
+    #[test]
+    fn test_load_block_constants_rejects_zero_matrix() {
+        let mut worker = StubSpec { id: "stub-0".into() }.build().unwrap();
+        let e = worker.load_block_constants(&[0u8; 72], &[[0u16; 64]; 64], &[0u64; 4]).unwrap_err();
+        assert_eq!(e.to_string(), "matrix is all zeros");
+        assert!(worker.load_block_constants(&[0u8; 72], &[[1u16; 64]; 64], &[0u64; 4]).is_ok());
+    }
+
+    #[test]
+    fn test_load_job() {
+        let mut worker = StubSpec { id: "stub-0".into() }.build().unwrap();
+        let job = MiningJob::new([7; 72], [[1; 64]; 64], [0, 0, 0, u64::MAX], 3);
+        worker.load_job(&job).unwrap();
+        assert_eq!(
+            job.constants(),
+            JobConstants { header: [7; 72], matrix: [[1; 64]; 64], target: [0, 0, 0, u64::MAX], job_id: 3 }
+        );
+        // Forwarded to `load_block_constants`, which rejects this matrix
+        let job = MiningJob { matrix: [[0; 64]; 64], ..job };
+        worker.load_job(&job).unwrap_err();
+    }
+
+    #[test]
+    fn test_load_jobs_default() {
+        let job = |job_id| JobConstants { header: [0; 72], matrix: [[1; 64]; 64], target: [0; 4], job_id };
+        let mut worker = NullWorker::new("null", 1).with_found_fixed_nonce();
+        let mut found = Vec::new();
+        assert!(worker.load_jobs(&[job(1), job(2)]).is_err());
+        worker.load_jobs(&[job(3)]).unwrap();
+        worker.calculate_hash(None, 0, 42);
+        assert_eq!(worker.copy_job_output_to(&[job(3)], &mut found).unwrap(), 1);
+        assert_eq!(found, [FoundNonce { job_id: 3, nonce: 42 }]);
+    }
+
+    #[test]
+    fn test_build_without_plugins() {
+        let manager = PluginManager::new();
+        let e = manager.build().err().unwrap();
+        assert_eq!(e.to_string(), "no mining plugins loaded; specify --gpu-plugin");
+        assert!(matches!(e.downcast_ref::<KarlsenMinerError>(), Some(KarlsenMinerError::NoPlugins)));
+        assert!(manager.build_workers().is_err());
+    }
+
+    #[test]
+    fn test_load_from_dir_skips_junk() {
+        let dir = std::env::temp_dir().join("karlsen_miner_plugin_dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        let library = |name: &str| dir.join(format!("{}.{}", name, std::env::consts::DLL_EXTENSION));
+        for path in [library("libjunk"), library("libbroken"), dir.join("README.txt")] {
+            std::fs::write(path, b"not a plugin").unwrap();
+        }
+        std::fs::create_dir_all(library("libdirectory")).unwrap();
+        assert_eq!(plugin_candidates(&dir).unwrap(), [library("libbroken"), library("libjunk")]);
+
+        let mut manager = PluginManager::new();
+        let app = unsafe { manager.load_from_dir(clap::App::new("test"), &dir) }.map_err(|(_, e)| e).unwrap();
+        assert_eq!(app.get_name(), "test");
+        assert_eq!(manager.plugin_count(), 0);
+
+        assert!(unsafe { manager.load_from_dir(app, &dir.join("missing")) }.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_output_overflow() {
+        let mut worker = NullWorker::new("buffered", 1).with_output_buffer(2).with_found_fixed_nonce();
+        worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        let mut nonces = vec![0u64; worker.max_output_nonces()];
+        worker.calculate_hash(None, 0, 5);
+        worker.calculate_hash(None, 0, 6);
+        assert_eq!(worker.output_pending(), 2);
+        assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 2);
+        assert_eq!(nonces, [5, 6]);
+
+        (7..10).for_each(|nonce| worker.calculate_hash(None, 0, nonce));
+        let e = worker.copy_output_to(&mut nonces).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<KarlsenMinerError>(),
+            Some(KarlsenMinerError::OutputOverflow { found: 3, capacity: 2 })
+        ));
+        // Nothing beyond the capacity was written
+        assert_eq!(nonces.len(), 2);
+    }
 }
@@ -0,0 +1,59 @@
+//! Parsing of the device lists operators pass on the command line, to be fed to `PluginManager::build_filtered`.
+use crate::Error;
+
+/// Expands a selection such as `0,2-4,7` into sorted device ids without duplicates. Ranges are inclusive and
+/// whitespace around ids is ignored; empty entries, malformed ids and ranges, and reversed ranges are rejected.
+pub fn parse_device_selection(spec: &str) -> Result<Vec<u32>, Error> {
+    let mut ids = Vec::new();
+    for entry in spec.split(',').map(str::trim) {
+        if entry.is_empty() {
+            return Err(format!("empty entry in device selection {:?}", spec).into());
+        }
+        let (first, last) = match entry.split_once('-') {
+            Some((first, last)) => match (first.trim().parse::<u32>(), last.trim().parse::<u32>()) {
+                (Ok(first), Ok(last)) => (first, last),
+                (Err(e), _) | (_, Err(e)) => return Err(format!("invalid device range {:?}: {}", entry, e).into()),
+            },
+            None => {
+                let id = entry.parse::<u32>().map_err(|e| format!("invalid device id {:?}: {}", entry, e))?;
+                (id, id)
+            }
+        };
+        if first > last {
+            return Err(format!("device range {:?} is reversed", entry).into());
+        }
+        ids.extend(first..=last);
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::parse_device_selection;
+
+    #[test]
+    fn test_parse_device_selection() {
+        assert_eq!(parse_device_selection("3").unwrap(), [3]);
+        assert_eq!(parse_device_selection("0,2-4,7").unwrap(), [0, 2, 3, 4, 7]);
+        assert_eq!(parse_device_selection(" 5 - 6 , 1 ").unwrap(), [1, 5, 6]);
+        assert_eq!(parse_device_selection("4-4").unwrap(), [4]);
+        // Overlapping entries are merged and sorted
+        assert_eq!(parse_device_selection("7,2-4,3,0-2").unwrap(), [0, 1, 2, 3, 4, 7]);
+    }
+
+    #[test]
+    fn test_parse_device_selection_malformed() {
+        let error = |spec| parse_device_selection(spec).unwrap_err().to_string();
+        assert_eq!(error(""), "empty entry in device selection \"\"");
+        assert_eq!(error("1,,2"), "empty entry in device selection \"1,,2\"");
+        assert_eq!(error("a"), "invalid device id \"a\": invalid digit found in string");
+        assert_eq!(error("4294967296"), "invalid device id \"4294967296\": number too large to fit in target type");
+        assert_eq!(error("2-"), "invalid device range \"2-\": cannot parse integer from empty string");
+        assert_eq!(error("-2"), "invalid device range \"-2\": cannot parse integer from empty string");
+        assert_eq!(error("1-b"), "invalid device range \"1-b\": invalid digit found in string");
+        assert_eq!(error("1-2-3"), "invalid device range \"1-2-3\": invalid digit found in string");
+        assert_eq!(error("0,4-2"), "device range \"4-2\" is reversed");
+    }
+}
@@ -0,0 +1,89 @@
+//! Host-side log events, emitted through the `log` crate with the `logging` feature and compiled out without it.
+//!
+//! The macros still type-check their arguments when disabled, so a build without the feature can't rot.
+
+#[cfg(feature = "logging")]
+macro_rules! log_info {
+    ($($arg:tt)*) => { log::info!($($arg)*) };
+}
+#[cfg(not(feature = "logging"))]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { log::warn!($($arg)*) };
+}
+#[cfg(not(feature = "logging"))]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! log_error {
+    ($($arg:tt)*) => { log::error!($($arg)*) };
+}
+#[cfg(not(feature = "logging"))]
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(all(test, feature = "logging"))]
+mod tests {
+    use crate::testing::NullPlugin;
+    use crate::PluginManager;
+    use log::{Level, Log, Metadata, Record};
+    use std::sync::Mutex;
+
+    /// Keeps every record, tests filter for their own since the logger is shared by the whole test binary
+    struct CaptureLogger {
+        records: Mutex<Vec<(Level, String)>>,
+    }
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.records.lock().unwrap().push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CaptureLogger = CaptureLogger { records: Mutex::new(Vec::new()) };
+
+    #[test]
+    fn test_plugin_load_is_logged() {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(NullPlugin::new(1, 16)));
+        let expected = format!("Registered built-in plugin Null Worker {}", env!("CARGO_PKG_VERSION"));
+        assert!(LOGGER.records.lock().unwrap().contains(&(Level::Info, expected)));
+
+        let path = std::env::temp_dir().join(format!("karlsen_miner_logged_plugin_{}.so", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        manager.loaded_paths.push(std::fs::canonicalize(&path).unwrap());
+        let path = path.to_str().unwrap();
+        assert!(unsafe { manager.load_single_plugin(clap::App::new("test"), path) }.is_err());
+        let expected = format!("Skipping plugin at {}: already loaded", path);
+        assert!(LOGGER.records.lock().unwrap().contains(&(Level::Warn, expected)));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -6,7 +6,7 @@ use std::error::Error as StdError;
 use std::ffi::OsStr;
 
 use clap::{App, FromArgMatches, IntoApp};
-use karlsen_miner::PluginManager;
+use karlsen_miner::{target, PluginConfig, PluginManager};
 use log::{error, info};
 use rand::{thread_rng, RngCore};
 use std::fs;
@@ -27,12 +27,13 @@ mod client;
 mod kaspad_messages;
 mod miner;
 mod pow;
-mod target;
 mod watch;
 
 //remove the opencl plugin support for the moment
 //const WHITELIST: [&str; 4] = ["libkaspacuda", "libkaspaopencl", "kaspacuda", "kaspaopencl"];
 const WHITELIST: [&str; 2] = ["libkaspacuda", "kaspacuda"];
+/// How long `--benchmark` measures each worker
+const BENCHMARK_DURATION: Duration = Duration::from_secs(10);
 
 pub mod proto {
     #![allow(clippy::derive_partial_eq_without_eq)]
@@ -105,7 +106,7 @@ async fn client_main(
 ) -> Result<(), Error> {
     let mut client = get_client(
         opt.karlsend_address.clone(),
-        opt.mining_address.clone(),
+        opt.mining_address.clone().unwrap_or_default(),
         opt.mine_when_not_synced,
         block_template_ctr.clone(),
     )
@@ -121,6 +122,25 @@ async fn client_main(
     Ok(())
 }
 
+/// `--benchmark`: runs `karlsen_miner::benchmark::run_benchmark` on every plugin worker in turn
+fn benchmark(plugin_manager: &PluginManager) -> Result<(), Error> {
+    let mut workers = plugin_manager.build_workers()?;
+    if workers.is_empty() {
+        return Err("No workers to benchmark".into());
+    }
+    for worker in workers.iter_mut() {
+        let result = karlsen_miner::benchmark::run_benchmark(worker.as_mut(), BENCHMARK_DURATION)?;
+        info!(
+            "{}: {:.2} Mhash/s ({} hashes in {:.1?})",
+            worker.id(),
+            result.hashes_per_sec / 1_000_000.0,
+            result.hashes,
+            result.elapsed
+        );
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     #[cfg(target_os = "windows")]
@@ -135,14 +155,20 @@ async fn main() -> Result<(), Error> {
     let matches = app.get_matches();
 
     let worker_count = plugin_manager.process_options(&matches)?;
+    // Plugin settings that aren't flags, e.g. KARLSEN_MINER_POOL_PASSWORD
+    plugin_manager.configure(&PluginConfig::from_env("KARLSEN_MINER_"))?;
     let mut opt: Opt = Opt::from_arg_matches(&matches)?;
     opt.process()?;
     env_logger::builder().filter_level(opt.log_level()).parse_default_env().init();
+    if opt.benchmark {
+        return benchmark(&plugin_manager);
+    }
     info!("=================================================================================");
     info!("                 karlsen-miner GPU {}", env!("CARGO_PKG_VERSION"));
-    info!(" Mining for: {}", opt.mining_address);
+    info!(" Mining for: {}", opt.mining_address.as_deref().unwrap_or_default());
     info!("=================================================================================");
     info!("Found plugins: {:?}", plugins);
+    info!("Loaded {} plugins: {}", plugin_manager.plugin_count(), plugin_manager.plugin_names().join(", "));
     info!("Plugins found {} workers", worker_count);
     if worker_count == 0 && opt.num_threads.unwrap_or(0) == 0 {
         error!("No workers specified");
@@ -0,0 +1,111 @@
+//! Per-worker metrics in the Prometheus text exposition format, for operators scraping the miner.
+use crate::{Worker, WorkerStats};
+use std::fmt::Write as _;
+
+/// One sample of `Worker::metrics`. The host adds the `worker` label itself when rendering.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+impl Metric {
+    pub fn new(name: &str, value: f64) -> Self {
+        Self { name: name.to_string(), labels: Vec::new(), value }
+    }
+
+    pub fn with_label(mut self, name: &str, value: &str) -> Self {
+        self.labels.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// The samples the default `Worker::metrics` reports, for overrides that add to them rather than replace them
+pub fn from_stats(stats: &WorkerStats) -> Vec<Metric> {
+    vec![
+        Metric::new("karlsen_miner_hashes_total", stats.hashes as f64),
+        Metric::new("karlsen_miner_found_total", stats.found as f64),
+        Metric::new("karlsen_miner_workload", stats.workload as f64),
+        Metric::new("karlsen_miner_paused", u8::from(stats.paused) as f64),
+    ]
+}
+
+/// Every worker's `Worker::metrics` labelled with its `Worker::id`, samples of the same metric kept together
+/// in order of first appearance
+pub fn render_prometheus(workers: &[Box<dyn Worker>]) -> String {
+    let mut families: Vec<(String, Vec<String>)> = Vec::new();
+    for worker in workers {
+        let id = worker.id();
+        for metric in worker.metrics() {
+            let labels: Vec<String> = std::iter::once(("worker", id.as_str()))
+                .chain(metric.labels.iter().map(|(name, value)| (name.as_str(), value.as_str())))
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+                .collect();
+            let line = format!("{}{{{}}} {}", metric.name, labels.join(","), format_value(metric.value));
+            match families.iter_mut().find(|(name, _)| *name == metric.name) {
+                Some((_, lines)) => lines.push(line),
+                None => families.push((metric.name, vec![line])),
+            }
+        }
+    }
+    let mut text = String::new();
+    for line in families.iter().flat_map(|(_, lines)| lines) {
+        writeln!(text, "{}", line).unwrap();
+    }
+    text
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    match value {
+        v if v.is_nan() => "NaN".into(),
+        f64::INFINITY => "+Inf".into(),
+        f64::NEG_INFINITY => "-Inf".into(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::{render_prometheus, Metric};
+    use crate::testing::NullWorker;
+    use crate::Worker;
+
+    #[test]
+    fn test_render_prometheus() {
+        let mut null = NullWorker::new("null", 256);
+        null.load_block_constants(&[0; 72], &[[0; 64]; 64], &[0; 4]).unwrap();
+        null.calculate_hash(None, u64::MAX, 0);
+        // Reports a temperature per sensor instead of the default metrics
+        let sensors = NullWorker::new("gpu \"0\"", 8).with_metrics(vec![
+            Metric::new("karlsen_miner_temperature_celsius", 61.5).with_label("sensor", "core"),
+            Metric::new("karlsen_miner_temperature_celsius", 70.0).with_label("sensor", "memory"),
+        ]);
+        let workers: Vec<Box<dyn Worker>> =
+            vec![Box::new(null), Box::new(NullWorker::new("idle", 64)), Box::new(sensors)];
+
+        let text = render_prometheus(&workers);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"karlsen_miner_hashes_total{worker="null"} 256"#,
+                r#"karlsen_miner_hashes_total{worker="idle"} 0"#,
+                r#"karlsen_miner_found_total{worker="null"} 0"#,
+                r#"karlsen_miner_found_total{worker="idle"} 0"#,
+                r#"karlsen_miner_workload{worker="null"} 256"#,
+                r#"karlsen_miner_workload{worker="idle"} 64"#,
+                r#"karlsen_miner_paused{worker="null"} 0"#,
+                r#"karlsen_miner_paused{worker="idle"} 0"#,
+                r#"karlsen_miner_temperature_celsius{worker="gpu \"0\"",sensor="core"} 61.5"#,
+                r#"karlsen_miner_temperature_celsius{worker="gpu \"0\"",sensor="memory"} 70"#,
+            ]
+        );
+        assert!(text.ends_with('\n'));
+        assert_eq!(render_prometheus(&[]), "");
+    }
+}
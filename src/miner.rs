@@ -13,7 +13,8 @@ use tokio::task::{self, JoinHandle};
 use tokio::time::MissedTickBehavior;
 
 use crate::pow::BlockSeed;
-use karlsen_miner::{PluginManager, WorkerSpec};
+use karlsen_miner::submission::SubmissionCache;
+use karlsen_miner::{topology, PluginManager, WorkerError, WorkerSpec};
 
 type MinerHandler = std::thread::JoinHandle<Result<(), Error>>;
 
@@ -227,15 +228,31 @@ impl MinerManager {
         worker_hashes_tried: Arc<AtomicU64>,
     ) -> MinerHandler {
         std::thread::spawn(move || {
-            let mut box_ = spec.build();
+            if let Some(node) = spec.numa_node() {
+                match topology::pin_thread_to_node(node) {
+                    Ok(()) => info!("{}: pinned to NUMA node {}", spec.id(), node),
+                    Err(e) => warn!("{}: failed pinning to NUMA node {}: {}", spec.id(), node, e),
+                }
+            }
+            let mut box_ = match spec.build() {
+                Ok(worker) => worker,
+                Err(e) => {
+                    error!("{}: failed to initialize, skipping device: {}", spec.id(), e);
+                    return Err(e);
+                }
+            };
             let gpu_work = box_.as_mut();
+            if let Err(e) = gpu_work.warmup() {
+                warn!("{}: warmup failed, the first job may start slowly: {}", gpu_work.id(), e);
+            }
             (|| {
                 info!("Spawned Thread for GPU {}", gpu_work.id());
-                let mut nonces = vec![0u64; 1];
+                let mut nonces = vec![0u64; gpu_work.max_output_nonces()];
+                let mut submissions = SubmissionCache::default();
 
                 let mut state = None;
 
-                loop {
+                'mining: loop {
                     nonces[0] = 0;
                     if state.is_none() {
                         state = match block_channel.wait_for_change() {
@@ -252,36 +269,60 @@ impl MinerManager {
                     }
                     let state_ref = match &state {
                         Some(s) => {
-                            s.load_to_gpu(gpu_work);
+                            if let Err(e) = s.load_to_gpu(gpu_work) {
+                                warn!("{}: rejected the block constants, skipping job: {}", gpu_work.id(), e);
+                                state = None;
+                                continue;
+                            }
                             s
                         },
                         None => continue,
                     };
                     state_ref.pow_gpu(gpu_work);
-                    if let Err(e) = gpu_work.sync() {
-                        warn!("CUDA run ignored: {}", e);
-                        continue
+                    let synced = gpu_work.sync();
+                    if let Err(e) = &synced {
+                        karlsen_miner::events::report_device_error(gpu_work, e);
+                    }
+                    match synced {
+                        Ok(()) => {}
+                        Err(WorkerError::DeviceLost(e)) => {
+                            error!("{}: device lost, stopping its thread: {}", gpu_work.id(), e);
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            warn!("CUDA run ignored: {}", e);
+                            continue
+                        }
                     }
 
-                    gpu_work.copy_output_to(&mut nonces)?;
-                    if nonces[0] != 0 {
-                        if let Some(block_seed) = state_ref.generate_block_if_pow(nonces[0]) {
-                            match send_channel.blocking_send(block_seed.clone()) {
-                                Ok(()) => block_seed.report_block(),
-                                Err(e) => error!("Failed submitting block: ({})", e.to_string()),
+                    let count = gpu_work.copy_output_to(&mut nonces)?;
+                    if count > 0 {
+                        for &nonce in nonces.iter().take(count).filter(|&&nonce| nonce != 0) {
+                            let block_seed = match state_ref.generate_block_if_pow(nonce) {
+                                Some(block_seed) => block_seed,
+                                None => {
+                                    let hash = state_ref.calculate_pow(nonce);
+                                    warn!("Something is wrong in GPU results! Got nonce {}, with hash real {:?}  (target: {}*2^196)", nonce, hash.0, state_ref.target.0[3]);
+                                    break 'mining;
+                                }
                             };
+                            if submissions.should_submit(state_ref.id as u64, nonce) {
+                                match send_channel.blocking_send(block_seed.clone()) {
+                                    Ok(()) => block_seed.report_block(),
+                                    Err(e) => error!("Failed submitting block: ({})", e.to_string()),
+                                };
+                            } else {
+                                warn!("{}: dropping nonce {} already submitted for this job", gpu_work.id(), nonce);
+                            }
+                            // A full block ends the job, the nonces left would only be stale
                             if let BlockSeed::FullBlock(_) = block_seed {
                                 state = None;
+                                break;
                             }
-                            nonces[0] = 0;
-                            hashes_tried.fetch_add(gpu_work.get_workload().try_into().unwrap(), Ordering::AcqRel);
-                            worker_hashes_tried.fetch_add(gpu_work.get_workload().try_into().unwrap(), Ordering::AcqRel);
-                            continue;
-                        } else {
-                            let hash = state_ref.calculate_pow(nonces[0]);
-                            warn!("Something is wrong in GPU results! Got nonce {}, with hash real {:?}  (target: {}*2^196)", nonces[0], hash.0, state_ref.target.0[3]);
-                            break;
                         }
+                        hashes_tried.fetch_add(gpu_work.get_workload().try_into().unwrap(), Ordering::AcqRel);
+                        worker_hashes_tried.fetch_add(gpu_work.get_workload().try_into().unwrap(), Ordering::AcqRel);
+                        continue;
                     }
 
                         /*
@@ -320,7 +361,10 @@ impl MinerManager {
                     {
                         if let Some(new_cmd) = block_channel.get_changed()? {
                             state = match new_cmd {
-                                Some(WorkerCommand::Job(s)) => Some(s),
+                                Some(WorkerCommand::Job(s)) => {
+                                    gpu_work.reset_for_new_job()?;
+                                    Some(s)
+                                }
                                 Some(WorkerCommand::Close) => {return Ok(());}
                                 None => None,
                             };
@@ -0,0 +1,318 @@
+//! Bookkeeping over the workers the host built, e.g. for the periodic hashrate line.
+use crate::events::{EventSink, MinerEvent};
+use crate::target::{difficulty_to_target, expected_hashes};
+use crate::Worker;
+#[cfg(feature = "serde")]
+use crate::WorkerStats;
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+pub struct WorkerPool {
+    workers: Vec<Box<dyn Worker>>,
+    last_sample: Instant,
+    last_hashes: u64,
+    events: EventSink,
+}
+
+impl WorkerPool {
+    /// Hands every worker the pool's `EventSink`
+    pub fn new(mut workers: Vec<Box<dyn Worker>>) -> Self {
+        let events = EventSink::new();
+        workers.iter_mut().for_each(|worker| worker.set_event_sink(events.clone()));
+        let last_hashes = total_hashes(&workers);
+        Self { workers, last_sample: Instant::now(), last_hashes, events }
+    }
+
+    /// The events the workers push from now on, every call subscribing a receiver of its own
+    pub fn events(&self) -> Receiver<MinerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Hashes per second over all workers since the previous sample, or since the pool was created
+    pub fn sample_hashrate(&mut self) -> f64 {
+        self.sample_hashrate_at(Instant::now())
+    }
+
+    fn sample_hashrate_at(&mut self, now: Instant) -> f64 {
+        let hashes = total_hashes(&self.workers);
+        let elapsed = now.duration_since(self.last_sample).as_secs_f64();
+        let delta = hashes.saturating_sub(self.last_hashes);
+        self.last_sample = now;
+        self.last_hashes = hashes;
+        match elapsed > 0.0 {
+            true => delta as f64 / elapsed,
+            false => 0.0,
+        }
+    }
+
+    /// Pauses every running worker hotter than `max_celsius` and returns their ids. Workers without
+    /// a temperature sensor are left alone; resuming is up to the caller.
+    pub fn pause_overheating(&mut self, max_celsius: u32) -> Vec<String> {
+        let mut paused = Vec::new();
+        for worker in &mut self.workers {
+            if !worker.is_paused() && worker.temperature_celsius().is_some_and(|celsius| celsius > max_celsius) {
+                worker.pause();
+                paused.push(worker.id());
+            }
+        }
+        paused
+    }
+
+    /// Ids of the running workers that made no progress for longer than `timeout`, likely stuck in a hung
+    /// kernel. Paused workers aren't expected to progress and are skipped; stopping or restarting is up to the caller.
+    pub fn stalled_workers(&self, timeout: Duration) -> Vec<String> {
+        self.stalled_workers_at(Instant::now(), timeout)
+    }
+
+    fn stalled_workers_at(&self, now: Instant, timeout: Duration) -> Vec<String> {
+        self.workers
+            .iter()
+            .filter(|worker| !worker.is_paused() && now.saturating_duration_since(worker.last_progress()) > timeout)
+            .map(|worker| worker.id())
+            .collect()
+    }
+
+    /// `status_json` of the workers with the `sample_hashrate` since the previous sample added as `hashrate`
+    #[cfg(feature = "serde")]
+    pub fn status_json(&mut self) -> String {
+        let hashrate = self.sample_hashrate();
+        let mut status = status_value(&self.workers);
+        status["hashrate"] = hashrate.into();
+        status.to_string()
+    }
+
+    pub fn workers(&self) -> &[Box<dyn Worker>] {
+        &self.workers
+    }
+
+    pub fn workers_mut(&mut self) -> &mut [Box<dyn Worker>] {
+        &mut self.workers
+    }
+
+    pub fn into_workers(self) -> Vec<Box<dyn Worker>> {
+        self.workers
+    }
+}
+
+/// The hashrate the pool sees: the hashes an accepted share of its difficulty takes on average, summed over
+/// the shares accepted within the last `window`. Noisy over windows holding few shares, but unlike the workers'
+/// own counts it leaves out stale and rejected work.
+pub struct EffectiveHashrate {
+    window: Duration,
+    started: Instant,
+    // When each share was accepted and the hashes it stands for, oldest first
+    shares: VecDeque<(Instant, f64)>,
+}
+
+impl EffectiveHashrate {
+    pub fn new(window: Duration) -> Self {
+        Self { window, started: Instant::now(), shares: VecDeque::new() }
+    }
+
+    pub fn record_accepted(&mut self, share_difficulty: f64) {
+        self.record_accepted_at(Instant::now(), share_difficulty)
+    }
+
+    fn record_accepted_at(&mut self, now: Instant, share_difficulty: f64) {
+        self.shares.push_back((now, expected_hashes(&difficulty_to_target(share_difficulty))));
+        self.expire(now);
+    }
+
+    /// Hashes per second over the window, or over the time since `new` while that is shorter
+    pub fn effective(&self) -> f64 {
+        self.effective_at(Instant::now())
+    }
+
+    fn effective_at(&self, now: Instant) -> f64 {
+        let since = now.checked_sub(self.window).unwrap_or(self.started).max(self.started);
+        let hashes: f64 = self.shares.iter().filter(|(at, _)| *at >= since).map(|(_, hashes)| hashes).sum();
+        let elapsed = now.duration_since(since).as_secs_f64();
+        match elapsed > 0.0 {
+            true => hashes / elapsed,
+            false => 0.0,
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self.shares.front().is_some_and(|(at, _)| now.duration_since(*at) > self.window) {
+            self.shares.pop_front();
+        }
+    }
+}
+
+fn total_hashes(workers: &[Box<dyn Worker>]) -> u64 {
+    workers.iter().map(|worker| worker.hashes_computed()).sum()
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct WorkerStatus {
+    id: String,
+    #[serde(flatten)]
+    stats: WorkerStats,
+}
+
+/// Compact JSON for monitoring tools scraping the miner, one `WorkerStats` per worker plus totals:
+/// `{"workers":[{"id":"#0","hashes":1024,"found":0,"workload":1024,"paused":false}],"hashes":1024,"found":0}`
+#[cfg(feature = "serde")]
+pub fn status_json(workers: &[Box<dyn Worker>]) -> String {
+    status_value(workers).to_string()
+}
+
+#[cfg(feature = "serde")]
+fn status_value(workers: &[Box<dyn Worker>]) -> serde_json::Value {
+    let statuses: Vec<WorkerStatus> =
+        workers.iter().map(|worker| WorkerStatus { id: worker.id(), stats: worker.stats() }).collect();
+    serde_json::json!({
+        "workers": statuses,
+        "hashes": statuses.iter().map(|status| status.stats.hashes).sum::<u64>(),
+        "found": statuses.iter().map(|status| status.stats.found).sum::<u64>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::MinerEvent;
+    use crate::pool::{EffectiveHashrate, WorkerPool};
+    use crate::testing::NullWorker;
+    use crate::{MiningJob, Worker};
+    use std::time::{Duration, Instant};
+
+    /// Named after and adding `workload` hashes every launch, `launches` of which it already made
+    fn counting(workload: usize, launches: usize) -> NullWorker {
+        let mut worker = NullWorker::new(&format!("counting-{}", workload), workload);
+        worker.load_block_constants(&[0; 72], &[[0; 64]; 64], &[0; 4]).unwrap();
+        (0..launches).for_each(|_| worker.calculate_hash(None, 0, 0));
+        worker
+    }
+
+    #[test]
+    fn test_sample_hashrate() {
+        let workers: Vec<Box<dyn Worker>> = vec![Box::new(counting(100, 50)), Box::new(counting(300, 0))];
+        let mut pool = WorkerPool::new(workers);
+        let start = pool.last_sample;
+
+        // Hashes counted before the pool existed are not part of the first window
+        pool.workers_mut().iter_mut().for_each(|worker| worker.calculate_hash(None, 0, 0));
+        assert_eq!(pool.sample_hashrate_at(start + Duration::from_secs(2)), 200.0);
+
+        for worker in pool.workers_mut() {
+            worker.calculate_hash(None, 0, 0);
+            worker.calculate_hash(None, 0, 0);
+        }
+        assert_eq!(pool.sample_hashrate_at(start + Duration::from_secs(3)), 800.0);
+        assert_eq!(pool.sample_hashrate_at(start + Duration::from_secs(3)), 0.0);
+    }
+
+    #[test]
+    fn test_events() {
+        let workers: Vec<Box<dyn Worker>> =
+            vec![Box::new(NullWorker::new("quiet", 8)), Box::new(NullWorker::new("lucky", 8).with_found_nonce(77))];
+        let mut pool = WorkerPool::new(workers);
+        let events = pool.events();
+        assert!(events.try_recv().is_err());
+
+        let job = MiningJob::new([0; 72], [[0; 64]; 64], [0; 4], 5);
+        for worker in pool.workers_mut() {
+            worker.load_job(&job).unwrap();
+        }
+        // A subscriber only gets what happens after it subscribed
+        let late = pool.events();
+        pool.workers_mut().iter_mut().for_each(|worker| worker.calculate_hash(None, u64::MAX, 0));
+
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            [
+                MinerEvent::JobApplied { worker: "quiet".into(), job_id: 5 },
+                MinerEvent::JobApplied { worker: "lucky".into(), job_id: 5 },
+                MinerEvent::NonceFound { worker: "lucky".into(), nonce: 77, job_id: 5 },
+            ]
+        );
+        assert_eq!(late.try_iter().collect::<Vec<_>>(), received[2..]);
+    }
+
+    #[test]
+    fn test_effective_hashrate() {
+        let mut effective = EffectiveHashrate::new(Duration::from_secs(60));
+        let start = effective.started;
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(effective.effective_at(at(10)), 0.0);
+
+        // A difficulty 1 share takes 2^48 / 0xffff hashes on average
+        let difficulty_one = 281474976710656.0 / 65535.0;
+        effective.record_accepted_at(at(10), 1.0);
+        effective.record_accepted_at(at(20), 4.0);
+        effective.record_accepted_at(at(30), 5.0);
+        let rate = effective.effective_at(at(30));
+        assert!((rate / (10.0 * difficulty_one / 30.0) - 1.0).abs() < 1e-9, "{}", rate);
+
+        // The first two shares fell out of the window, the rate is averaged over all of it
+        effective.record_accepted_at(at(85), 2.0);
+        let rate = effective.effective_at(at(85));
+        assert!((rate / (7.0 * difficulty_one / 60.0) - 1.0).abs() < 1e-9, "{}", rate);
+        assert_eq!(effective.shares.len(), 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_status_json() {
+        use crate::pool::status_json;
+        use serde_json::{json, Value};
+
+        let mut paused = counting(300, 0);
+        paused.pause();
+        let workers: Vec<Box<dyn Worker>> = vec![Box::new(counting(100, 50)), Box::new(paused)];
+        let status: Value = serde_json::from_str(&status_json(&workers)).unwrap();
+        assert_eq!(
+            status,
+            json!({
+                "workers": [
+                    {"id": "counting-100", "hashes": 5000, "found": 0, "workload": 100, "paused": false},
+                    {"id": "counting-300", "hashes": 0, "found": 0, "workload": 300, "paused": true},
+                ],
+                "hashes": 5000,
+                "found": 0,
+            })
+        );
+        assert_eq!(status_json(&[]), r#"{"found":0,"hashes":0,"workers":[]}"#);
+
+        let mut pool = WorkerPool::new(workers);
+        let status: Value = serde_json::from_str(&pool.status_json()).unwrap();
+        assert!(status["hashrate"].is_f64());
+        assert_eq!(status["workers"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_pause_overheating() {
+        let workers: Vec<Box<dyn Worker>> = vec![
+            Box::new(counting(1, 0).with_temperature(70)),
+            Box::new(counting(2, 0).with_temperature(91)),
+            Box::new(counting(3, 0)),
+        ];
+        let mut pool = WorkerPool::new(workers);
+        assert_eq!(pool.pause_overheating(90), ["counting-2"]);
+        assert_eq!(pool.workers().iter().map(|worker| worker.is_paused()).collect::<Vec<_>>(), [false, true, false]);
+        // Already paused workers are not reported again
+        assert!(pool.pause_overheating(90).is_empty());
+    }
+
+    #[test]
+    fn test_stalled_workers() {
+        let workers: Vec<Box<dyn Worker>> =
+            vec![Box::new(counting(1, 0)), Box::new(counting(2, 0)), Box::new(counting(3, 0))];
+        let mut pool = WorkerPool::new(workers);
+        pool.workers_mut().iter_mut().for_each(|worker| worker.calculate_hash(None, 0, 0));
+        let launched = Instant::now();
+        let timeout = Duration::from_secs(10);
+        assert!(pool.stalled_workers_at(launched, timeout).is_empty());
+
+        // Only the second worker keeps launching, the paused third one isn't expected to
+        pool.workers_mut()[2].pause();
+        std::thread::sleep(Duration::from_millis(5));
+        pool.workers_mut()[1].calculate_hash(None, 0, 0);
+        let later = launched + timeout + Duration::from_millis(1);
+        assert_eq!(pool.stalled_workers_at(later, timeout), ["counting-1"]);
+    }
+}
@@ -3,23 +3,18 @@ use std::sync::Arc;
 use std::time::{Duration, UNIX_EPOCH};
 use time::{macros::format_description, OffsetDateTime};
 
-pub use crate::pow::hasher::HeaderHasher;
 use crate::{
-    pow::{
-        hasher::{Hasher, PowHasher},
-        heavy_hash::Matrix,
-    },
     proto::{RpcBlock, RpcBlockHeader},
     target::{self, Uint256},
     Error, Hash,
 };
+pub use karlsen_miner::cpu::hasher::HeaderHasher;
+use karlsen_miner::cpu::{
+    hasher::{Hasher, PowHasher},
+    heavy_hash::Matrix,
+};
 use karlsen_miner::Worker;
 
-mod hasher;
-mod heavy_hash;
-mod keccak;
-mod xoshiro;
-
 #[derive(Clone, Debug)]
 pub enum BlockSeed {
     FullBlock(Box<RpcBlock>),
@@ -163,8 +158,8 @@ impl State {
         })
     }
 
-    pub fn load_to_gpu(&self, gpu_work: &mut dyn Worker) {
-        gpu_work.load_block_constants(&self.pow_hash_header, &self.matrix.0, &self.target.0);
+    pub fn load_to_gpu(&self, gpu_work: &mut dyn Worker) -> Result<(), Error> {
+        gpu_work.load_block_constants(&self.pow_hash_header, &self.matrix.0, &self.target.0)
     }
 
     #[inline(always)]
@@ -260,10 +255,10 @@ fn decode_to_slice<T: AsRef<[u8]>>(data: T, out: &mut [u8]) -> Result<(), FromHe
 
 #[cfg(test)]
 mod tests {
-    use crate::pow::hasher::{Hasher, HeaderHasher};
     use crate::pow::serialize_header;
     use crate::proto::{RpcBlockHeader, RpcBlockLevelParents};
     use crate::Hash;
+    use karlsen_miner::cpu::hasher::{Hasher, HeaderHasher};
 
     struct Buf(Vec<u8>);
     impl Hasher for Buf {
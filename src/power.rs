@@ -0,0 +1,169 @@
+//! Keeping the workers' combined power draw under a budget, for rigs on a limited circuit.
+use crate::Worker;
+use std::collections::{BTreeMap, HashMap};
+
+/// Lowers the workload of the hungriest workers while their total `Worker::power_watts` is over the cap, and
+/// gives it back once there is headroom again. The draw is assumed to scale with the workload, which is how a
+/// change is judged before the sensors catch up. Workers without a power reading or that can't resize are
+/// left alone.
+pub struct PowerGovernor {
+    cap_watts: u32,
+    min_workload: usize,
+    // The workload each lowered worker had before the governor first touched it, by `Worker::id`
+    original: HashMap<String, usize>,
+}
+
+impl PowerGovernor {
+    pub fn new(cap_watts: u32) -> Self {
+        Self { cap_watts, min_workload: 1, original: HashMap::new() }
+    }
+
+    /// Never lowers a worker below `workload`, even if the cap can't be met otherwise
+    pub fn with_min_workload(mut self, workload: usize) -> Self {
+        self.min_workload = workload.max(1);
+        self
+    }
+
+    pub fn set_cap_watts(&mut self, cap_watts: u32) {
+        self.cap_watts = cap_watts;
+    }
+
+    /// Over the cap, lowers the worker drawing most by a quarter of its workload until the total is under it.
+    /// Under the cap, raises the lowered workers back as far as the headroom allows. The workers must be synced
+    /// as for `Worker::set_workload`. Returns the ids and new workloads of the workers resized.
+    pub fn adjust(&mut self, workers: &mut [Box<dyn Worker>]) -> Vec<(String, usize)> {
+        let mut draws: Vec<Option<f64>> = workers.iter().map(|worker| worker.power_watts().map(f64::from)).collect();
+        let cap = self.cap_watts as f64;
+        let mut fixed = vec![false; workers.len()];
+        // Index to new workload, so a worker lowered a few times is reported once
+        let mut resized = BTreeMap::new();
+
+        while total(&draws) > cap {
+            let hungriest = (0..workers.len())
+                .filter(|&i| !fixed[i] && draws[i].is_some() && workers[i].get_workload() > self.min_workload)
+                .max_by(|&a, &b| draws[a].partial_cmp(&draws[b]).unwrap());
+            let i = match hungriest {
+                Some(i) => i,
+                None => break,
+            };
+            let workload = workers[i].get_workload();
+            let lowered = (workload - workload / 4).min(workload - 1).max(self.min_workload);
+            match self.resize(workers[i].as_mut(), lowered) {
+                true => {
+                    draws[i] = draws[i].map(|watts| watts * lowered as f64 / workload as f64);
+                    resized.insert(i, lowered);
+                }
+                false => fixed[i] = true,
+            }
+        }
+
+        if resized.is_empty() {
+            for i in 0..workers.len() {
+                let (workload, original) = match (workers[i].power_watts(), self.original.get(&workers[i].id())) {
+                    (Some(_), Some(&original)) => (workers[i].get_workload(), original),
+                    _ => continue,
+                };
+                let per_unit = draws[i].unwrap() / workload as f64;
+                let headroom = (cap - total(&draws)).max(0.0);
+                let raised = match per_unit > 0.0 {
+                    true => original.min(workload + (headroom / per_unit) as usize),
+                    false => original,
+                };
+                if raised > workload && self.resize(workers[i].as_mut(), raised) {
+                    draws[i] = Some(per_unit * raised as f64);
+                    resized.insert(i, raised);
+                    if raised == original {
+                        self.original.remove(&workers[i].id());
+                    }
+                }
+            }
+        }
+        resized.into_iter().map(|(i, workload)| (workers[i].id(), workload)).collect()
+    }
+
+    fn resize(&mut self, worker: &mut dyn Worker, workload: usize) -> bool {
+        let before = worker.get_workload();
+        match worker.set_workload(workload) {
+            Ok(()) => {
+                self.original.entry(worker.id()).or_insert(before);
+                true
+            }
+            Err(e) => {
+                log_warn!("Can't resize {} to keep under the power cap: {}", worker.id(), e);
+                false
+            }
+        }
+    }
+}
+
+fn total(draws: &[Option<f64>]) -> f64 {
+    draws.iter().flatten().sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::power::PowerGovernor;
+    use crate::testing::NullWorker;
+    use crate::Worker;
+
+    /// Draws `full_watts` at a workload of 1000, proportionally less below it
+    fn worker(name: &'static str, full_watts: Option<u32>, resizable: bool) -> Box<dyn Worker> {
+        let worker = NullWorker::new(name, 1000);
+        let worker = match full_watts {
+            Some(watts) => worker.with_power_watts(watts),
+            None => worker,
+        };
+        Box::new(match resizable {
+            true => worker,
+            false => worker.with_fixed_workload(),
+        })
+    }
+
+    fn drawn(workers: &[Box<dyn Worker>]) -> u32 {
+        workers.iter().filter_map(|worker| worker.power_watts()).sum()
+    }
+
+    fn workloads(workers: &[Box<dyn Worker>]) -> Vec<usize> {
+        workers.iter().map(|worker| worker.get_workload()).collect()
+    }
+
+    #[test]
+    fn test_power_cap() {
+        let mut workers = vec![
+            worker("big", Some(200), true),
+            worker("medium", Some(100), true),
+            worker("small", Some(50), true),
+            worker("fixed", Some(40), false),
+            worker("unmetered", None, true),
+        ];
+        let mut governor = PowerGovernor::new(1000);
+        assert!(governor.adjust(&mut workers).is_empty());
+
+        governor.set_cap_watts(230);
+        let resized = governor.adjust(&mut workers);
+        assert!(drawn(&workers) <= 230, "{}", drawn(&workers));
+        // The hungriest went first and the small one was never needed
+        let names: Vec<_> = resized.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(names, ["big", "medium"]);
+        assert!(workers[0].get_workload() < workers[1].get_workload());
+        assert_eq!(workloads(&workers)[2..], [1000, 1000, 1000]);
+        // Settles under the cap
+        governor.adjust(&mut workers);
+        assert!(drawn(&workers) <= 230, "{}", drawn(&workers));
+
+        // Some headroom gives back part of it, within the cap
+        governor.set_cap_watts(250);
+        assert!(!governor.adjust(&mut workers).is_empty());
+        assert!(drawn(&workers) <= 250 && drawn(&workers) > 230, "{}", drawn(&workers));
+
+        governor.set_cap_watts(1000);
+        governor.adjust(&mut workers);
+        assert_eq!(workloads(&workers), [1000; 5]);
+        assert!(governor.original.is_empty());
+
+        // Workers that can't be lowered any further are left over the cap
+        let mut governor = PowerGovernor::new(10).with_min_workload(500);
+        governor.adjust(&mut workers);
+        assert_eq!(workloads(&workers), [500, 500, 500, 1000, 1000]);
+    }
+}
@@ -0,0 +1,132 @@
+//! Per-device overclocking profiles, applied through `Worker::apply_clock_profile`.
+//!
+//! A profile is a small TOML subset, one table per device id:
+//!
+//! ```toml
+//! # RTX 3070
+//! [device.0]
+//! core_offset_mhz = 150
+//! mem_offset_mhz = -500
+//! ```
+//!
+//! Offsets left out default to 0.
+use crate::{Error, Worker};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClockOffsets {
+    pub core_offset_mhz: i32,
+    pub mem_offset_mhz: i32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClockProfile {
+    devices: BTreeMap<u32, ClockOffsets>,
+}
+
+impl ClockProfile {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut devices = BTreeMap::new();
+        let mut current = None;
+        for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(table) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                let id = table
+                    .trim()
+                    .strip_prefix("device.")
+                    .and_then(|id| id.parse::<u32>().ok())
+                    .ok_or_else(|| format!("line {}: expected a [device.<id>] table, got [{}]", number, table))?;
+                if devices.insert(id, ClockOffsets::default()).is_some() {
+                    return Err(format!("line {}: device {} is listed twice", number, id).into());
+                }
+                current = Some(id);
+                continue;
+            }
+            let (key, value) =
+                line.split_once('=').ok_or_else(|| format!("line {}: expected `key = value`", number))?;
+            let id = current.ok_or_else(|| format!("line {}: `{}` outside of a [device.<id>] table", number, line))?;
+            let value = value
+                .trim()
+                .parse::<i32>()
+                .map_err(|e| format!("line {}: bad offset `{}`: {}", number, value.trim(), e))?;
+            let offsets = devices.get_mut(&id).unwrap();
+            match key.trim() {
+                "core_offset_mhz" => offsets.core_offset_mhz = value,
+                "mem_offset_mhz" => offsets.mem_offset_mhz = value,
+                key => return Err(format!("line {}: unknown key `{}`", number, key).into()),
+            }
+        }
+        Ok(Self { devices })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("failed reading {}: {}", path.display(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn offsets(&self, device_id: u32) -> Option<ClockOffsets> {
+        self.devices.get(&device_id).copied()
+    }
+
+    /// Applies the offsets of `device_id`, usually `WorkerSpec::device_id` of the spec `worker` was built from.
+    /// `Ok(false)` if the profile doesn't list the device.
+    pub fn apply(&self, device_id: u32, worker: &mut dyn Worker) -> Result<bool, Error> {
+        match self.offsets(device_id) {
+            Some(offsets) => {
+                worker.apply_clock_profile(offsets.core_offset_mhz, offsets.mem_offset_mhz)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::{ClockOffsets, ClockProfile};
+    use crate::testing::NullWorker;
+
+    #[test]
+    fn test_parse() {
+        let profile = ClockProfile::parse(
+            "# rig 1\n[device.0]\ncore_offset_mhz = 150\nmem_offset_mhz = -500 # stable\n\n[device.2]\nmem_offset_mhz=800\n",
+        )
+        .unwrap();
+        assert_eq!(profile.offsets(0), Some(ClockOffsets { core_offset_mhz: 150, mem_offset_mhz: -500 }));
+        assert_eq!(profile.offsets(2), Some(ClockOffsets { core_offset_mhz: 0, mem_offset_mhz: 800 }));
+        assert_eq!(profile.offsets(1), None);
+        assert_eq!(ClockProfile::parse("").unwrap(), ClockProfile::default());
+    }
+
+    #[test]
+    fn test_apply() {
+        let profile = ClockProfile::parse("[device.1]\ncore_offset_mhz = 100\nmem_offset_mhz = -200").unwrap();
+        let mut worker = NullWorker::new("clock", 1).with_clock_control();
+        assert!(profile.apply(1, &mut worker).unwrap());
+        assert!(!profile.apply(0, &mut worker).unwrap());
+        assert_eq!(worker.clock_offsets(), [(100, -200)]);
+
+        // Workers without clock control refuse
+        let mut worker = NullWorker::new("null", 1);
+        assert!(profile.apply(1, &mut worker).unwrap_err().to_string().contains("unsupported"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (text, error) in [
+            ("core_offset_mhz = 1", "line 1: `core_offset_mhz = 1` outside"),
+            ("[gpu.0]", "line 1: expected a [device.<id>] table"),
+            ("[device.0]\n[device.0]", "line 2: device 0 is listed twice"),
+            ("[device.0]\nvoltage = 1", "line 2: unknown key `voltage`"),
+            ("[device.0]\ncore_offset_mhz = fast", "line 2: bad offset `fast`"),
+            ("[device.0]\ncore_offset_mhz", "line 2: expected `key = value`"),
+        ] {
+            let e = ClockProfile::parse(text).unwrap_err().to_string();
+            assert!(e.starts_with(error), "{}", e);
+        }
+    }
+}
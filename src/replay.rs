@@ -0,0 +1,143 @@
+//! Replays recorded jobs, to run the mining pipeline in CI without a pool or a node.
+//!
+//! A recording holds one job per line, as written by `ReplaySource::record`:
+//!
+//! ```text
+//! # job_id header(72 bytes, hex) target(32 bytes little-endian, hex)
+//! 1 2a2a…0000 ffff…ff00
+//! ```
+//!
+//! The matrix isn't stored, it is generated from the header's pre-pow hash like the clients do.
+use crate::header::{generate_matrix, header_from_slice};
+use crate::target::{target_from_le_bytes, target_to_le_bytes};
+use crate::{Error, MiningJob};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::Duration;
+
+pub struct ReplaySource {
+    jobs: VecDeque<MiningJob>,
+    delay: Duration,
+    started: bool,
+}
+
+impl ReplaySource {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut jobs = VecDeque::new();
+        for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            jobs.push_back(parse_job(line).map_err(|e| format!("line {}: {}", number, e))?);
+        }
+        Ok(Self { jobs, delay: Duration::ZERO, started: false })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("failed reading {}: {}", path.display(), e))?;
+        Self::parse(&text)
+    }
+
+    /// How long to wait before handing out each job after the first, like a pool sending new templates
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// The recording `parse` reads `jobs` back from
+    pub fn record(jobs: &[MiningJob]) -> String {
+        jobs.iter()
+            .map(|job| {
+                let target = hex::encode(target_to_le_bytes(&job.target));
+                format!("{} {} {}\n", job.job_id, hex::encode(job.header), target)
+            })
+            .collect()
+    }
+
+    /// Jobs not handed out yet
+    pub fn remaining(&self) -> usize {
+        self.jobs.len()
+    }
+}
+
+/// Each job is stamped when handed out, being received from the replay
+impl Iterator for ReplaySource {
+    type Item = MiningJob;
+
+    fn next(&mut self) -> Option<MiningJob> {
+        let job = self.jobs.pop_front()?;
+        if self.started {
+            std::thread::sleep(self.delay);
+        }
+        self.started = true;
+        Some(MiningJob::new(job.header, job.matrix, job.target, job.job_id))
+    }
+}
+
+fn parse_job(line: &str) -> Result<MiningJob, Error> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    let (job_id, header, target) = match fields[..] {
+        [job_id, header, target] => (job_id, header, target),
+        _ => return Err(format!("expected `job_id header target`, got {} fields", fields.len()).into()),
+    };
+    let job_id = job_id.parse::<u64>().map_err(|e| format!("bad job id `{}`: {}", job_id, e))?;
+    let header = header_from_slice(&hex::decode(header)?)?;
+    let target: [u8; 32] = hex::decode(target)?
+        .try_into()
+        .map_err(|bytes: Vec<u8>| format!("the target is {} bytes, not 32", bytes.len()))?;
+    let matrix = generate_matrix(header[..32].try_into().unwrap());
+    Ok(MiningJob::new(header, matrix, target_from_le_bytes(&target), job_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header::generate_matrix;
+    use crate::replay::ReplaySource;
+    use crate::testing::NullWorker;
+    use crate::{MiningJob, Worker};
+    use std::time::{Duration, Instant};
+
+    fn job(seed: u8) -> MiningJob {
+        let mut header = [0u8; 72];
+        header[..32].copy_from_slice(&[seed; 32]);
+        header[32..40].copy_from_slice(&(seed as u64 * 1000).to_le_bytes());
+        let matrix = generate_matrix(&[seed; 32]);
+        MiningJob::new(header, matrix, [u64::MAX >> seed, seed as u64, 0, 1], seed as u64)
+    }
+
+    #[test]
+    fn test_replay_into_worker() {
+        let jobs = [job(1), job(2), job(3)];
+        // Per process, so concurrent runs of the tests don't share the file
+        let path = std::env::temp_dir().join(format!("karlsen_miner_replay_{}.txt", std::process::id()));
+        std::fs::write(&path, format!("# recorded\n{}", ReplaySource::record(&jobs))).unwrap();
+
+        let replay = ReplaySource::load(&path).unwrap().with_delay(Duration::from_millis(10));
+        assert_eq!(replay.remaining(), 3);
+        let mut worker = NullWorker::new("loading", 1);
+        let start = Instant::now();
+        let mut ids = Vec::new();
+        for job in replay {
+            worker.load_job(&job).unwrap();
+            ids.push(job.job_id);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(ids, [1, 2, 3]);
+        let expected: Vec<_> = jobs.iter().map(|job| job.constants()).collect();
+        assert_eq!(worker.loaded_constants(), expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (text, error) in [
+            ("1 00", "line 1: expected `job_id header target`, got 2 fields"),
+            ("x 00 00", "line 1: bad job id `x`"),
+            ("\n1 00 00", "line 2: the header is 1 bytes, not 72"),
+        ] {
+            let e = ReplaySource::parse(text).err().unwrap().to_string();
+            assert!(e.starts_with(error), "{}", e);
+        }
+        assert_eq!(ReplaySource::parse("# nothing\n\n").unwrap().remaining(), 0);
+    }
+}
@@ -0,0 +1,199 @@
+//! Splits one job's nonce space between workers by how fast they hash, and one device's time between mining
+//! and other work.
+use crate::{events, partition_weighted, Error, MiningJob, NonceRange, Worker};
+use std::time::{Duration, Instant};
+
+/// Hands each worker a `NonceRange` proportional to the hashes it computed since the previous `assign`, so
+/// slower devices scan smaller ranges, and launches the workers over their ranges one batch at a time.
+/// The first `assign`, without any history, splits evenly.
+#[derive(Default)]
+pub struct JobScheduler {
+    // One per worker, in the order of the slice passed to `assign`
+    assignments: Vec<Assignment>,
+    last_hashes: Vec<u64>,
+}
+
+struct Assignment {
+    range: NonceRange,
+    // First nonce of `range` not launched yet
+    next: u64,
+}
+
+impl JobScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resets every worker and loads `job` into it, then gives each its range; the ranges are in order of `workers`.
+    /// Fails on the first worker rejecting the job.
+    pub fn assign(&mut self, job: &MiningJob, workers: &mut [Box<dyn Worker>]) -> Result<Vec<NonceRange>, Error> {
+        let hashes: Vec<u64> = workers.iter().map(|worker| worker.hashes_computed()).collect();
+        let weights: Vec<u64> = match self.last_hashes.len() == hashes.len() {
+            true => hashes.iter().zip(&self.last_hashes).map(|(now, before)| now.saturating_sub(*before)).collect(),
+            false => vec![0; hashes.len()],
+        };
+        self.last_hashes = hashes;
+
+        let ranges = partition_weighted(&weights);
+        self.assignments = ranges.iter().map(|&range| Assignment { range, next: range.start }).collect();
+        for worker in workers.iter_mut() {
+            worker.reset_for_new_job()?;
+            worker.load_job(job)?;
+        }
+        Ok(ranges)
+    }
+
+    /// Launches each worker over the next `Worker::batch_size` nonces of its range, returning how many workers
+    /// still had nonces left. `workers` must be the slice passed to `assign`.
+    pub fn launch(&mut self, workers: &mut [Box<dyn Worker>]) -> usize {
+        assert_eq!(workers.len(), self.assignments.len(), "workers changed since the job was assigned");
+        let mut launched = 0;
+        for (worker, assignment) in workers.iter_mut().zip(&mut self.assignments) {
+            let left = assignment.range.end() - assignment.next;
+            if left == 0 {
+                continue;
+            }
+            let count = worker.batch_size().clamp(1, left);
+            worker.calculate_hash_range(assignment.next, count);
+            assignment.next += count;
+            launched += 1;
+        }
+        launched
+    }
+
+    /// The ranges of the current job, in order of the workers
+    pub fn ranges(&self) -> Vec<NonceRange> {
+        self.assignments.iter().map(|assignment| assignment.range).collect()
+    }
+}
+
+/// Mines on a worker for `duty` of every cycle and pauses it for the rest, so another workload can have the
+/// device in between. A launch running past the mining part eats into the pause rather than the next cycle.
+pub struct TimeSliceScheduler {
+    duty: f64,
+    cycle: Duration,
+}
+
+impl TimeSliceScheduler {
+    /// `duty` is the fraction of each cycle spent mining, between 0 and 1
+    pub fn new(duty: f64) -> Self {
+        Self { duty: duty.clamp(0.0, 1.0), cycle: Duration::from_secs(1) }
+    }
+
+    /// Shorter cycles share the device more finely but pay the switch more often; the default is a second
+    pub fn with_cycle(mut self, cycle: Duration) -> Self {
+        self.cycle = cycle;
+        self
+    }
+
+    /// Mines the job already loaded into `worker` in cycles until `deadline` or `Worker::request_stop`,
+    /// handing every nonce found to `on_found`. The worker is left resumed.
+    pub fn run_until(
+        &self,
+        worker: &mut dyn Worker,
+        deadline: Instant,
+        mut on_found: impl FnMut(u64),
+    ) -> Result<(), Error> {
+        let mining = self.cycle.mul_f64(self.duty);
+        let mut nonces = vec![0; worker.max_output_nonces()];
+        let mut cycle_start = Instant::now();
+        while cycle_start < deadline && !worker.stop_requested() {
+            let mine_until = (cycle_start + mining).min(deadline);
+            while Instant::now() < mine_until && !worker.stop_requested() {
+                worker.calculate_hash(None, u64::MAX, 0);
+                events::reported_sync(worker)?;
+                let count = worker.copy_output_to(&mut nonces)?;
+                nonces.iter().take(count).filter(|&&nonce| nonce != 0).for_each(|&nonce| on_found(nonce));
+            }
+            let next_cycle = (cycle_start + self.cycle).min(deadline);
+            let now = Instant::now();
+            if next_cycle > now && !worker.stop_requested() {
+                worker.pause();
+                std::thread::sleep(next_cycle - now);
+                worker.resume();
+            }
+            cycle_start = next_cycle;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduler::{JobScheduler, TimeSliceScheduler};
+    use crate::testing::NullWorker;
+    use crate::{MiningJob, NonceRange, Worker};
+    use std::time::{Duration, Instant};
+
+    /// Hashes `rate` nonces per launch whatever it is asked, and tracks the ranges it was launched over
+    fn rate(rate: u64) -> Box<dyn Worker> {
+        Box::new(
+            NullWorker::new(&format!("rate-{}", rate), 16).with_hashes_per_launch(move |_| rate).with_covered_ranges(),
+        )
+    }
+
+    #[test]
+    fn test_faster_worker_gets_larger_range() {
+        let job = MiningJob::new([0; 72], [[1; 64]; 64], [u64::MAX; 4], 1);
+        let mut workers = vec![rate(300), rate(100)];
+        let mut scheduler = JobScheduler::new();
+
+        // Nothing to go by yet
+        let ranges = scheduler.assign(&job, &mut workers).unwrap();
+        assert_eq!(ranges[0].len, u64::MAX / 2);
+        assert_eq!(scheduler.launch(&mut workers), 2);
+        assert_eq!(workers[0].covered_ranges(), [NonceRange { start: 0, len: 16 }]);
+        assert_eq!(workers[1].covered_ranges(), [NonceRange { start: u64::MAX / 2, len: 16 }]);
+
+        let ranges = scheduler.assign(&job, &mut workers).unwrap();
+        assert!(ranges[0].len > ranges[1].len);
+        assert_eq!(ranges[0].len, (u64::MAX as u128 * 3 / 4) as u64);
+        assert_eq!((ranges[0].end(), ranges[1].end()), (ranges[1].start, u64::MAX));
+        assert_eq!(scheduler.ranges(), ranges);
+        scheduler.launch(&mut workers);
+        assert_eq!(workers[1].covered_ranges(), [NonceRange { start: ranges[1].start, len: 16 }]);
+    }
+
+    #[test]
+    fn test_idle_worker_gets_no_range() {
+        let job = MiningJob::new([0; 72], [[1; 64]; 64], [u64::MAX; 4], 1);
+        let mut workers = vec![rate(1), rate(0)];
+        let mut scheduler = JobScheduler::new();
+        scheduler.assign(&job, &mut workers).unwrap();
+        scheduler.launch(&mut workers);
+        // The second worker hashed nothing, so the first one gets the whole nonce space
+        let ranges = scheduler.assign(&job, &mut workers).unwrap();
+        assert_eq!(ranges[1].len, 0);
+        assert_eq!(scheduler.launch(&mut workers), 1);
+    }
+
+    /// Each launch takes a couple of milliseconds, all of it counted as mining
+    fn sleepy() -> NullWorker {
+        let mut worker = NullWorker::new("sleepy", 16).with_launch_time(Duration::from_millis(2));
+        worker.load_block_constants(&[0; 72], &[[1; 64]; 64], &[0; 4]).unwrap();
+        worker
+    }
+
+    #[test]
+    fn test_time_slices() {
+        let mut worker = sleepy();
+        let scheduler = TimeSliceScheduler::new(0.7).with_cycle(Duration::from_millis(100));
+        let start = Instant::now();
+        scheduler.run_until(&mut worker, start + Duration::from_secs(1), |_| {}).unwrap();
+        let elapsed = start.elapsed();
+
+        let fraction = worker.mining_time().as_secs_f64() / elapsed.as_secs_f64();
+        assert!((0.55..0.85).contains(&fraction), "mined {:.2} of the time", fraction);
+        assert!((9..=10).contains(&worker.pauses()), "{}", worker.pauses());
+        // Launching while paused is a bug
+        assert_eq!(worker.paused_launches(), 0);
+        assert!(!worker.is_paused());
+
+        // Always mining never pauses
+        let mut worker = sleepy();
+        let scheduler = TimeSliceScheduler::new(1.0).with_cycle(Duration::from_millis(50));
+        scheduler.run_until(&mut worker, Instant::now() + Duration::from_millis(200), |_| {}).unwrap();
+        assert!(worker.mining_time() >= Duration::from_millis(150));
+        assert_eq!(worker.pauses(), 0);
+    }
+}
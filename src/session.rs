@@ -0,0 +1,133 @@
+//! What the workers already did, kept across restarts of long unattended runs.
+//!
+//! A `SessionState` holds every worker's `Worker::covered_ranges` and generator state, keyed by `Worker::id`
+//! since that is what stays the same when the workers are built again. Restoring it lets a worker carry on
+//! where it stopped instead of rescanning nonces it already covered.
+use crate::{Error, NonceRange, Worker};
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorkerSession {
+    pub worker: String,
+    pub covered: Vec<NonceRange>,
+    /// `Worker::rng_state`, `None` for workers not drawing their nonces from a generator of their own
+    pub rng_state: Option<[u64; 4]>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionState {
+    pub workers: Vec<WorkerSession>,
+}
+
+impl SessionState {
+    pub fn capture(workers: &[Box<dyn Worker>]) -> Self {
+        let workers = workers
+            .iter()
+            .map(|worker| WorkerSession {
+                worker: worker.id(),
+                covered: worker.covered_ranges(),
+                rng_state: worker.rng_state(),
+            })
+            .collect();
+        Self { workers }
+    }
+
+    /// Hands every worker its session with `Worker::restore_session`, once the job the ranges were covered for
+    /// is loaded again. Workers without a session are left alone. Returns how many were restored, and fails on
+    /// the first worker refusing its session.
+    pub fn restore(&self, workers: &mut [Box<dyn Worker>]) -> Result<usize, Error> {
+        let mut restored = 0;
+        for worker in workers.iter_mut() {
+            let id = worker.id();
+            if let Some(session) = self.workers.iter().find(|session| session.worker == id) {
+                worker.restore_session(session).map_err(|e| format!("restoring {} failed: {}", id, e))?;
+                restored += 1;
+            }
+        }
+        Ok(restored)
+    }
+
+    /// Writes the session as JSON, replacing `path` only once the whole file was written
+    #[cfg(feature = "serde")]
+    pub fn save_to(&self, path: &Path) -> Result<(), Error> {
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, serde_json::to_vec(self)?)
+            .map_err(|e| format!("failed writing {}: {}", partial.display(), e))?;
+        std::fs::rename(&partial, path).map_err(|e| format!("failed replacing {}: {}", path.display(), e))?;
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn restore_from(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read(path).map_err(|e| format!("failed reading {}: {}", path.display(), e))?;
+        Ok(serde_json::from_slice(&text).map_err(|e| format!("malformed session {}: {}", path.display(), e))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::CpuWorker;
+    use crate::session::SessionState;
+    use crate::{NonceRange, Worker};
+
+    fn workers() -> Vec<Box<dyn Worker>> {
+        vec![Box::new(CpuWorker::new(0, 16, [1, 2, 3, 4])), Box::new(CpuWorker::new(1, 16, [5, 6, 7, 8]))]
+    }
+
+    fn mined() -> Vec<Box<dyn Worker>> {
+        let mut workers = workers();
+        for (i, worker) in workers.iter_mut().enumerate() {
+            worker.load_block_constants(&[0; 72], &crate::header::generate_matrix(&[1; 32]), &[0; 4]).unwrap();
+            worker.calculate_hash_range(1000 * i as u64, 10);
+            worker.calculate_hash(None, u64::MAX, 0);
+        }
+        workers
+    }
+
+    #[test]
+    fn test_restore() {
+        let before = mined();
+        let session = SessionState::capture(&before);
+        assert_eq!(session.workers[1].covered, [NonceRange { start: 1000, len: 10 }]);
+
+        let mut after = workers();
+        // Built in another order, plus a new worker the session doesn't know
+        after.swap(0, 1);
+        after.push(Box::new(CpuWorker::new(2, 16, [9, 9, 9, 9])));
+        for worker in after.iter_mut() {
+            worker.load_block_constants(&[0; 72], &crate::header::generate_matrix(&[1; 32]), &[0; 4]).unwrap();
+        }
+        assert_eq!(session.restore(&mut after).unwrap(), 2);
+        assert_eq!(after[0].covered_ranges(), before[1].covered_ranges());
+        assert_eq!(after[1].rng_state(), before[0].rng_state());
+        assert!(after[2].covered_ranges().is_empty());
+
+        // Carrying on draws what the old workers would have drawn next
+        let mut before = before;
+        for (old, new) in [(0, 1), (1, 0)] {
+            before[old].calculate_hash(None, u64::MAX, 0);
+            after[new].calculate_hash(None, u64::MAX, 0);
+            assert_eq!(before[old].rng_state(), after[new].rng_state());
+        }
+
+        let mut null: Vec<Box<dyn Worker>> = vec![Box::new(crate::testing::NullWorker::new("CPU #0", 1))];
+        assert!(session.restore(&mut null).unwrap_err().to_string().contains("unsupported"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_restore_round_trip() {
+        let session = SessionState::capture(&mined());
+        assert_eq!(session.workers.len(), 2);
+        // Per process, so concurrent runs of the tests don't share the file
+        let path = std::env::temp_dir().join(format!("karlsen_miner_session_{}.json", std::process::id()));
+        session.save_to(&path).unwrap();
+        let restored = SessionState::restore_from(&path).unwrap();
+        assert_eq!(restored, session);
+        std::fs::remove_file(&path).unwrap();
+        assert!(SessionState::restore_from(&path).unwrap_err().to_string().starts_with("failed reading"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Solo mining against a node of one's own.
+//!
+//! The node is reached through `NodeRpc`, which hands out block templates and accepts solved blocks;
+//! `SoloClient` polls it and turns each new template into the constants a worker needs. The miner's own gRPC
+//! client implements `NodeRpc` for a Karlsen node, but the miner keeps following the node's template
+//! notifications rather than polling; `SoloClient` is for hosts embedding the workers.
+use crate::header::{generate_matrix, HEADER_SIZE};
+use crate::target::u256_from_compact_target;
+use crate::{Error, MiningJob};
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// A block template as returned by the node, with its pre-pow hash already computed
+#[derive(Clone)]
+pub struct BlockTemplate<B> {
+    pub block: B,
+    pub pre_pow_hash: [u8; 32],
+    pub timestamp: u64,
+    /// Compact encoding of the block target
+    pub bits: u32,
+}
+
+#[async_trait(?Send)]
+pub trait NodeRpc {
+    type Block: Clone;
+
+    /// `Ok(None)` while the node can't hand out templates, e.g. because it is still syncing
+    async fn get_block_template(&mut self, pay_address: &str) -> Result<Option<BlockTemplate<Self::Block>>, Error>;
+    async fn submit_block(&mut self, block: Self::Block, nonce: u64) -> Result<(), Error>;
+}
+
+/// A template ready to be loaded into a worker
+pub struct SoloJob {
+    /// PRE_POW_HASH || TIME || 32 zero byte padding
+    pub header: [u8; HEADER_SIZE],
+    pub matrix: [[u16; 64]; 64],
+    pub target: [u64; 4],
+}
+
+impl SoloJob {
+    /// Templates carry no id of their own, `job_id` is whatever the caller numbers them with
+    pub fn mining_job(&self, job_id: u64) -> MiningJob {
+        MiningJob::new(self.header, self.matrix, self.target, job_id)
+    }
+}
+
+pub struct SoloClient<R: NodeRpc> {
+    rpc: R,
+    pay_address: String,
+    poll_interval: Duration,
+    template: Option<BlockTemplate<R::Block>>,
+}
+
+impl<R: NodeRpc> SoloClient<R> {
+    pub fn new(rpc: R, pay_address: &str) -> Self {
+        Self { rpc, pay_address: pay_address.to_string(), poll_interval: Duration::from_millis(500), template: None }
+    }
+
+    /// How long to wait between template requests, and before retrying while the node is syncing
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Polls the node until it returns a template that differs from the current one. Work on the previous
+    /// template is stale once this returns, so the host should `request_stop` its workers.
+    pub async fn next_job(&mut self) -> Result<SoloJob, Error> {
+        let mut waiting_for_sync = false;
+        loop {
+            match self.rpc.get_block_template(&self.pay_address).await? {
+                None if !waiting_for_sync => {
+                    log_info!("Node has no block template (still syncing?), retrying");
+                    waiting_for_sync = true;
+                }
+                Some(template) if !self.is_current(&template) => {
+                    let job = job_from_template(&template);
+                    self.template = Some(template);
+                    return Ok(job);
+                }
+                _ => {}
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Submits the current template solved with `nonce`
+    pub async fn submit(&mut self, nonce: u64) -> Result<(), Error> {
+        let block = self.template.as_ref().ok_or("no template to submit a block for")?.block.clone();
+        self.rpc.submit_block(block, nonce).await
+    }
+
+    fn is_current(&self, template: &BlockTemplate<R::Block>) -> bool {
+        self.template.as_ref().is_some_and(|current| {
+            (current.pre_pow_hash, current.timestamp) == (template.pre_pow_hash, template.timestamp)
+        })
+    }
+}
+
+fn job_from_template<B>(template: &BlockTemplate<B>) -> SoloJob {
+    let mut header = [0u8; HEADER_SIZE];
+    header[..32].copy_from_slice(&template.pre_pow_hash);
+    header[32..40].copy_from_slice(&template.timestamp.to_le_bytes());
+    SoloJob {
+        header,
+        matrix: generate_matrix(&template.pre_pow_hash),
+        target: u256_from_compact_target(template.bits).0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header::generate_matrix;
+    use crate::solo::{BlockTemplate, NodeRpc, SoloClient};
+    use crate::target::u256_from_compact_target;
+    use crate::Error;
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    /// Replays `templates` in order, repeating the last one, and records submitted blocks
+    struct MockNode {
+        templates: VecDeque<Option<BlockTemplate<u32>>>,
+        requests: usize,
+        submitted: Vec<(u32, u64)>,
+    }
+
+    #[async_trait(?Send)]
+    impl NodeRpc for MockNode {
+        type Block = u32;
+
+        async fn get_block_template(&mut self, pay_address: &str) -> Result<Option<BlockTemplate<u32>>, Error> {
+            assert_eq!(pay_address, "karlsen:solo");
+            self.requests += 1;
+            match self.templates.len() {
+                1 => Ok(self.templates[0].clone()),
+                _ => Ok(self.templates.pop_front().unwrap()),
+            }
+        }
+
+        async fn submit_block(&mut self, block: u32, nonce: u64) -> Result<(), Error> {
+            self.submitted.push((block, nonce));
+            Ok(())
+        }
+    }
+
+    fn template(block: u32, timestamp: u64) -> BlockTemplate<u32> {
+        BlockTemplate { block, pre_pow_hash: [block as u8; 32], timestamp, bits: 0x1e7fffff }
+    }
+
+    #[tokio::test]
+    async fn test_solo_client_follows_templates() {
+        let templates = vec![None, None, Some(template(1, 1000)), Some(template(1, 1000)), Some(template(2, 2000))];
+        let node = MockNode { templates: templates.into(), requests: 0, submitted: vec![] };
+        let mut client = SoloClient::new(node, "karlsen:solo").with_poll_interval(Duration::from_millis(1));
+
+        // Retries through the syncing node until the first template
+        let job = client.next_job().await.unwrap();
+        assert_eq!(client.rpc.requests, 3);
+        assert_eq!(job.header[..32], [1u8; 32]);
+        assert_eq!(&job.header[32..40], &1000u64.to_le_bytes());
+        assert_eq!(job.header[40..], [0u8; 32]);
+        assert_eq!(job.matrix, generate_matrix(&[1u8; 32]));
+        assert_eq!(job.target, u256_from_compact_target(0x1e7fffff).0);
+        client.submit(42).await.unwrap();
+
+        // The unchanged template is skipped
+        let job = client.next_job().await.unwrap();
+        assert_eq!(client.rpc.requests, 5);
+        assert_eq!(job.header[..32], [2u8; 32]);
+        client.submit(7).await.unwrap();
+        assert_eq!(client.rpc.submitted, [(1, 42), (2, 7)]);
+    }
+}
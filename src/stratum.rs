@@ -0,0 +1,561 @@
+//! Pool client for the Karlsen flavour of stratum.
+//!
+//! Jobs arrive as `mining.notify` with the pre-pow hash as four little-endian words plus the timestamp,
+//! and are turned into the header, matrix and target `Worker::load_block_constants` expects.
+use crate::header::{generate_matrix, HEADER_SIZE};
+use crate::target::difficulty_to_target;
+use crate::{Error, MiningJob};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LinesCodec};
+
+const JOB_NOT_FOUND: u64 = 21;
+
+/// A job ready to be loaded into a worker
+#[derive(Clone)]
+pub struct StratumJob {
+    pub id: String,
+    /// PRE_POW_HASH || TIME || 32 zero byte padding
+    pub header: [u8; HEADER_SIZE],
+    pub matrix: [[u16; 64]; 64],
+    pub target: [u64; 4],
+    /// Bits of the nonce the miner may choose; the rest is the pool assigned extranonce in `nonce_fixed`
+    pub nonce_mask: u64,
+    pub nonce_fixed: u64,
+}
+
+impl StratumJob {
+    /// The pool's job ids are opaque strings, `job_id` is whatever the caller numbers them with
+    pub fn mining_job(&self, job_id: u64) -> MiningJob {
+        MiningJob::new(self.header, self.matrix, self.target, job_id)
+    }
+}
+
+/// How a dropped connection is re-established: the first attempt waits `initial_backoff`, every further one
+/// `multiplier` times longer up to `max_backoff`. After `max_attempts` failed attempts in a row `next_job`
+/// fails, e.g. to fail over to a backup pool; `None` retries forever.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// How long to wait before the attempt following one that waited `previous`
+    fn next_backoff(&self, previous: Duration) -> Duration {
+        previous.mul_f64(self.multiplier).min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ShareCounts {
+    pub accepted: u64,
+    pub stale: u64,
+    pub rejected: u64,
+}
+
+pub struct StratumClient {
+    address: String,
+    user: String,
+    framed: Option<Framed<TcpStream, LinesCodec>>,
+    next_id: u64,
+    target: [u64; 4],
+    nonce_mask: u64,
+    nonce_fixed: u64,
+    job: Option<StratumJob>,
+    // Request id of the `mining.subscribe` on the current connection, until the pool answered it
+    subscribe_id: Option<u64>,
+    // Request id -> job id of shares waiting for the pool's answer
+    pending: HashMap<u64, String>,
+    shares: ShareCounts,
+    reconnect: ReconnectPolicy,
+}
+
+impl StratumClient {
+    /// A client for `address` (`host:port`, without the `stratum+tcp://` scheme) mining as `user`. It connects
+    /// on the first `next_job`, and `reconnect` governs every connection after a failed or dropped one,
+    /// `ReconnectPolicy::default()` retries forever.
+    pub fn new(address: &str, user: &str, reconnect: ReconnectPolicy) -> Self {
+        Self {
+            address: address.to_string(),
+            user: user.to_string(),
+            framed: None,
+            next_id: 1,
+            target: difficulty_to_target(1.0),
+            nonce_mask: u64::MAX,
+            nonce_fixed: 0,
+            job: None,
+            subscribe_id: None,
+            pending: HashMap::new(),
+            shares: ShareCounts::default(),
+            reconnect,
+        }
+    }
+
+    /// Like `new`, but connects and authorizes `user` right away, failing if the pool can't be reached.
+    pub async fn connect(address: &str, user: &str, reconnect: ReconnectPolicy) -> Result<Self, Error> {
+        let mut client = Self::new(address, user, reconnect);
+        client.handshake().await?;
+        Ok(client)
+    }
+
+    /// Waits for the next job. A difficulty change re-issues the current job with the new target,
+    /// and a dropped connection is re-established transparently, per the `ReconnectPolicy`. Lines the client
+    /// can't make sense of are logged and skipped, so only running out of reconnection attempts fails.
+    pub async fn next_job(&mut self) -> Result<StratumJob, Error> {
+        loop {
+            let Some(framed) = self.framed.as_mut() else {
+                // Never connected, or gave up reconnecting on an earlier call
+                if let Err(e) = self.handshake().await {
+                    log_warn!("Connecting to {} failed: {}", self.address, e);
+                    self.reconnect().await?;
+                }
+                continue;
+            };
+            match framed.next().await {
+                Some(Ok(line)) => {
+                    if let Some(job) = self.handle_line(&line) {
+                        return Ok(job);
+                    }
+                }
+                Some(Err(e)) => {
+                    log_warn!("Stratum connection to {} failed: {}", self.address, e);
+                    self.reconnect().await?;
+                }
+                None => {
+                    log_warn!("Stratum connection to {} closed", self.address);
+                    self.reconnect().await?;
+                }
+            }
+        }
+    }
+
+    /// Submits `nonce` for the current job. Callers holding a nonce from an older job should check
+    /// `is_current` first, as the pool would reject it as stale.
+    pub async fn submit(&mut self, nonce: u64) -> Result<(), Error> {
+        let job_id = self.job.as_ref().ok_or("no job to submit a share for")?.id.clone();
+        let id = self.next_id();
+        self.pending.insert(id, job_id.clone());
+        let params = json!([self.user, job_id, format!("{:016x}", nonce)]);
+        self.send(id, "mining.submit", params).await
+    }
+
+    pub fn is_current(&self, job_id: &str) -> bool {
+        self.job.as_ref().is_some_and(|job| job.id == job_id)
+    }
+
+    pub fn shares(&self) -> ShareCounts {
+        self.shares
+    }
+
+    async fn handshake(&mut self) -> Result<(), Error> {
+        let socket = TcpStream::connect(&self.address).await?;
+        self.framed = Some(Framed::new(socket, LinesCodec::new()));
+        // Answers to shares sent on the dropped connection will never come, and its job is stale on this one
+        self.pending.clear();
+        self.job = None;
+        let id = self.next_id();
+        self.subscribe_id = Some(id);
+        let agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        self.send(id, "mining.subscribe", json!([agent])).await?;
+        let id = self.next_id();
+        self.send(id, "mining.authorize", json!([self.user, "x"])).await
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Error> {
+        self.framed = None;
+        let policy = self.reconnect;
+        let mut backoff = policy.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            tokio::time::sleep(backoff).await;
+            match self.handshake().await {
+                Ok(()) => {
+                    log_info!("Reconnected to {}", self.address);
+                    return Ok(());
+                }
+                Err(e) if policy.max_attempts.is_some_and(|max| attempt >= max) => {
+                    self.framed = None;
+                    return Err(
+                        format!("gave up reconnecting to {} after {} attempts: {}", self.address, attempt, e).into()
+                    );
+                }
+                Err(e) => {
+                    backoff = policy.next_backoff(backoff);
+                    log_warn!("Reconnecting to {} failed, retrying in {:?}: {}", self.address, backoff, e);
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, id: u64, method: &str, params: Value) -> Result<(), Error> {
+        let line = json!({"id": id, "method": method, "params": params}).to_string();
+        self.framed.as_mut().ok_or("not connected")?.send(line).await?;
+        Ok(())
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// The job the line completes, if any. Lines that aren't JSON or carry malformed parameters are skipped
+    /// with a warning rather than failing, a pool sending one odd message is still worth mining on.
+    fn handle_line(&mut self, line: &str) -> Option<StratumJob> {
+        let msg: Value = match serde_json::from_str(line) {
+            Ok(msg) => msg,
+            Err(e) => {
+                log_warn!("Skipping invalid stratum line {:?}: {}", line, e);
+                return None;
+            }
+        };
+        let params = &msg["params"];
+        match msg["method"].as_str() {
+            Some("mining.notify") => match self.parse_notify(params) {
+                Some(job) => {
+                    self.job = Some(job.clone());
+                    Some(job)
+                }
+                None => {
+                    log_warn!("Skipping malformed mining.notify: {}", line);
+                    None
+                }
+            },
+            Some("mining.set_difficulty") => match params[0].as_f64() {
+                Some(difficulty) => {
+                    self.target = difficulty_to_target(difficulty);
+                    log_info!("Stratum difficulty set to {}", difficulty);
+                    self.job.as_mut().map(|job| {
+                        job.target = self.target;
+                        job.clone()
+                    })
+                }
+                None => {
+                    log_warn!("Skipping malformed difficulty: {}", line);
+                    None
+                }
+            },
+            Some("mining.set_extranonce") | Some("set_extranonce") => {
+                if let Err(e) = self.set_extranonce(params) {
+                    log_warn!("Skipping {}: {}", line, e);
+                }
+                None
+            }
+            Some(method) => {
+                log_warn!("Ignoring unsupported stratum method {}", method);
+                None
+            }
+            None => {
+                self.handle_response(&msg);
+                None
+            }
+        }
+    }
+
+    fn handle_response(&mut self, msg: &Value) {
+        let id = msg["id"].as_u64();
+        if id.is_some() && id == self.subscribe_id {
+            self.subscribe_id = None;
+            let result = &msg["result"];
+            // Plain stratum answers `[subscriptions, extranonce, extranonce2_size]`. The Karlsen bridge answers
+            // `[true, "EthereumStratum/1.0.0"]` and sends the extranonce with `set_extranonce` afterwards.
+            if result[2].is_u64() {
+                if let Err(e) = self.set_extranonce(&json!([result[1], result[2]])) {
+                    log_warn!("Ignoring the extranonce of the subscribe answer {}: {}", msg, e);
+                }
+            }
+            return;
+        }
+        let job_id = match id.and_then(|id| self.pending.remove(&id)) {
+            Some(job_id) => job_id,
+            None => return, // authorize acknowledgements
+        };
+        // Some pools accept with `[true, ..]` rather than `true`
+        let accepted = msg["result"] == true || msg["result"][0] == true;
+        match &msg["error"] {
+            Value::Null if accepted => self.shares.accepted += 1,
+            Value::Array(error) if error.first().and_then(Value::as_u64) == Some(JOB_NOT_FOUND) => {
+                log_warn!("Stale share (job id: {})", job_id);
+                self.shares.stale += 1;
+            }
+            error => {
+                log_warn!("Share rejected (job id: {}): {}", job_id, error);
+                self.shares.rejected += 1;
+            }
+        }
+    }
+
+    fn set_extranonce(&mut self, params: &Value) -> Result<(), Error> {
+        let extranonce = params[0].as_str().ok_or("malformed extranonce")?;
+        // Bytes of the nonce left to the miner
+        let size = params[1].as_u64().ok_or("malformed extranonce size")?;
+        let fixed = match extranonce {
+            "" => 0,
+            _ => u64::from_str_radix(extranonce, 16)?,
+        };
+        (self.nonce_mask, self.nonce_fixed) = match size {
+            8.. => (u64::MAX, 0),
+            _ => ((1 << (size * 8)) - 1, fixed << (size * 8)),
+        };
+        Ok(())
+    }
+
+    fn parse_notify(&self, params: &Value) -> Option<StratumJob> {
+        let id = params[0].as_str()?.to_string();
+        let words = params[1].as_array().filter(|words| words.len() == 4)?;
+        let timestamp = params[2].as_u64()?;
+
+        let mut header = [0u8; HEADER_SIZE];
+        for (chunk, word) in header[..32].chunks_exact_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.as_u64()?.to_le_bytes());
+        }
+        header[32..40].copy_from_slice(&timestamp.to_le_bytes());
+        let matrix = generate_matrix(header[..32].try_into().unwrap());
+        Some(StratumJob {
+            id,
+            header,
+            matrix,
+            target: self.target,
+            nonce_mask: self.nonce_mask,
+            nonce_fixed: self.nonce_fixed,
+        })
+    }
+}
+
+/// The pool side of a stratum connection, for tests talking to a `StratumClient` over a local socket
+#[cfg(test)]
+pub(crate) mod mock_pool {
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::{Framed, LinesCodec};
+
+    pub(crate) type Pool = Framed<TcpStream, LinesCodec>;
+
+    pub(crate) async fn accept(listener: &TcpListener) -> Pool {
+        Framed::new(listener.accept().await.unwrap().0, LinesCodec::new())
+    }
+
+    pub(crate) async fn recv(pool: &mut Pool) -> Value {
+        serde_json::from_str(&pool.next().await.unwrap().unwrap()).unwrap()
+    }
+
+    pub(crate) async fn send(pool: &mut Pool, msg: Value) {
+        pool.send(msg.to_string()).await.unwrap();
+    }
+
+    /// Accepts the subscribe/authorize pair of `user` and hands out a 2 byte extranonce
+    pub(crate) async fn handshake(pool: &mut Pool, user: &str) {
+        let subscribe = recv(pool).await;
+        assert_eq!(subscribe["method"], "mining.subscribe");
+        let authorize = recv(pool).await;
+        assert_eq!(authorize["method"], "mining.authorize");
+        assert_eq!(authorize["params"][0], user);
+        send(pool, json!({"id": subscribe["id"], "result": [[], "abcd", 6], "error": null})).await;
+        send(pool, json!({"id": authorize["id"], "result": true, "error": null})).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header::generate_matrix;
+    use crate::stratum::mock_pool::{accept, handshake, recv, send};
+    use crate::stratum::{ReconnectPolicy, ShareCounts, StratumClient};
+    use crate::target::difficulty_to_target;
+    use futures::SinkExt;
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_notify_and_submit_framing() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool = tokio::spawn(async move {
+            let mut pool = accept(&listener).await;
+            handshake(&mut pool, "karlsen:miner").await;
+            send(&mut pool, json!({"id": null, "method": "mining.set_difficulty", "params": [4.0]})).await;
+            send(&mut pool, json!({"id": null, "method": "mining.notify", "params": ["7", [1, 2, 3, 4], 1234]})).await;
+
+            let submit = recv(&mut pool).await;
+            assert_eq!(submit["method"], "mining.submit");
+            assert_eq!(submit["params"], json!(["karlsen:miner", "7", "abcd0000000000ff"]));
+            send(&mut pool, json!({"id": submit["id"], "result": true, "error": null})).await;
+            let stale = recv(&mut pool).await;
+            send(&mut pool, json!({"id": stale["id"], "result": null, "error": [21, "Job not found", null]})).await;
+            send(&mut pool, json!({"id": null, "method": "mining.set_difficulty", "params": [8.0]})).await;
+        });
+
+        let mut client = StratumClient::connect(&address, "karlsen:miner", ReconnectPolicy::default()).await.unwrap();
+        let job = client.next_job().await.unwrap();
+        assert_eq!(job.id, "7");
+        assert_eq!(&job.header[..8], &1u64.to_le_bytes());
+        assert_eq!(&job.header[24..32], &4u64.to_le_bytes());
+        assert_eq!(&job.header[32..40], &1234u64.to_le_bytes());
+        assert_eq!(job.header[40..], [0u8; 32]);
+        assert_eq!(job.matrix, generate_matrix(job.header[..32].try_into().unwrap()));
+        assert_eq!(job.target, difficulty_to_target(4.0));
+        assert_eq!((job.nonce_mask, job.nonce_fixed), (0x0000_ffff_ffff_ffff, 0xabcd_0000_0000_0000));
+        assert!(client.is_current("7") && !client.is_current("6"));
+
+        client.submit(job.nonce_fixed | 0xff).await.unwrap();
+        client.submit(0xffff).await.unwrap();
+        // A difficulty change mid-job re-issues the same job with the new target
+        let retargeted = client.next_job().await.unwrap();
+        assert_eq!(retargeted.id, "7");
+        assert_eq!(retargeted.target, difficulty_to_target(8.0));
+        assert_eq!(client.shares(), ShareCounts { accepted: 1, stale: 1, rejected: 0 });
+        pool.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bridge_subscribe_and_bad_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool = tokio::spawn(async move {
+            let mut pool = accept(&listener).await;
+            let subscribe = recv(&mut pool).await;
+            let authorize = recv(&mut pool).await;
+            // The bridge's subscribe answer, the extranonce comes separately
+            send(&mut pool, json!({"id": subscribe["id"], "result": [true, "EthereumStratum/1.0.0"], "error": null}))
+                .await;
+            send(&mut pool, json!({"id": authorize["id"], "result": true, "error": null})).await;
+            // None of these stop the client
+            pool.send("not json".to_string()).await.unwrap();
+            send(&mut pool, json!({"id": null, "method": "mining.notify", "params": ["9", [1, 2], 5]})).await;
+            send(&mut pool, json!({"id": null, "method": "mining.set_difficulty", "params": ["hard"]})).await;
+            send(&mut pool, json!({"id": null, "method": "set_extranonce", "params": ["zz", 6]})).await;
+
+            send(&mut pool, json!({"id": null, "method": "set_extranonce", "params": ["abcd", 6]})).await;
+            send(&mut pool, json!({"id": null, "method": "mining.notify", "params": ["9", [1, 2, 3, 4], 5]})).await;
+            let submit = recv(&mut pool).await;
+            send(&mut pool, json!({"id": submit["id"], "result": [true, null], "error": null})).await;
+            send(&mut pool, json!({"id": null, "method": "mining.set_difficulty", "params": [2.0]})).await;
+            pool
+        });
+
+        let mut client = StratumClient::connect(&address, "karlsen:miner", ReconnectPolicy::default()).await.unwrap();
+        let job = tokio::time::timeout(Duration::from_secs(5), client.next_job()).await.unwrap().unwrap();
+        assert_eq!(job.id, "9");
+        assert_eq!(job.target, difficulty_to_target(1.0));
+        assert_eq!((job.nonce_mask, job.nonce_fixed), (0x0000_ffff_ffff_ffff, 0xabcd_0000_0000_0000));
+
+        client.submit(job.nonce_fixed | 1).await.unwrap();
+        let retargeted = client.next_job().await.unwrap();
+        assert_eq!(retargeted.target, difficulty_to_target(2.0));
+        assert_eq!(client.shares(), ShareCounts { accepted: 1, stale: 0, rejected: 0 });
+        drop(pool.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_after_disconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (notify_tx, notify_rx) = tokio::sync::oneshot::channel();
+        let pool = tokio::spawn(async move {
+            let mut pool = accept(&listener).await;
+            handshake(&mut pool, "karlsen:miner").await;
+            send(&mut pool, json!({"id": null, "method": "mining.notify", "params": ["7", [1, 2, 3, 4], 1]})).await;
+            drop(pool);
+
+            let mut pool = accept(&listener).await;
+            handshake(&mut pool, "karlsen:miner").await;
+            notify_rx.await.unwrap();
+            send(&mut pool, json!({"id": null, "method": "mining.notify", "params": ["8", [5, 6, 7, 8], 99]})).await;
+            pool
+        });
+
+        let policy = ReconnectPolicy {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+            ..ReconnectPolicy::default()
+        };
+        let mut client = StratumClient::connect(&address, "karlsen:miner", policy).await.unwrap();
+        let job = tokio::time::timeout(Duration::from_secs(5), client.next_job()).await.unwrap().unwrap();
+        assert_eq!(job.id, "7");
+
+        // Reconnected, but the new connection has no job yet: the one from before is stale
+        assert!(tokio::time::timeout(Duration::from_millis(200), client.next_job()).await.is_err());
+        assert!(!client.is_current("7"));
+        assert!(client.submit(1).await.is_err());
+        notify_tx.send(()).unwrap();
+        let job = tokio::time::timeout(Duration::from_secs(5), client.next_job()).await.unwrap().unwrap();
+        assert_eq!(job.id, "8");
+        assert_eq!(&job.header[32..40], &99u64.to_le_bytes());
+        drop(pool.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_new_connects_on_first_job() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut client = StratumClient::new(&address, "karlsen:miner", ReconnectPolicy::default());
+        let pool = tokio::spawn(async move {
+            let mut pool = accept(&listener).await;
+            handshake(&mut pool, "karlsen:miner").await;
+            send(&mut pool, json!({"id": null, "method": "mining.notify", "params": ["7", [1, 2, 3, 4], 1]})).await;
+            pool
+        });
+
+        assert!(client.submit(1).await.is_err());
+        let job = tokio::time::timeout(Duration::from_secs(5), client.next_job()).await.unwrap().unwrap();
+        assert_eq!(job.id, "7");
+        assert_eq!(job.nonce_fixed, 0xabcd_0000_0000_0000);
+        drop(pool.await.unwrap());
+    }
+
+    #[test]
+    fn test_next_backoff() {
+        let policy = ReconnectPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(250),
+            multiplier: 1.5,
+            max_attempts: None,
+        };
+        assert_eq!(policy.next_backoff(Duration::from_millis(100)), Duration::from_millis(150));
+        assert_eq!(policy.next_backoff(Duration::from_millis(200)), Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool = tokio::spawn(async move {
+            let mut pool = accept(&listener).await;
+            handshake(&mut pool, "karlsen:miner").await;
+            // Refuses every reconnection from now on
+            drop(listener);
+        });
+
+        let policy = ReconnectPolicy {
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(50),
+            multiplier: 2.0,
+            max_attempts: Some(3),
+        };
+        let mut client = StratumClient::connect(&address, "karlsen:miner", policy).await.unwrap();
+        pool.await.unwrap();
+        let start = Instant::now();
+        let e = tokio::time::timeout(Duration::from_secs(5), client.next_job()).await.unwrap().err().unwrap();
+        // Waited 20, 40 and then 50ms
+        assert!(start.elapsed() >= Duration::from_millis(110), "{:?}", start.elapsed());
+        assert!(e.to_string().starts_with(&format!("gave up reconnecting to {} after 3 attempts", address)), "{}", e);
+    }
+}
@@ -0,0 +1,83 @@
+//! Guards the pool against the same share twice.
+//!
+//! Overlapping batches can make a worker report one winning nonce more than once, which pools reject as a
+//! duplicate share. `SubmissionCache` remembers what was submitted for the last few jobs.
+use std::collections::{HashSet, VecDeque};
+
+/// Jobs remembered by `SubmissionCache::default`, the current one and the one it replaced
+const DEFAULT_MAX_JOBS: usize = 2;
+
+pub struct SubmissionCache {
+    max_jobs: usize,
+    // Most recent job first
+    jobs: VecDeque<(u64, HashSet<u64>)>,
+}
+
+impl SubmissionCache {
+    /// Remembers the nonces of the `max_jobs` most recently seen jobs, at least one
+    pub fn new(max_jobs: usize) -> Self {
+        let max_jobs = max_jobs.max(1);
+        Self { max_jobs, jobs: VecDeque::with_capacity(max_jobs) }
+    }
+
+    /// Whether `nonce` wasn't submitted for `job_id` yet, and records it. A job id not seen before counts as a new
+    /// template and evicts the oldest job once more than `max_jobs` are remembered.
+    pub fn should_submit(&mut self, job_id: u64, nonce: u64) -> bool {
+        let index = match self.jobs.iter().position(|(id, _)| *id == job_id) {
+            Some(index) => index,
+            None => {
+                self.jobs.push_front((job_id, HashSet::new()));
+                self.jobs.truncate(self.max_jobs);
+                0
+            }
+        };
+        self.jobs[index].1.insert(nonce)
+    }
+
+    /// How many jobs are currently remembered
+    pub fn job_count(&self) -> usize {
+        self.jobs.len()
+    }
+}
+
+impl Default for SubmissionCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_JOBS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::submission::SubmissionCache;
+
+    #[test]
+    fn test_repeated_nonce() {
+        let mut cache = SubmissionCache::default();
+        assert!(cache.should_submit(1, 42));
+        assert!(!cache.should_submit(1, 42));
+        assert!(cache.should_submit(1, 43));
+        // The same nonce is a different share on another job
+        assert!(cache.should_submit(2, 42));
+        assert!(!cache.should_submit(2, 42));
+    }
+
+    #[test]
+    fn test_job_rollover_evicts() {
+        let mut cache = SubmissionCache::new(2);
+        assert!(cache.should_submit(1, 42));
+        assert!(cache.should_submit(2, 7));
+        // Still within the window
+        assert!(!cache.should_submit(1, 42));
+        assert!(cache.should_submit(3, 9));
+        assert_eq!(cache.job_count(), 2);
+        // Job 1 was evicted by job 3, so it's new again
+        assert!(cache.should_submit(1, 42));
+        assert!(!cache.should_submit(3, 9));
+        assert!(cache.should_submit(2, 7));
+
+        let mut cache = SubmissionCache::new(0);
+        assert!(cache.should_submit(1, 42));
+        assert!(cache.should_submit(2, 42));
+        assert_eq!(cache.job_count(), 1);
+    }
+}
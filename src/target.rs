@@ -23,6 +23,146 @@ pub fn u256_from_compact_target(bits: u32) -> Uint256 {
     }
 }
 
+/// The target block headers encode in `bits`: a 3 byte mantissa times `256^(exponent - 3)`, with the exponent in
+/// the top byte. Mantissas with the sign bit set decode to a zero target, as in consensus.
+pub fn bits_to_target(bits: u32) -> [u64; 4] {
+    u256_from_compact_target(bits).0
+}
+
+/// The inverse of [`bits_to_target`]. The encoding keeps only the 3 most significant bytes, so the bits decode to
+/// `target` truncated to them.
+pub fn target_to_bits(target: &[u64; 4]) -> u32 {
+    let bytes = Uint256::new(*target).to_be_bytes();
+    let first = match bytes.iter().position(|&byte| byte != 0) {
+        Some(first) => first,
+        None => return 0,
+    };
+    let mut size = (32 - first) as u32;
+    let mut mantissa = bytes[first..].iter().chain(&[0, 0]).take(3).fold(0u32, |acc, &byte| acc << 8 | byte as u32);
+    // The mantissa is signed, a set top bit moves one byte into the exponent
+    if mantissa & 0x800000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    size << 24 | mantissa
+}
+
+/// Pool difficulty 1, i.e. `0xffff * 2^208`
+const MAX_TARGET: [u64; 4] = [0, 0, 0, 0xffff << 16];
+
+/// `target = MAX_TARGET / difficulty` rounded down, saturating to the largest target for difficulties that are too
+/// small (including zero, negative and NaN). Words are little-endian, as everywhere else.
+///
+/// The division is done on integers: `difficulty` is exactly `mantissa * 2^exponent`, so the target is
+/// `MAX_TARGET * 2^-exponent / mantissa` and the only rounding is that of the final division.
+pub fn difficulty_to_target(difficulty: f64) -> [u64; 4] {
+    if difficulty.is_nan() || difficulty <= 0.0 {
+        return [u64::MAX; 4];
+    }
+    if difficulty.is_infinite() {
+        return [0; 4];
+    }
+    let (mantissa, exponent) = f64_parts(difficulty);
+    shifted_quotient(&MAX_TARGET, -exponent, mantissa).unwrap_or([u64::MAX; 4])
+}
+
+/// The inverse of [`difficulty_to_target`], an all-zero target has an infinite difficulty
+pub fn target_to_difficulty(target: &[u64; 4]) -> f64 {
+    target_to_f64(&MAX_TARGET) / target_to_f64(target)
+}
+
+/// How many hashes it takes on average to find one meeting `target`, `2^256 / (target + 1)`
+pub fn expected_hashes(target: &[u64; 4]) -> f64 {
+    115792089237316195423570985008687907853269984665640564039457584007913129639936.0 / (target_to_f64(target) + 1.0)
+}
+
+fn target_to_f64(target: &[u64; 4]) -> f64 {
+    target.iter().rev().fold(0f64, |acc, &word| acc * 18446744073709551616.0 + word as f64)
+}
+
+/// The `[u64; 4]` target every function here and `Worker::load_block_constants` take, from 32 bytes with the least
+/// significant one first, as stratum pools and `replay` files usually send it
+pub fn target_from_le_bytes(bytes: &[u8; 32]) -> [u64; 4] {
+    Uint256::from_le_bytes(*bytes).0
+}
+
+/// Like [`target_from_le_bytes`] for the most significant byte first, as hex targets from node RPC usually are
+pub fn target_from_be_bytes(bytes: &[u8; 32]) -> [u64; 4] {
+    let mut le = *bytes;
+    le.reverse();
+    target_from_le_bytes(&le)
+}
+
+/// The inverse of [`target_from_le_bytes`]
+pub fn target_to_le_bytes(target: &[u64; 4]) -> [u8; 32] {
+    Uint256::new(*target).to_le_bytes()
+}
+
+/// The inverse of [`target_from_be_bytes`]
+pub fn target_to_be_bytes(target: &[u64; 4]) -> [u8; 32] {
+    Uint256::new(*target).to_be_bytes()
+}
+
+/// Whether `hash` is a valid proof of work for `target`, i.e. `hash <= target`.
+///
+/// Both are little-endian: `hash[0]` is the least significant byte (the order `heavy_hash` produces it in)
+/// and `target[0]` is the least significant word (the order `Worker::load_block_constants` receives it in).
+/// Targets given as bytes go through [`target_from_le_bytes`] or [`target_from_be_bytes`] first, by the order
+/// they come in; their words are never in any other order.
+pub fn hash_meets_target(hash: &[u8; 32], target: &[u64; 4]) -> bool {
+    Uint256::from_le_bytes(*hash) <= Uint256::new(*target)
+}
+
+/// A finite, positive `value` as `(mantissa, exponent)` with `value == mantissa * 2^exponent`
+fn f64_parts(value: f64) -> (u64, i32) {
+    let bits = value.to_bits();
+    let biased_exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1u64 << 52) - 1);
+    match biased_exponent {
+        0 => (fraction, -1074),
+        _ => (fraction | (1u64 << 52), biased_exponent - 1075),
+    }
+}
+
+/// `floor(value * 2^shift / divisor)`, `None` if that doesn't fit 256 bits
+fn shifted_quotient(value: &[u64; 4], shift: i32, divisor: u64) -> Option<[u64; 4]> {
+    // With the two spare words any shift up to 128 fits before dividing; past that even `divisor = u64::MAX`
+    // leaves more than 256 bits of a non-zero value
+    let mut wide = [0u64; 6];
+    match shift {
+        s if s > 128 => return value.iter().all(|&word| word == 0).then_some([0; 4]),
+        s if s <= -256 => return Some([0; 4]),
+        s if s >= 0 => {
+            let (words, bits) = (s as usize / 64, s as u32 % 64);
+            for (i, &word) in value.iter().enumerate() {
+                wide[i + words] |= word << bits;
+                if bits > 0 {
+                    wide[i + words + 1] |= word >> (64 - bits);
+                }
+            }
+        }
+        s => {
+            let (words, bits) = ((-s) as usize / 64, (-s) as u32 % 64);
+            for i in 0..4 - words {
+                wide[i] = value[i + words] >> bits;
+                if bits > 0 && i + words + 1 < 4 {
+                    wide[i] |= value[i + words + 1] << (64 - bits);
+                }
+            }
+        }
+    }
+    let mut remainder = 0u128;
+    for word in wide.iter_mut().rev() {
+        let current = remainder << 64 | *word as u128;
+        *word = (current / divisor as u128) as u64;
+        remainder = current % divisor as u128;
+    }
+    match wide[4..] {
+        [0, 0] => Some([wide[0], wide[1], wide[2], wide[3]]),
+        _ => None,
+    }
+}
+
 /// Little-endian large integer type
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
 pub struct Uint256(pub [u64; 4]);
@@ -115,3 +255,114 @@ impl core::ops::Shl<usize> for Uint256 {
         Uint256(ret)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::target::{
+        bits_to_target, difficulty_to_target, hash_meets_target, target_from_be_bytes, target_from_le_bytes,
+        target_to_be_bytes, target_to_bits, target_to_difficulty, target_to_le_bytes, Uint256,
+    };
+
+    #[test]
+    fn test_difficulty_to_target() {
+        assert_eq!(difficulty_to_target(1.0), [0, 0, 0, 0x00000000ffff0000]);
+        assert_eq!(difficulty_to_target(2.0), [0, 0, 0, 0x000000007fff8000]);
+        assert_eq!(difficulty_to_target(0.5), [0, 0, 0, 0x00000001fffe0000]);
+        assert_eq!(difficulty_to_target(65535.0), [0, 0, 0, 1 << 16]);
+        assert_eq!(difficulty_to_target(65535.0 * 2f64.powi(208)), [1, 0, 0, 0]);
+        assert_eq!(difficulty_to_target(0.0), [u64::MAX; 4]);
+        assert_eq!(difficulty_to_target(1e-30), [u64::MAX; 4]);
+        assert_eq!(difficulty_to_target(f64::INFINITY), [0; 4]);
+        assert_eq!(difficulty_to_target(2f64.powi(-32)), [0, 0, 0, 0xffff << 48]);
+        assert_eq!(difficulty_to_target(1e300), [0; 4]);
+    }
+
+    #[test]
+    fn test_integer_difficulty_target_is_exact() {
+        // floor(0xffff * 2^208 / difficulty), down to the last of the bits an f64 quotient would lose
+        assert_eq!(difficulty_to_target(7.0), [0x2492492492492492, 0x9249249249249249, 0x4924924924924924, 0x24922492]);
+        assert_eq!(
+            difficulty_to_target(3_000_000_007.0),
+            [0x284bb7c34a787690, 0x1d6ffd49bf4d08d8, 0x6e7f8f73e5a3de03, 0x1]
+        );
+    }
+
+    #[test]
+    fn test_target_to_difficulty() {
+        assert_eq!(target_to_difficulty(&[0, 0, 0, 0x00000000ffff0000]), 1.0);
+        assert_eq!(target_to_difficulty(&[0; 4]), f64::INFINITY);
+        for difficulty in [0.001, 1.0, 1234.5678, 4.2e9, 1.5e15] {
+            let round_trip = target_to_difficulty(&difficulty_to_target(difficulty));
+            assert!((round_trip - difficulty).abs() / difficulty < 1e-12, "{} != {}", round_trip, difficulty);
+        }
+    }
+
+    #[test]
+    fn test_bits_to_target() {
+        // Genesis bits of the Kaspa-derived networks and of Bitcoin, whose `0x1d00ffff` is pool difficulty 1
+        let pairs = [
+            (0x207fffff, [0, 0, 0, 0x7fffff0000000000]),
+            (0x1e7fffff, [0, 0, 0, 0x00007fffff000000]),
+            (0x1d00ffff, [0, 0, 0, 0x00000000ffff0000]),
+            (0x1b0404cb, [0, 0, 0, 0x00000000000404cb]),
+            (0x1a0ffff0, [0, 0, 0xf000000000000000, 0x0000000000000fff]),
+            (0x03123456, [0x123456, 0, 0, 0]),
+            (0x01120000, [0x12, 0, 0, 0]),
+        ];
+        for (bits, target) in pairs {
+            assert_eq!(bits_to_target(bits), target, "{:#x}", bits);
+            assert_eq!(target_to_bits(&target), bits, "{:#x}", bits);
+        }
+        assert_eq!(bits_to_target(target_to_bits(&difficulty_to_target(1.0))), difficulty_to_target(1.0));
+        assert_eq!(target_to_bits(&[0; 4]), 0);
+        // Negative mantissas decode to zero, and a set top bit is never encoded
+        assert_eq!(bits_to_target(0x04923456), [0; 4]);
+        assert_eq!(target_to_bits(&[0x80, 0, 0, 0]), 0x02008000);
+        // Bytes past the mantissa are truncated
+        assert_eq!(target_to_bits(&[0x12345678, 0, 0, 0]), 0x04123456);
+        assert_eq!(bits_to_target(0x04123456), [0x12345600, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_hash_meets_target() {
+        let target = [0x1111, 0x2222, 0, 0x00000000ffff0000];
+        let at = Uint256::new(target).to_le_bytes();
+        let mut below = at;
+        below[0] -= 1;
+        let mut above = at;
+        above[0] += 1;
+        assert!(hash_meets_target(&at, &target));
+        assert!(hash_meets_target(&below, &target));
+        assert!(!hash_meets_target(&above, &target));
+
+        // A larger most significant byte outweighs everything below it
+        let mut high = [0u8; 32];
+        high[31] = 1;
+        assert!(!hash_meets_target(&high, &target));
+        assert!(hash_meets_target(&[0xff; 32], &[u64::MAX; 4]));
+    }
+
+    #[test]
+    fn test_target_bytes_round_trip() {
+        let target = [0x0706050403020100, 0x0f0e0d0c0b0a0908, 0x1716151413121110, 0x1f1e1d1c1b1a1918];
+        let le: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let mut be = le;
+        be.reverse();
+
+        assert_eq!(target_from_le_bytes(&le), target);
+        assert_eq!(target_from_be_bytes(&be), target);
+        assert_eq!(target_to_le_bytes(&target), le);
+        assert_eq!(target_to_be_bytes(&target), be);
+        for target in [[0; 4], [u64::MAX; 4], difficulty_to_target(1.0), [1, 0, 0, 1 << 63]] {
+            assert_eq!(target_from_le_bytes(&target_to_le_bytes(&target)), target);
+            assert_eq!(target_from_be_bytes(&target_to_be_bytes(&target)), target);
+        }
+
+        // The same bytes read the wrong way round are a different target, the least significant byte
+        // of one being the most significant of the other
+        let one_le: [u8; 32] = core::array::from_fn(|i| u8::from(i == 0));
+        assert_eq!(target_from_le_bytes(&one_le), [1, 0, 0, 0]);
+        assert_eq!(target_from_be_bytes(&one_le), [0, 0, 0, 1 << 56]);
+        assert!(hash_meets_target(&one_le, &target_from_le_bytes(&one_le)));
+    }
+}
@@ -0,0 +1,805 @@
+//! In-tree plugin for exercising the host without a GPU, enabled with the `testing` feature.
+//!
+//! `NullWorker` accepts any constants and computes nothing, but can be scripted to report a found nonce,
+//! which makes pool and stratum plumbing testable deterministically. It also records what the host applied to
+//! it (constants, clock offsets, the calls it made) and reports scripted power draw and metrics, for testing
+//! host-side policies. Its `with_*` hooks script the device behaviors those policies react to.
+use crate::events::{EventSink, MinerEvent};
+use crate::metrics::{self, Metric};
+use crate::{
+    create_plugin, planted_self_check, take_created, Error, JobConstants, KarlsenMinerError, MiningJob, NonceRange,
+    Plugin, Worker, WorkerError, WorkerSpec,
+};
+use clap::ArgMatches;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+const PLUGIN_NAME: &str = "Null Worker";
+
+/// Makes the error a failing `sync` reports out of its message
+type SyncError = fn(String) -> WorkerError;
+
+/// What each launch finds
+#[derive(Clone, Copy)]
+enum Finds {
+    Nothing,
+    Nonce(u64),
+    FixedNonce,
+    LaunchNumber,
+}
+
+pub struct NullWorker {
+    id: String,
+    workload: usize,
+    finds: Finds,
+    // The device's output buffer, holding the nonces found since it was last read
+    found: Vec<u64>,
+    // None for a single slot each launch overwrites
+    output_capacity: Option<usize>,
+    scanned: Vec<u64>,
+    calls: RefCell<Vec<&'static str>>,
+    // `try_sync` polls each async launch stays busy for, None to complete it right away like the default
+    pipelining: Option<u32>,
+    busy_polls: Cell<u32>,
+    // Cap on queued async launches, None while launches aren't queued like the default `Worker::set_max_inflight`
+    max_inflight: Option<u32>,
+    inflight: Cell<u32>,
+    job_id: Option<u64>,
+    launches: u64,
+    hashes_computed: u64,
+    counts_hashes: bool,
+    // Hashes a launch at the given workload computes, the workload itself when None
+    hashes_per_launch: Option<Box<dyn Fn(usize) -> u64>>,
+    // None while the worker tracks no ranges like the default `Worker::covered_ranges`
+    covered: Option<Vec<NonceRange>>,
+    launch_time: Duration,
+    mining_time: Duration,
+    warmup_time: Option<Duration>,
+    // When the last launch hashed, `None` before the first one
+    progress: Option<Instant>,
+    events: Option<EventSink>,
+    device_lost: bool,
+    // Launches whose `sync` fails, and what with
+    flaky_sync: Option<(u64, SyncError)>,
+    loaded: Vec<JobConstants>,
+    // None while the worker refuses clock profiles like the default `Worker::apply_clock_profile`
+    clock_offsets: Option<Vec<(i32, i32)>>,
+    // Watts drawn at the workload the worker was built with
+    power_watts: Option<(u32, usize)>,
+    fixed_workload: bool,
+    max_workload: Option<usize>,
+    metrics: Option<Vec<Metric>>,
+    temperature: Option<u32>,
+    memory_usage: Option<u64>,
+    reject_zero_matrix: bool,
+    self_verifies: bool,
+    planted_self_check: bool,
+    // None while the worker ignores `set_batch_size` like the default
+    batch_size: Option<u64>,
+    nonce_le: bool,
+    paused: bool,
+    pauses: u32,
+    paused_launches: u32,
+    stop_requested: AtomicBool,
+    stop_after: Option<u64>,
+}
+
+impl NullWorker {
+    pub fn new(id: &str, workload: usize) -> Self {
+        Self {
+            id: id.to_string(),
+            workload,
+            finds: Finds::Nothing,
+            found: Vec::new(),
+            output_capacity: None,
+            scanned: Vec::new(),
+            calls: RefCell::new(Vec::new()),
+            pipelining: None,
+            busy_polls: Cell::new(0),
+            max_inflight: None,
+            inflight: Cell::new(0),
+            job_id: None,
+            launches: 0,
+            hashes_computed: 0,
+            counts_hashes: true,
+            hashes_per_launch: None,
+            covered: None,
+            launch_time: Duration::ZERO,
+            mining_time: Duration::ZERO,
+            warmup_time: None,
+            progress: None,
+            events: None,
+            device_lost: false,
+            flaky_sync: None,
+            loaded: Vec::new(),
+            clock_offsets: None,
+            power_watts: None,
+            fixed_workload: false,
+            max_workload: None,
+            metrics: None,
+            temperature: None,
+            memory_usage: None,
+            reject_zero_matrix: false,
+            self_verifies: false,
+            planted_self_check: false,
+            batch_size: None,
+            nonce_le: true,
+            paused: false,
+            pauses: 0,
+            paused_launches: 0,
+            stop_requested: AtomicBool::new(false),
+            stop_after: None,
+        }
+    }
+
+    fn emit(&self, event: MinerEvent) {
+        if let Some(events) = &self.events {
+            events.emit(event);
+        }
+    }
+
+    /// Reports `nonce` as found after every launch
+    pub fn with_found_nonce(mut self, nonce: u64) -> Self {
+        self.finds = Finds::Nonce(nonce);
+        self
+    }
+
+    /// Reports the `nonce_fixed` of each launch as found, so every launch finds a nonce of the caller's choosing
+    pub fn with_found_fixed_nonce(mut self) -> Self {
+        self.finds = Finds::FixedNonce;
+        self
+    }
+
+    /// Reports the number of each launch as found, the first one finding 1, so the finds of different launches
+    /// tell apart
+    pub fn with_found_launch_number(mut self) -> Self {
+        self.finds = Finds::LaunchNumber;
+        self
+    }
+
+    /// Keeps the nonces found until the output is read, overflowing beyond `capacity` of them
+    pub fn with_output_buffer(mut self, capacity: usize) -> Self {
+        self.output_capacity = Some(capacity);
+        self
+    }
+
+    /// Fails every `sync` with `WorkerError::DeviceLost`
+    pub fn with_device_lost(mut self) -> Self {
+        self.device_lost = true;
+        self
+    }
+
+    /// Fails `sync` after each of the first `failures` launches with what `fail_with` makes of `launch N`
+    pub fn with_flaky_sync(mut self, failures: u64, fail_with: SyncError) -> Self {
+        self.flaky_sync = Some((failures, fail_with));
+        self
+    }
+
+    /// Keeps each `calculate_hash_async` launch running for `busy_polls` `try_sync` calls, and fails reading
+    /// the output of one that didn't complete yet
+    pub fn with_pipelining(mut self, busy_polls: u32) -> Self {
+        self.pipelining = Some(busy_polls);
+        self
+    }
+
+    /// Queues `calculate_hash_async` launches up to the `set_max_inflight` cap, refusing those beyond it;
+    /// `try_sync` completes the oldest queued launch and `sync` all of them
+    pub fn with_launch_queue(mut self) -> Self {
+        self.max_inflight = Some(u32::MAX);
+        self
+    }
+
+    /// Honors `set_batch_size` instead of ignoring it
+    pub fn with_batch_size_control(mut self) -> Self {
+        self.batch_size = Some(self.workload as u64);
+        self
+    }
+
+    /// Takes `launch_time` per launch, however large the workload
+    pub fn with_launch_time(mut self, launch_time: Duration) -> Self {
+        self.launch_time = launch_time;
+        self
+    }
+
+    /// Warms up for `warmup_time`, a launch's worth of hashes, and fails the test when it happens with a job
+    /// loaded already
+    pub fn with_warmup(mut self, warmup_time: Duration) -> Self {
+        self.warmup_time = Some(warmup_time);
+        self
+    }
+
+    /// Computes `hashes(workload)` hashes per launch rather than its workload, e.g. for a device that slows down
+    /// at some workloads
+    pub fn with_hashes_per_launch(mut self, hashes: impl Fn(usize) -> u64 + 'static) -> Self {
+        self.hashes_per_launch = Some(Box::new(hashes));
+        self
+    }
+
+    /// Tracks the ranges `calculate_hash_range` launched over for the current job, reporting them from
+    /// `covered_ranges`
+    pub fn with_covered_ranges(mut self) -> Self {
+        self.covered = Some(Vec::new());
+        self
+    }
+
+    /// Stops itself once it made `launches` launches, as if `request_stop` was called from another thread
+    pub fn with_stop_after(mut self, launches: u64) -> Self {
+        self.stop_after = Some(launches);
+        self
+    }
+
+    /// Reports no hashes computed, like backends that don't count them
+    pub fn without_hash_count(mut self) -> Self {
+        self.counts_hashes = false;
+        self
+    }
+
+    /// Accepts and records clock profiles instead of refusing them
+    pub fn with_clock_control(mut self) -> Self {
+        self.clock_offsets = Some(Vec::new());
+        self
+    }
+
+    /// Draws `watts` at the current workload and proportionally less or more at others
+    pub fn with_power_watts(mut self, watts: u32) -> Self {
+        self.power_watts = Some((watts, self.workload));
+        self
+    }
+
+    /// Rejects every `set_workload` like the default
+    pub fn with_fixed_workload(mut self) -> Self {
+        self.fixed_workload = true;
+        self
+    }
+
+    /// Reads `celsius` off its sensor
+    pub fn with_temperature(mut self, celsius: u32) -> Self {
+        self.temperature = Some(celsius);
+        self
+    }
+
+    /// Reports `bytes` of device memory allocated for its buffers
+    pub fn with_memory_usage(mut self, bytes: u64) -> Self {
+        self.memory_usage = Some(bytes);
+        self
+    }
+
+    /// Fails every `set_workload` above `max_workload` as running the device out of memory
+    pub fn with_max_workload(mut self, max_workload: usize) -> Self {
+        self.max_workload = Some(max_workload);
+        self
+    }
+
+    /// Reports `metrics` instead of the default ones derived from `Worker::stats`
+    pub fn with_metrics(mut self, metrics: Vec<Metric>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Fails loading a job whose matrix is all zeros, as a real device rejects constants it can't hash with
+    pub fn with_zero_matrix_rejected(mut self) -> Self {
+        self.reject_zero_matrix = true;
+        self
+    }
+
+    /// Claims to verify the nonces it reports itself, so the host trusts them without recomputing
+    pub fn with_self_verification(mut self) -> Self {
+        self.self_verifies = true;
+        self
+    }
+
+    /// Checks itself with `planted_self_check` rather than passing like the default, so it only passes when
+    /// scripted to find the planted nonce, e.g. with `with_found_fixed_nonce`
+    pub fn with_planted_self_check(mut self) -> Self {
+        self.planted_self_check = true;
+        self
+    }
+
+    /// The constants of every job loaded so far, oldest first; those from `load_block_constants` as job 0
+    pub fn loaded_constants(&self) -> &[JobConstants] {
+        &self.loaded
+    }
+
+    /// Launches that hashed, i.e. made with a job loaded
+    pub fn launches(&self) -> u64 {
+        self.launches
+    }
+
+    /// Time spent in launches that hashed
+    pub fn mining_time(&self) -> Duration {
+        self.mining_time
+    }
+
+    /// How often `pause` was called
+    pub fn pauses(&self) -> u32 {
+        self.pauses
+    }
+
+    /// Launches attempted while paused, which a host should never make
+    pub fn paused_launches(&self) -> u32 {
+        self.paused_launches
+    }
+
+    /// The `Worker` calls made so far, oldest first: `calculate_hash`, `sync`, `enqueue` (`calculate_hash_async`),
+    /// `try_sync` and `copy_output_to`
+    pub fn calls(&self) -> Vec<&'static str> {
+        self.calls.borrow().clone()
+    }
+
+    /// The nonces the last launch was handed, empty for one over the whole workload
+    pub fn scanned_nonces(&self) -> &[u64] {
+        &self.scanned
+    }
+
+    /// Every `(core_offset_mhz, mem_offset_mhz)` accepted by `apply_clock_profile`, oldest first
+    pub fn clock_offsets(&self) -> &[(i32, i32)] {
+        self.clock_offsets.as_deref().unwrap_or_default()
+    }
+
+    fn load(
+        &mut self,
+        header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+        job_id: u64,
+    ) -> Result<(), Error> {
+        if self.reject_zero_matrix && matrix.iter().flatten().all(|&v| v == 0) {
+            return Err("matrix is all zeros".into());
+        }
+        self.stop_requested.store(false, Ordering::Relaxed);
+        self.loaded.push(JobConstants { header: *header, matrix: *matrix, target: *target, job_id });
+        self.job_id = Some(job_id);
+        self.found.clear();
+        if let Some(covered) = &mut self.covered {
+            covered.clear();
+        }
+        Ok(())
+    }
+
+    fn launch_hashes(&self) -> u64 {
+        match &self.hashes_per_launch {
+            Some(hashes) => hashes(self.workload),
+            None => self.workload as u64,
+        }
+    }
+
+    fn device_status(&self) -> Result<(), WorkerError> {
+        if self.device_lost {
+            return Err(WorkerError::DeviceLost(self.id.clone()));
+        }
+        match self.flaky_sync {
+            Some((failures, fail_with)) if self.launches <= failures => {
+                Err(fail_with(format!("launch {}", self.launches)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Worker for NullWorker {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn source_plugin(&self) -> &'static str {
+        PLUGIN_NAME
+    }
+
+    fn load_block_constants(
+        &mut self,
+        hash_header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+    ) -> Result<(), Error> {
+        self.load(hash_header, matrix, target, 0)
+    }
+
+    fn load_job(&mut self, job: &MiningJob) -> Result<(), Error> {
+        self.load(&job.header, &job.matrix, &job.target, job.job_id)?;
+        self.emit(MinerEvent::JobApplied { worker: self.id.clone(), job_id: job.job_id });
+        Ok(())
+    }
+
+    fn current_job_id(&self) -> Option<u64> {
+        self.job_id
+    }
+
+    /// Does nothing before the first `load_block_constants`, like a real device without a job, nor while paused
+    /// or asked to stop
+    fn calculate_hash(&mut self, nonces: Option<&Vec<u64>>, _nonce_mask: u64, nonce_fixed: u64) {
+        self.calls.borrow_mut().push("calculate_hash");
+        if self.paused {
+            self.paused_launches += 1;
+            return;
+        }
+        if self.stop_requested() {
+            return;
+        }
+        if let Some(job_id) = self.job_id {
+            let start = Instant::now();
+            std::thread::sleep(self.launch_time);
+            self.mining_time += start.elapsed();
+            self.launches += 1;
+            if self.stop_after == Some(self.launches) {
+                self.request_stop();
+            }
+            self.scanned = nonces.cloned().unwrap_or_default();
+            let found = match self.finds {
+                Finds::Nothing => None,
+                Finds::Nonce(nonce) => Some(nonce),
+                Finds::FixedNonce => Some(nonce_fixed).filter(|&nonce| nonce != 0),
+                Finds::LaunchNumber => Some(self.launches),
+            };
+            self.hashes_computed += self.launch_hashes();
+            self.progress = Some(Instant::now());
+            if self.output_capacity.is_none() {
+                self.found.clear();
+            }
+            if let Some(nonce) = found {
+                self.found.push(nonce);
+                self.emit(MinerEvent::NonceFound { worker: self.id.clone(), nonce, job_id });
+            }
+        }
+    }
+
+    fn calculate_hash_range(&mut self, start: u64, count: u64) {
+        let nonces: Vec<u64> = (start..start.saturating_add(count)).collect();
+        let launches = self.launches;
+        self.calculate_hash(Some(&nonces), 0, 0);
+        if let (Some(covered), true) = (&mut self.covered, self.launches > launches) {
+            match covered.last_mut() {
+                Some(last) if last.end() == start => last.len += nonces.len() as u64,
+                _ => covered.push(NonceRange { start, len: nonces.len() as u64 }),
+            }
+        }
+    }
+
+    fn covered_ranges(&self) -> Vec<NonceRange> {
+        self.covered.clone().unwrap_or_default()
+    }
+
+    fn sync(&self) -> Result<(), WorkerError> {
+        self.calls.borrow_mut().push("sync");
+        self.busy_polls.set(0);
+        self.inflight.set(0);
+        self.device_status()
+    }
+
+    fn calculate_hash_async(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64) {
+        self.calls.borrow_mut().push("enqueue");
+        if let Some(max_inflight) = self.max_inflight {
+            if self.inflight.get() >= max_inflight {
+                return;
+            }
+            self.inflight.set(self.inflight.get() + 1);
+        }
+        self.busy_polls.set(self.pipelining.unwrap_or(0));
+        self.calculate_hash(nonces, nonce_mask, nonce_fixed);
+    }
+
+    fn try_sync(&self) -> Result<bool, WorkerError> {
+        self.calls.borrow_mut().push("try_sync");
+        let done = match self.max_inflight {
+            Some(_) => {
+                self.inflight.set(self.inflight.get().saturating_sub(1));
+                self.inflight.get() == 0
+            }
+            None => {
+                let busy = self.busy_polls.get();
+                self.busy_polls.set(busy.saturating_sub(1));
+                busy == 0
+            }
+        };
+        self.device_status().map(|()| done)
+    }
+
+    fn set_max_inflight(&mut self, max_inflight: u32) {
+        if let Some(max) = &mut self.max_inflight {
+            *max = max_inflight;
+        }
+    }
+
+    fn inflight(&self) -> u32 {
+        self.inflight.get()
+    }
+
+    fn get_workload(&self) -> usize {
+        self.workload
+    }
+
+    fn set_batch_size(&mut self, hashes_per_launch: u64) {
+        if let Some(batch_size) = &mut self.batch_size {
+            *batch_size = hashes_per_launch;
+        }
+    }
+
+    fn batch_size(&self) -> u64 {
+        self.batch_size.unwrap_or(self.workload as u64)
+    }
+
+    fn set_workload(&mut self, workload: usize) -> Result<(), Error> {
+        if self.fixed_workload {
+            return Err("changing the workload is unsupported by this worker".into());
+        }
+        if self.max_workload.is_some_and(|max_workload| workload > max_workload) {
+            return Err("out of device memory".into());
+        }
+        self.workload = workload;
+        Ok(())
+    }
+
+    fn set_nonce_endianness(&mut self, le: bool) {
+        self.nonce_le = le;
+    }
+
+    fn nonce_endianness(&self) -> bool {
+        self.nonce_le
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+        self.pauses += 1;
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn request_stop(&self) {
+        self.stop_requested.store(true, Ordering::Relaxed);
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.stop_requested.load(Ordering::Relaxed)
+    }
+
+    fn apply_clock_profile(&mut self, core_offset_mhz: i32, mem_offset_mhz: i32) -> Result<(), Error> {
+        match &mut self.clock_offsets {
+            Some(offsets) => {
+                offsets.push((core_offset_mhz, mem_offset_mhz));
+                Ok(())
+            }
+            None => Err("clock profiles are unsupported by this worker".into()),
+        }
+    }
+
+    fn memory_usage_bytes(&self) -> Option<u64> {
+        self.memory_usage
+    }
+
+    fn temperature_celsius(&self) -> Option<u32> {
+        self.temperature
+    }
+
+    fn power_watts(&self) -> Option<u32> {
+        self.power_watts
+            .map(|(watts, at_workload)| (watts as u64 * self.workload as u64 / at_workload.max(1) as u64) as u32)
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        match &self.metrics {
+            Some(metrics) => metrics.clone(),
+            None => metrics::from_stats(&self.stats()),
+        }
+    }
+
+    fn hashes_computed(&self) -> u64 {
+        match self.counts_hashes {
+            true => self.hashes_computed,
+            false => 0,
+        }
+    }
+
+    fn last_progress(&self) -> Instant {
+        self.progress.unwrap_or_else(Instant::now)
+    }
+
+    fn warmup(&mut self) -> Result<(), Error> {
+        if let Some(warmup_time) = self.warmup_time {
+            assert!(self.job_id.is_none(), "warmed up after a job was loaded");
+            std::thread::sleep(warmup_time);
+            self.hashes_computed += self.launch_hashes();
+        }
+        Ok(())
+    }
+
+    fn self_check(&mut self) -> Result<(), Error> {
+        match self.planted_self_check {
+            true => planted_self_check(self),
+            false => Ok(()),
+        }
+    }
+
+    fn self_verifies(&self) -> bool {
+        self.self_verifies
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    fn event_sink(&self) -> Option<EventSink> {
+        self.events.clone()
+    }
+
+    fn max_output_nonces(&self) -> usize {
+        self.output_capacity.unwrap_or(1)
+    }
+
+    fn output_pending(&self) -> usize {
+        self.found.len()
+    }
+
+    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error> {
+        assert_eq!(self.busy_polls.get(), 0, "copy_output_to called before the launch completed");
+        self.calls.borrow_mut().push("copy_output_to");
+        let found = std::mem::take(&mut self.found);
+        let capacity = self.max_output_nonces();
+        if found.len() > capacity {
+            return Err(KarlsenMinerError::OutputOverflow { found: found.len(), capacity }.into());
+        }
+        if nonces.len() < capacity {
+            nonces.resize(capacity, 0);
+        }
+        nonces[..found.len()].copy_from_slice(&found);
+        // Reading nothing clears the first slot, like a device resetting its output
+        if let (true, Some(first)) = (found.is_empty(), nonces.first_mut()) {
+            *first = 0;
+        }
+        Ok(found.len())
+    }
+}
+
+#[derive(Clone)]
+pub struct NullWorkerSpec {
+    id: String,
+    workload: usize,
+    scripted_nonce: Option<u64>,
+}
+
+impl WorkerSpec for NullWorkerSpec {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn build(&self) -> Result<Box<dyn Worker>, Error> {
+        let worker = NullWorker::new(&self.id, self.workload);
+        Ok(Box::new(match self.scripted_nonce {
+            Some(nonce) => worker.with_found_nonce(nonce),
+            None => worker,
+        }))
+    }
+
+    fn clone_spec(&self) -> Box<dyn WorkerSpec> {
+        Box::new(self.clone())
+    }
+
+    fn gpu_type(&self) -> &str {
+        "Null"
+    }
+}
+
+/// Hands out `devices` `NullWorker`s, register it with `PluginManager::register_plugin`
+pub struct NullPlugin {
+    specs: Vec<NullWorkerSpec>,
+}
+
+impl NullPlugin {
+    pub fn new(devices: usize, workload: usize) -> Self {
+        let specs = (0..devices)
+            .map(|i| NullWorkerSpec { id: format!("Null #{}", i), workload, scripted_nonce: None })
+            .collect();
+        Self { specs }
+    }
+
+    /// Every worker built afterwards reports `nonce` after each launch
+    pub fn with_found_nonce(mut self, nonce: u64) -> Self {
+        self.specs.iter_mut().for_each(|spec| spec.scripted_nonce = Some(nonce));
+        self
+    }
+}
+
+impl Plugin for NullPlugin {
+    fn name(&self) -> &'static str {
+        PLUGIN_NAME
+    }
+
+    fn version(&self) -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn enabled(&self) -> bool {
+        !self.specs.is_empty()
+    }
+
+    fn get_worker_specs(&self) -> Vec<Box<dyn WorkerSpec>> {
+        self.specs.iter().map(|spec| Box::new(spec.clone()) as Box<dyn WorkerSpec>).collect()
+    }
+
+    fn process_option(&mut self, _matchs: &ArgMatches) -> Result<usize, Error> {
+        Ok(self.specs.len())
+    }
+}
+
+/// Runs what `declare_plugin!(P, constructor, A)` exports as `_plugin_create`, without building the plugin as a
+/// library: constructs the plugin and has `A` augment a fresh `App`. A plugin's own unit tests can then check
+/// its `Plugin::name`, arguments and worker specs.
+pub fn create_plugin_in_process<P: Plugin, A: clap::Args>(
+    constructor: fn() -> Result<P, Error>,
+) -> Result<(clap::App<'static>, Box<dyn Plugin>), Error> {
+    let app = Box::into_raw(Box::new(clap::App::new("karlsen-miner")));
+    // The plugin is linked in, so unlike a loaded one it can't outlive its code
+    let (app, plugin) = unsafe { take_created(create_plugin(app, constructor, |app, _name| A::augment_args(app))) };
+    plugin.map(|plugin| (app, plugin))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{create_plugin_in_process, NullPlugin, NullWorker};
+    use crate::{Error, PluginManager, Worker};
+    use clap::FromArgMatches;
+
+    #[test]
+    fn test_null_plugin_cycle() {
+        let matches = clap::App::new("test").try_get_matches_from(["test"]).unwrap();
+        let mut manager = PluginManager::new();
+        manager.register_plugin(Box::new(NullPlugin::new(2, 256).with_found_nonce(1234)));
+        assert_eq!(manager.process_options(&matches).unwrap(), 2);
+
+        let mut workers = manager.build_workers().unwrap();
+        assert_eq!(workers.iter().map(|worker| worker.id()).collect::<Vec<_>>(), ["Null #0", "Null #1"]);
+        let mut nonces = vec![0u64; 1];
+        for worker in &mut workers {
+            assert_eq!(worker.source_plugin(), "Null Worker");
+            worker.load_block_constants(&[0; 72], &[[0; 64]; 64], &[0; 4]).unwrap();
+            worker.calculate_hash(None, u64::MAX, 0);
+            worker.sync().unwrap();
+            assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 1);
+            assert_eq!(nonces[0], 1234);
+            assert_eq!(worker.hashes_computed(), 256);
+        }
+    }
+
+    #[test]
+    fn test_null_worker_without_script() {
+        let mut worker = NullWorker::new("null", 16);
+        assert_eq!(worker.source_plugin(), "Null Worker");
+        let mut nonces = vec![0u64; 1];
+        // Nothing runs until constants are loaded
+        worker.calculate_hash(None, u64::MAX, 0);
+        assert_eq!(worker.hashes_computed(), 0);
+
+        worker.load_block_constants(&[0; 72], &[[0; 64]; 64], &[0; 4]).unwrap();
+        worker.calculate_hash(None, u64::MAX, 0);
+        assert_eq!(worker.copy_output_to(&mut nonces).unwrap(), 0);
+        assert_eq!(worker.hashes_computed(), 16);
+    }
+
+    #[derive(clap::Args)]
+    struct NullOpt {
+        #[clap(long = "null-devices", default_value = "1")]
+        null_devices: usize,
+    }
+
+    fn three_devices() -> Result<NullPlugin, Error> {
+        Ok(NullPlugin::new(3, 64))
+    }
+
+    #[test]
+    fn test_create_plugin_in_process() {
+        let (app, plugin) = create_plugin_in_process::<NullPlugin, NullOpt>(three_devices).unwrap();
+        assert_eq!(plugin.name(), "Null Worker");
+        assert_eq!(plugin.get_worker_specs().len(), 3);
+        let matches = app.try_get_matches_from(["karlsen-miner", "--null-devices", "5"]).unwrap();
+        assert_eq!(NullOpt::from_arg_matches(&matches).unwrap().null_devices, 5);
+
+        let failing = || Err::<NullPlugin, Error>("no devices".into());
+        let e = create_plugin_in_process::<NullPlugin, NullOpt>(failing).err().unwrap();
+        assert_eq!(e.to_string(), "no devices");
+    }
+}
@@ -0,0 +1,290 @@
+//! A soft hashrate cap for backends that can't enforce `Worker::set_hashrate_cap` themselves.
+use crate::events::EventSink;
+use crate::metrics::Metric;
+use crate::session::WorkerSession;
+use crate::{Error, FoundNonce, JobConstants, MiningJob, NonceRange, Worker, WorkerError, WorkerStats};
+use std::time::{Duration, Instant};
+
+/// Delays each launch of the wrapped worker until the hashes launched before it fit under the cap. Time spent
+/// idle doesn't build up credit, so over any window the worker hashes at most the cap plus one batch. Everything
+/// but hashing is passed through, `calculate_hash_until` included as its default launches through the wrapper.
+pub struct ThrottledWorker<W: Worker> {
+    worker: W,
+    max_hs: Option<u64>,
+    // Earliest time the next launch may start
+    next_launch: Instant,
+}
+
+impl<W: Worker> ThrottledWorker<W> {
+    pub fn new(worker: W, max_hs: u64) -> Self {
+        let mut throttled = Self { worker, max_hs: None, next_launch: Instant::now() };
+        throttled.set_hashrate_cap(max_hs);
+        throttled
+    }
+
+    pub fn into_inner(self) -> W {
+        self.worker
+    }
+
+    /// Sleeps until `hashes` more may be launched
+    fn throttle(&mut self, hashes: u64) {
+        let max_hs = match self.max_hs {
+            Some(max_hs) => max_hs,
+            None => return,
+        };
+        let now = Instant::now();
+        if self.next_launch > now {
+            std::thread::sleep(self.next_launch - now);
+        }
+        self.next_launch = self.next_launch.max(now) + Duration::from_secs_f64(hashes as f64 / max_hs as f64);
+    }
+
+    fn launched(&self, nonces: Option<&Vec<u64>>) -> u64 {
+        match nonces {
+            Some(nonces) => nonces.len() as u64,
+            None => self.worker.batch_size(),
+        }
+    }
+}
+
+impl<W: Worker> Worker for ThrottledWorker<W> {
+    fn id(&self) -> String {
+        self.worker.id()
+    }
+
+    fn source_plugin(&self) -> &'static str {
+        self.worker.source_plugin()
+    }
+
+    fn uuid(&self) -> u128 {
+        self.worker.uuid()
+    }
+
+    fn set_nonce_endianness(&mut self, le: bool) {
+        self.worker.set_nonce_endianness(le)
+    }
+
+    fn nonce_endianness(&self) -> bool {
+        self.worker.nonce_endianness()
+    }
+
+    fn self_check(&mut self) -> Result<(), Error> {
+        self.worker.self_check()
+    }
+
+    fn warmup(&mut self) -> Result<(), Error> {
+        self.worker.warmup()
+    }
+
+    fn load_block_constants(
+        &mut self,
+        hash_header: &[u8; 72],
+        matrix: &[[u16; 64]; 64],
+        target: &[u64; 4],
+    ) -> Result<(), Error> {
+        self.worker.load_block_constants(hash_header, matrix, target)
+    }
+
+    fn reset_for_new_job(&mut self) -> Result<(), Error> {
+        self.worker.reset_for_new_job()
+    }
+
+    fn load_job(&mut self, job: &MiningJob) -> Result<(), Error> {
+        self.worker.load_job(job)
+    }
+
+    fn current_job_id(&self) -> Option<u64> {
+        self.worker.current_job_id()
+    }
+
+    fn load_jobs(&mut self, jobs: &[JobConstants]) -> Result<(), Error> {
+        self.worker.load_jobs(jobs)
+    }
+
+    fn calculate_hash(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64) {
+        self.throttle(self.launched(nonces));
+        self.worker.calculate_hash(nonces, nonce_mask, nonce_fixed)
+    }
+
+    fn calculate_hash_range(&mut self, start: u64, count: u64) {
+        self.throttle(count);
+        self.worker.calculate_hash_range(start, count)
+    }
+
+    fn covered_ranges(&self) -> Vec<NonceRange> {
+        self.worker.covered_ranges()
+    }
+
+    fn rng_state(&self) -> Option<[u64; 4]> {
+        self.worker.rng_state()
+    }
+
+    fn set_rng_seed(&mut self, seed: u64) {
+        self.worker.set_rng_seed(seed)
+    }
+
+    fn restore_session(&mut self, session: &WorkerSession) -> Result<(), Error> {
+        self.worker.restore_session(session)
+    }
+
+    fn sync(&self) -> Result<(), WorkerError> {
+        self.worker.sync()
+    }
+
+    fn calculate_hash_async(&mut self, nonces: Option<&Vec<u64>>, nonce_mask: u64, nonce_fixed: u64) {
+        self.throttle(self.launched(nonces));
+        self.worker.calculate_hash_async(nonces, nonce_mask, nonce_fixed)
+    }
+
+    fn try_sync(&self) -> Result<bool, WorkerError> {
+        self.worker.try_sync()
+    }
+
+    fn set_max_inflight(&mut self, max_inflight: u32) {
+        self.worker.set_max_inflight(max_inflight)
+    }
+
+    fn inflight(&self) -> u32 {
+        self.worker.inflight()
+    }
+
+    fn get_workload(&self) -> usize {
+        self.worker.get_workload()
+    }
+
+    fn set_workload(&mut self, workload: usize) -> Result<(), Error> {
+        self.worker.set_workload(workload)
+    }
+
+    fn set_batch_size(&mut self, hashes_per_launch: u64) {
+        self.worker.set_batch_size(hashes_per_launch)
+    }
+
+    fn batch_size(&self) -> u64 {
+        self.worker.batch_size()
+    }
+
+    fn set_hashrate_cap(&mut self, max_hs: u64) {
+        self.max_hs = Some(max_hs).filter(|&max_hs| max_hs > 0);
+        self.next_launch = Instant::now();
+    }
+
+    fn hashrate_cap(&self) -> Option<u64> {
+        self.max_hs
+    }
+
+    fn apply_clock_profile(&mut self, core_offset_mhz: i32, mem_offset_mhz: i32) -> Result<(), Error> {
+        self.worker.apply_clock_profile(core_offset_mhz, mem_offset_mhz)
+    }
+
+    fn hashes_computed(&self) -> u64 {
+        self.worker.hashes_computed()
+    }
+
+    fn self_verifies(&self) -> bool {
+        self.worker.self_verifies()
+    }
+
+    fn pause(&mut self) {
+        self.worker.pause()
+    }
+
+    fn resume(&mut self) {
+        self.worker.resume()
+    }
+
+    fn is_paused(&self) -> bool {
+        self.worker.is_paused()
+    }
+
+    fn request_stop(&self) {
+        self.worker.request_stop()
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.worker.stop_requested()
+    }
+
+    fn memory_usage_bytes(&self) -> Option<u64> {
+        self.worker.memory_usage_bytes()
+    }
+
+    fn temperature_celsius(&self) -> Option<u32> {
+        self.worker.temperature_celsius()
+    }
+
+    fn power_watts(&self) -> Option<u32> {
+        self.worker.power_watts()
+    }
+
+    fn last_progress(&self) -> Instant {
+        self.worker.last_progress()
+    }
+
+    fn stats(&self) -> WorkerStats {
+        self.worker.stats()
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        self.worker.metrics()
+    }
+
+    fn set_event_sink(&mut self, sink: EventSink) {
+        self.worker.set_event_sink(sink)
+    }
+
+    fn event_sink(&self) -> Option<EventSink> {
+        self.worker.event_sink()
+    }
+
+    fn copy_output_to(&mut self, nonces: &mut Vec<u64>) -> Result<usize, Error> {
+        self.worker.copy_output_to(nonces)
+    }
+
+    fn max_output_nonces(&self) -> usize {
+        self.worker.max_output_nonces()
+    }
+
+    fn output_pending(&self) -> usize {
+        self.worker.output_pending()
+    }
+
+    fn copy_job_output_to(&mut self, jobs: &[JobConstants], found: &mut Vec<FoundNonce>) -> Result<usize, Error> {
+        self.worker.copy_job_output_to(jobs, found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::NullWorker;
+    use crate::throttle::ThrottledWorker;
+    use crate::Worker;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_hashrate_cap() {
+        let mut worker = NullWorker::new("null", 100);
+        worker.load_block_constants(&[0; 72], &[[0; 64]; 64], &[0; 4]).unwrap();
+        assert_eq!(worker.hashrate_cap(), None);
+
+        let mut worker = ThrottledWorker::new(worker, 10_000);
+        assert_eq!(worker.hashrate_cap(), Some(10_000));
+        let start = Instant::now();
+        worker.calculate_hash_until(start + Duration::from_millis(200)).unwrap();
+        let elapsed = start.elapsed().as_secs_f64();
+        // At most one batch over the cap for the window
+        let hashes = worker.hashes_computed();
+        assert!(hashes as f64 <= 10_000.0 * elapsed + 100.0, "{} hashes in {}s", hashes, elapsed);
+        assert!(hashes >= 1000, "{}", hashes);
+
+        // Lifting the cap takes the sleeps out
+        worker.set_hashrate_cap(0);
+        assert_eq!(worker.hashrate_cap(), None);
+        let start = Instant::now();
+        for _ in 0..1000 {
+            worker.calculate_hash(None, u64::MAX, 0);
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(worker.into_inner().hashes_computed(), hashes + 100_000);
+    }
+}
@@ -0,0 +1,70 @@
+//! Placement of host threads near their device; on multi-socket machines a thread on the device's
+//! NUMA node avoids crossing the socket interconnect for every PCIe transfer.
+use crate::Error;
+
+/// NUMA node of the PCI device at `domain:bus:device`, as reported by the kernel. `None` when the platform
+/// doesn't expose it, e.g. outside Linux or on single-node machines where the kernel reports -1.
+pub fn pci_numa_node(domain: u32, bus: u32, device: u32) -> Option<u32> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let path = format!("/sys/bus/pci/devices/{:04x}:{:02x}:{:02x}.0/numa_node", domain, bus, device);
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Restricts the calling thread to the CPUs of NUMA node `node`
+#[cfg(target_os = "linux")]
+pub fn pin_thread_to_node(node: u32) -> Result<(), Error> {
+    use nix::sched::{sched_setaffinity, CpuSet};
+    use nix::unistd::Pid;
+
+    let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+    let cpus = std::fs::read_to_string(&path).map_err(|e| format!("failed reading {}: {}", path, e))?;
+    let mut set = CpuSet::new();
+    for cpu in parse_cpu_list(&cpus)? {
+        set.set(cpu)?;
+    }
+    // Pid 0 is the calling thread
+    sched_setaffinity(Pid::from_raw(0), &set)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_thread_to_node(node: u32) -> Result<(), Error> {
+    Err(format!("pinning threads to NUMA node {} is only supported on Linux", node).into())
+}
+
+/// Parses the kernel's CPU list format, e.g. `0-3,8,10-11`
+#[cfg(any(target_os = "linux", test))]
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>, Error> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let (first, last): (usize, usize) = (first.parse()?, last.parse()?);
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::topology::{parse_cpu_list, pin_thread_to_node};
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0\n").unwrap(), [0]);
+        assert_eq!(parse_cpu_list("0-3,8,10-11").unwrap(), [0, 1, 2, 3, 8, 10, 11]);
+        assert!(parse_cpu_list("").unwrap().is_empty());
+        assert!(parse_cpu_list("0-x").is_err());
+    }
+
+    #[test]
+    fn test_pin_thread_to_node() {
+        // No machine has this many nodes
+        assert!(pin_thread_to_node(4096).is_err());
+        if cfg!(target_os = "linux") && std::path::Path::new("/sys/devices/system/node/node0").exists() {
+            // Runs on its own thread so the test harness thread keeps its affinity
+            std::thread::spawn(|| pin_thread_to_node(0)).join().unwrap().unwrap();
+        }
+    }
+}
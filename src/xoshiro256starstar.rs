@@ -1,23 +1,167 @@
+use std::sync::{Arc, Mutex};
+
+const JUMP: [u64; 4] = [0x180ec6d33cfd0aba, 0xd5a61266f0c9392c, 0xa9582618e03fc9aa, 0x39abdc4529b1661c];
 const LONG_JUMP: [u64; 4] = [0x76e15d3efefdcbbf, 0xc5004e441c522fb3, 0x77710069854ee241, 0x39109bb02acbe635];
 
+const SELF_TEST_SEED: [u64; 4] = [1, 2, 3, 4];
+/// First outputs of the reference C implementation (xoshiro256starstar.c) seeded with `SELF_TEST_SEED`
+const SELF_TEST_OUTPUTS: [u64; 16] = [
+    0x0000000000002d00,
+    0x0000000000000000,
+    0x000000005a007080,
+    0x10e0000000009d80,
+    0x10e0b61ce1009d80,
+    0x0870021ce143ad00,
+    0xe071c3c2e143f089,
+    0x75a1690ef7a20380,
+    0x9309685b465c23f9,
+    0x284f3cc2e13e3c88,
+    0xc8d749005a413820,
+    0x1194b410fef20904,
+    0xb54a54470263b28c,
+    0x959e65495daf641c,
+    0xe561ccecea17f527,
+    0xd7713c78965a463c,
+];
+
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "[u64; 4]", into = "[u64; 4]"))]
 pub struct Xoshiro256StarStar {
     pub(crate) state: [u64; 4],
+    // Unconsumed 16 bit lanes of the last `u64` drawn by `next_u16`, lowest lane first
+    u16_cache: u64,
+    u16_left: u8,
+}
+
+/// What the nonce generation helpers need from a generator, so other PRNGs can be compared against the default
+/// `Xoshiro256StarStar` without touching worker code
+pub trait RngSource {
+    fn next_u64(&mut self) -> u64;
+    /// Fills `dest` with little-endian `next_u64` outputs, a trailing partial word uses the low bytes of the last one
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let mut chunks = dest.chunks_exact_mut(8);
+        for chunk in &mut chunks {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes());
+        }
+        let tail = chunks.into_remainder();
+        if !tail.is_empty() {
+            let len = tail.len();
+            tail.copy_from_slice(&self.next_u64().to_le_bytes()[..len]);
+        }
+    }
+    /// Skips far enough ahead that the outputs before and after the jump never overlap in practice
+    fn jump(&mut self);
+}
+
+/// Endless stream of `next_u64` outputs, see [`Xoshiro256StarStar::iter_u64`]
+pub struct NonceStream<'a, R: RngSource = Xoshiro256StarStar> {
+    rng: &'a mut R,
+}
+
+impl<'a, R: RngSource> NonceStream<'a, R> {
+    pub fn new(rng: &'a mut R) -> Self {
+        Self { rng }
+    }
 }
 
 pub struct Xoshiro256StarStarStateIter {
     current: Xoshiro256StarStar,
 }
 
+/// A generator handle that can be sent to and used from several threads.
+///
+/// There are two ways to hand it out:
+/// - `clone` takes the current state for the new handle and `jump`s the original past it, so every handle
+///   owns a non-overlapping stream and never waits on another thread. Each clone costs a jump though
+///   (256 `next_u64` calls), and the outputs depend on the order handles were cloned in.
+/// - `share` returns a handle onto the same locked generator: one sequence like a single-threaded generator
+///   would produce, interleaved between the threads, at the price of taking the lock on every call.
+pub struct SharedXoshiro {
+    rng: Arc<Mutex<Xoshiro256StarStar>>,
+}
+
 fn rotl(x: u64, k: i32) -> u64 {
     (x << k) | (x >> (64 - k))
 }
 
+fn splitmix64(seed: &mut u64) -> u64 {
+    *seed = seed.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Reproducible generator for worker thread `stream_index`, seeded from `base_seed` with SplitMix64 and then
+/// `jump`ed `stream_index` times, so every index gets its own non-overlapping stream. Each jump costs 256
+/// `next_u64` calls; for large indices, `long_jump` a shared generator once per thread instead.
+pub fn seeded_stream_for(base_seed: u64, stream_index: u64) -> Xoshiro256StarStar {
+    jumped_stream(Xoshiro256StarStar::from_seed_u64(base_seed), stream_index)
+}
+
+/// `seeded_stream_for` with any generator: `rng` `jump`ed `stream_index` times
+pub fn jumped_stream<R: RngSource>(mut rng: R, stream_index: u64) -> R {
+    for _ in 0..stream_index {
+        rng.jump();
+    }
+    rng
+}
+
+/// Whether the generator reproduces the reference implementation's first 16 outputs, so downstream users
+/// can check that nonce generation is deterministic on their platform
+pub fn self_test() -> bool {
+    let mut rng = Xoshiro256StarStar::new(&SELF_TEST_SEED);
+    SELF_TEST_OUTPUTS.iter().all(|&expected| rng.next_u64() == expected)
+}
+
 impl Xoshiro256StarStar {
     pub fn new(seed: &[u64; 4]) -> Self {
         let mut state = [0u64; 4];
         state.copy_from_slice(seed);
-        Self { state }
+        Self::with_state(state)
+    }
+
+    pub fn state(&self) -> [u64; 4] {
+        self.state
+    }
+
+    /// Restores a checkpointed state. An all-zero state is rejected since the generator never leaves it.
+    pub fn from_state(state: [u64; 4]) -> Option<Self> {
+        match state == [0u64; 4] {
+            true => None,
+            false => Some(Self::with_state(state)),
+        }
+    }
+
+    /// The state as four little-endian words, a snapshot that doesn't need the `serde` feature.
+    /// Values cached by `next_u16` are not part of it.
+    pub fn save(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, word) in bytes.chunks_exact_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Inverse of `save`, `None` for the all-zero state like `from_state`
+    pub fn restore(bytes: &[u8; 32]) -> Option<Self> {
+        let mut state = [0u64; 4];
+        for (word, chunk) in state.iter_mut().zip(bytes.chunks_exact(8)) {
+            *word = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Self::from_state(state)
+    }
+
+    /// Expands a single seed into the full state with SplitMix64, as recommended by the xoshiro authors
+    pub fn from_seed_u64(seed: u64) -> Self {
+        let mut seed = seed;
+        let state = [splitmix64(&mut seed), splitmix64(&mut seed), splitmix64(&mut seed), splitmix64(&mut seed)];
+        Self::with_state(state)
+    }
+
+    fn with_state(state: [u64; 4]) -> Self {
+        Self { state, u16_cache: 0, u16_left: 0 }
     }
 
     pub fn next_u64(&mut self) -> u64 {
@@ -36,27 +180,81 @@ impl Xoshiro256StarStar {
         result
     }
 
-    pub fn long_jump(&mut self) {
-        let mut s0 = 0u64;
-        let mut s1 = 0u64;
-        let mut s2 = 0u64;
-        let mut s3 = 0u64;
-        for jmp in LONG_JUMP {
+    /// Uniform value in `0..bound` via Lemire's multiply-and-reject, without modulo bias. `bound == 0` returns 0.
+    pub fn next_u64_below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        let mut m = self.next_u64() as u128 * bound as u128;
+        if (m as u64) < bound {
+            let threshold = bound.wrapping_neg() % bound;
+            while (m as u64) < threshold {
+                m = self.next_u64() as u128 * bound as u128;
+            }
+        }
+        (m >> 64) as u64
+    }
+
+    /// Splits one `next_u64` into four values, handed out from the least significant 16 bits up, and only
+    /// draws the next `u64` once all four were consumed. `next_u64` and the other methods bypass this cache.
+    pub fn next_u16(&mut self) -> u16 {
+        if self.u16_left == 0 {
+            self.u16_cache = self.next_u64();
+            self.u16_left = 4;
+        }
+        let value = self.u16_cache as u16;
+        self.u16_cache >>= 16;
+        self.u16_left -= 1;
+        value
+    }
+
+    /// `next_u64_below` for `u16`s, drawing from `next_u16`. `bound == 0` returns 0.
+    pub fn next_u16_below(&mut self, bound: u16) -> u16 {
+        if bound == 0 {
+            return 0;
+        }
+        let mut m = self.next_u16() as u32 * bound as u32;
+        if (m as u16) < bound {
+            let threshold = bound.wrapping_neg() % bound;
+            while (m as u16) < threshold {
+                m = self.next_u16() as u32 * bound as u32;
+            }
+        }
+        (m >> 16) as u16
+    }
+
+    /// Fills `dest` with little-endian `u64`s, a trailing partial word uses the low bytes of the last value
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RngSource::fill_bytes(self, dest)
+    }
+
+    /// Advances the state by 2^128 calls, handing out up to 2^128 non-overlapping subsequences
+    pub fn jump(&mut self) {
+        self.apply_jump(&JUMP);
+    }
+
+    fn apply_jump(&mut self, polynomial: &[u64; 4]) {
+        let mut state = [0u64; 4];
+        for &jmp in polynomial {
             for b in 0..64 {
                 if jmp & 1u64 << b != 0 {
-                    s0 ^= self.state[0];
-                    s1 ^= self.state[1];
-                    s2 ^= self.state[2];
-                    s3 ^= self.state[3];
+                    for (s, current) in state.iter_mut().zip(self.state) {
+                        *s ^= current;
+                    }
                 }
                 self.next_u64();
             }
-
-            self.state[0] = s0;
-            self.state[1] = s1;
-            self.state[2] = s2;
-            self.state[3] = s3;
         }
+        self.state = state;
+    }
+
+    /// Advances the state by 2^192 calls, handing out up to 2^64 starting points that can each be `jump`ed further
+    pub fn long_jump(&mut self) {
+        self.apply_jump(&LONG_JUMP);
+    }
+
+    pub fn iter_u64(&mut self) -> NonceStream<'_> {
+        NonceStream::new(self)
     }
 
     pub fn iter_jump_state(&self) -> impl Iterator<Item = [u64; 4]> {
@@ -65,6 +263,87 @@ impl Xoshiro256StarStar {
     }
 }
 
+impl SharedXoshiro {
+    pub fn new(rng: Xoshiro256StarStar) -> Self {
+        Self { rng: Arc::new(Mutex::new(rng)) }
+    }
+
+    /// Another handle onto this generator, contending on its lock
+    pub fn share(&self) -> Self {
+        Self { rng: Arc::clone(&self.rng) }
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        self.lock().next_u64()
+    }
+
+    pub fn fill_bytes(&self, dest: &mut [u8]) {
+        self.lock().fill_bytes(dest)
+    }
+
+    /// A snapshot of the generator, e.g. to checkpoint it
+    pub fn get(&self) -> Xoshiro256StarStar {
+        *self.lock()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Xoshiro256StarStar> {
+        // A panicking thread can't leave the state half updated, any state is a valid generator
+        self.rng.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Splits off a disjoint stream, see the type's documentation
+impl Clone for SharedXoshiro {
+    fn clone(&self) -> Self {
+        let mut rng = self.lock();
+        let split = *rng;
+        rng.jump();
+        Self::new(split)
+    }
+}
+
+impl From<Xoshiro256StarStar> for SharedXoshiro {
+    fn from(rng: Xoshiro256StarStar) -> Self {
+        Self::new(rng)
+    }
+}
+
+impl RngSource for Xoshiro256StarStar {
+    fn next_u64(&mut self) -> u64 {
+        Xoshiro256StarStar::next_u64(self)
+    }
+
+    fn jump(&mut self) {
+        Xoshiro256StarStar::jump(self)
+    }
+}
+
+impl TryFrom<[u64; 4]> for Xoshiro256StarStar {
+    type Error = &'static str;
+
+    fn try_from(state: [u64; 4]) -> Result<Self, Self::Error> {
+        Self::from_state(state).ok_or("xoshiro256** state must not be all zeros")
+    }
+}
+
+impl From<Xoshiro256StarStar> for [u64; 4] {
+    fn from(rng: Xoshiro256StarStar) -> Self {
+        rng.state
+    }
+}
+
+impl<R: RngSource> Iterator for NonceStream<'_, R> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        Some(self.rng.next_u64())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
 impl Iterator for Xoshiro256StarStarStateIter {
     type Item = [u64; 4];
 
@@ -73,3 +352,241 @@ impl Iterator for Xoshiro256StarStarStateIter {
         Some(self.current.state)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::xoshiro256starstar::{
+        jumped_stream, seeded_stream_for, self_test, splitmix64, NonceStream, RngSource, SharedXoshiro,
+        Xoshiro256StarStar, SELF_TEST_OUTPUTS,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_splitmix64_vectors() {
+        let mut seed = 1234567;
+        let expected = [6457827717110365317, 3203168211198807973, 9817491932198370423, 4593380528125082431];
+        for value in expected {
+            assert_eq!(splitmix64(&mut seed), value);
+        }
+    }
+
+    #[test]
+    fn test_from_seed_u64() {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(1234567);
+        assert_eq!(rng.state, [6457827717110365317, 3203168211198807973, 9817491932198370423, 4593380528125082431]);
+        let expected = [3504822795582309479, 1819558768956484042, 1250851346055027673, 16940231675099994102];
+        for value in expected {
+            assert_eq!(rng.next_u64(), value);
+        }
+    }
+
+    #[test]
+    fn test_self_test() {
+        assert!(self_test());
+        // The reference vectors start 11520, 0, 1509978240, as published with the C implementation
+        assert_eq!(SELF_TEST_OUTPUTS[..3], [11520, 0, 1509978240]);
+        let mut other = Xoshiro256StarStar::new(&[1, 2, 3, 5]);
+        assert!(SELF_TEST_OUTPUTS.iter().any(|&expected| other.next_u64() != expected));
+    }
+
+    #[test]
+    fn test_jump() {
+        let mut rng = Xoshiro256StarStar::new(&[1, 2, 3, 4]);
+        let mut jumped = rng;
+        jumped.jump();
+        assert_eq!(jumped.state, [0x8c7a153956b5f3d1, 0x701f1a713401d85e, 0x6527f66a65469085, 0x8386b786c4408050]);
+
+        let window: Vec<u64> = (0..1000).map(|_| rng.next_u64()).collect();
+        assert!((0..1000).map(|_| jumped.next_u64()).all(|value| !window.contains(&value)));
+    }
+
+    #[test]
+    fn test_seeded_stream_for() {
+        assert_eq!(seeded_stream_for(42, 0).state, Xoshiro256StarStar::from_seed_u64(42).state);
+        let mut jumped = Xoshiro256StarStar::from_seed_u64(42);
+        jumped.jump();
+        jumped.jump();
+        assert_eq!(seeded_stream_for(42, 2).state, jumped.state);
+
+        let mut first = seeded_stream_for(42, 1);
+        let mut second = seeded_stream_for(42, 2);
+        let window: Vec<u64> = (0..1000).map(|_| first.next_u64()).collect();
+        assert!((0..1000).map(|_| second.next_u64()).all(|value| !window.contains(&value)));
+    }
+
+    #[test]
+    fn test_long_jump() {
+        let mut rng = Xoshiro256StarStar::new(&[1, 2, 3, 4]);
+        let mut jumped = rng;
+        rng.long_jump();
+        jumped.jump();
+        assert_ne!(rng.state, jumped.state);
+        assert_eq!(rng.state, [0x096a8eb71295a400, 0xdbf84991e50f4516, 0x534ee745810d2a0e, 0x31655ca1a2215bf1]);
+        assert_eq!(rng.next_u64(), 5942309088398569549);
+        assert_eq!(rng.next_u64(), 15625447729937358436);
+    }
+
+    #[test]
+    fn test_fill_bytes() {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(42);
+        let mut expected = rng;
+        let mut buf = [0u8; 13];
+        rng.fill_bytes(&mut buf);
+        assert_eq!(buf[..8], expected.next_u64().to_le_bytes());
+        assert_eq!(buf[8..], expected.next_u64().to_le_bytes()[..5]);
+        assert_eq!(rng.state, expected.state);
+    }
+
+    #[test]
+    fn test_next_u64_below() {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(7);
+        assert_eq!(rng.next_u64_below(0), 0);
+        let mut counts = [0u32; 6];
+        for _ in 0..60_000 {
+            counts[rng.next_u64_below(6) as usize] += 1;
+        }
+        assert!(counts.iter().all(|&count| (9_000..11_000).contains(&count)), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_next_u16() {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(3);
+        let mut expected = rng;
+        let word = expected.next_u64();
+        let values: Vec<u16> = (0..4).map(|_| rng.next_u16()).collect();
+        assert_eq!(values, [word as u16, (word >> 16) as u16, (word >> 32) as u16, (word >> 48) as u16]);
+        assert_eq!(rng.state, expected.state);
+
+        // The fifth value starts the next word
+        assert_eq!(rng.next_u16(), expected.next_u64() as u16);
+    }
+
+    #[test]
+    fn test_next_u16_below() {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(7);
+        assert_eq!(rng.next_u16_below(0), 0);
+        let mut counts = [0u32; 6];
+        for _ in 0..60_000 {
+            counts[rng.next_u16_below(6) as usize] += 1;
+        }
+        assert!(counts.iter().all(|&count| (9_000..11_000).contains(&count)), "{:?}", counts);
+        assert!((0..1000).all(|_| rng.next_u16_below(64) < 64));
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(9);
+        rng.next_u64();
+        let mut restored = Xoshiro256StarStar::from_state(rng.state()).unwrap();
+        assert_eq!(restored.next_u64(), rng.next_u64());
+        assert!(Xoshiro256StarStar::from_state([0; 4]).is_none());
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let mut rng = Xoshiro256StarStar::new(&[1, 2, 3, 0x0807060504030201]);
+        let bytes = rng.save();
+        assert_eq!(bytes[..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bytes[24..], [1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut restored = Xoshiro256StarStar::restore(&bytes).unwrap();
+        assert_eq!(restored.next_u64(), rng.next_u64());
+    }
+
+    #[test]
+    fn test_restore_rejects_zero_state() {
+        assert!(Xoshiro256StarStar::restore(&[0; 32]).is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(9);
+        rng.next_u64();
+        let json = serde_json::to_string(&rng).unwrap();
+        let mut restored: Xoshiro256StarStar = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.next_u64(), rng.next_u64());
+        assert!(serde_json::from_str::<Xoshiro256StarStar>("[0,0,0,0]").is_err());
+    }
+
+    #[test]
+    fn test_iter_u64() {
+        let mut rng = Xoshiro256StarStar::from_seed_u64(1);
+        let mut expected = rng;
+        let values: Vec<u64> = rng.iter_u64().take(4).collect();
+        assert_eq!(values, (0..4).map(|_| expected.next_u64()).collect::<Vec<u64>>());
+        assert_eq!(rng.state, expected.state);
+    }
+
+    /// Counts up by one, a jump skips a million values
+    struct CounterRng(u64);
+
+    impl RngSource for CounterRng {
+        fn next_u64(&mut self) -> u64 {
+            self.0 += 1;
+            self.0
+        }
+
+        fn jump(&mut self) {
+            self.0 += 1_000_000;
+        }
+    }
+
+    #[test]
+    fn test_custom_rng_source() {
+        let mut rng = jumped_stream(CounterRng(0), 2);
+        let nonces: Vec<u64> = NonceStream::new(&mut rng).take(3).collect();
+        assert_eq!(nonces, [2_000_001, 2_000_002, 2_000_003]);
+        let mut bytes = [0u8; 10];
+        rng.fill_bytes(&mut bytes);
+        assert_eq!(bytes[..8], 2_000_004u64.to_le_bytes());
+        assert_eq!(bytes[8..], 2_000_005u64.to_le_bytes()[..2]);
+
+        // The default generator behaves the same through the trait
+        let mut xoshiro = Xoshiro256StarStar::from_seed_u64(42);
+        let mut expected = seeded_stream_for(42, 1);
+        let stream: Vec<u64> = NonceStream::new(&mut jumped_stream(xoshiro, 1)).take(2).collect();
+        assert_eq!(stream, [expected.next_u64(), expected.next_u64()]);
+        assert_eq!(RngSource::next_u64(&mut xoshiro), Xoshiro256StarStar::from_seed_u64(42).next_u64());
+    }
+
+    fn draw_on_threads(handles: Vec<SharedXoshiro>) -> Vec<u64> {
+        let threads: Vec<_> = handles
+            .into_iter()
+            .map(|rng| {
+                std::thread::spawn(move || {
+                    let mut bytes = [0u8; 16];
+                    let mut values: Vec<u64> = (0..5000).map(|_| rng.next_u64()).collect();
+                    rng.fill_bytes(&mut bytes);
+                    values.extend(bytes.chunks_exact(8).map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap())));
+                    values
+                })
+            })
+            .collect();
+        threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_shared_xoshiro() {
+        let shared = SharedXoshiro::new(Xoshiro256StarStar::from_seed_u64(5));
+        let mut expected = shared.get();
+        let clones: Vec<_> = (0..4).map(|_| shared.clone()).collect();
+        // The first clone continues where the original was, the original moved on by a jump per clone
+        assert_eq!(clones[0].get().state, expected.state);
+        for _ in 0..4 {
+            expected.jump();
+        }
+        assert_eq!(shared.get().state, expected.state);
+
+        let values = draw_on_threads(clones);
+        assert_eq!(values.len(), 4 * 5002);
+        assert_eq!(values.iter().collect::<HashSet<_>>().len(), values.len());
+
+        // Shared handles interleave a single stream
+        let mut single = shared.get();
+        let values = draw_on_threads((0..4).map(|_| shared.share()).collect());
+        let mut sequence: HashSet<_> = (0..4 * 5002).map(|_| single.next_u64()).collect();
+        assert!(values.iter().all(|value| sequence.remove(value)));
+        assert!(sequence.is_empty());
+        assert_eq!(shared.get().state, single.state);
+    }
+}